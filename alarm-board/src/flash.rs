@@ -0,0 +1,153 @@
+use crate::pac;
+use alarm_core::FlashRegion;
+use cortex_m::interrupt;
+
+/// Start of the `CALIBRATION` region reserved in `memory.x`.
+const CALIBRATION_START: usize = 0x0803_C000;
+const CALIBRATION_PAGE_COUNT: usize = 2;
+/// Start of the `JOURNAL` region reserved in `memory.x`.
+const JOURNAL_START: usize = 0x0803_D000;
+const JOURNAL_PAGE_COUNT: usize = 4;
+/// Start of the `CONFIG` region reserved in `memory.x`.
+const CONFIG_START: usize = 0x0803_F000;
+const CONFIG_PAGE_COUNT: usize = 2;
+const PAGE_SIZE: usize = 2048;
+const KEY1: u32 = 0x4567_0123;
+const KEY2: u32 = 0xCDEF_89AB;
+
+#[derive(Debug)]
+pub enum FlashError {
+    OutOfBounds,
+    Unaligned,
+    Program,
+    WriteProtected,
+}
+
+/// One of the regions reserved at the end of the STM32F303's internal flash,
+/// programmed directly through the FLASH registers since the HAL only exposes
+/// `ACR`.
+///
+/// The CPU stalls while a page erases (about 40 ms), so this is only used
+/// from tasks that can afford it. Each region must only be created once; the
+/// program/erase registers are shared between them, so every operation runs
+/// in a critical section.
+pub struct InternalFlash {
+    start: usize,
+    page_count: usize,
+}
+
+impl InternalFlash {
+    pub fn config() -> Self {
+        InternalFlash {
+            start: CONFIG_START,
+            page_count: CONFIG_PAGE_COUNT,
+        }
+    }
+    pub fn journal() -> Self {
+        InternalFlash {
+            start: JOURNAL_START,
+            page_count: JOURNAL_PAGE_COUNT,
+        }
+    }
+    pub fn calibration() -> Self {
+        InternalFlash {
+            start: CALIBRATION_START,
+            page_count: CALIBRATION_PAGE_COUNT,
+        }
+    }
+    fn regs(&self) -> &'static pac::flash::RegisterBlock {
+        unsafe { &*pac::FLASH::ptr() }
+    }
+    fn unlock(&self) {
+        let regs = self.regs();
+        if regs.cr.read().lock().bit_is_set() {
+            regs.keyr.write(|w| unsafe { w.bits(KEY1) });
+            regs.keyr.write(|w| unsafe { w.bits(KEY2) });
+        }
+    }
+    fn lock(&self) {
+        self.regs().cr.modify(|_, w| w.lock().set_bit());
+    }
+    fn wait_ready(&self) -> Result<(), FlashError> {
+        let regs = self.regs();
+        while regs.sr.read().bsy().bit_is_set() {}
+        let sr = regs.sr.read();
+        let result = if sr.pgerr().bit_is_set() {
+            Err(FlashError::Program)
+        } else if sr.wrprterr().bit_is_set() {
+            Err(FlashError::WriteProtected)
+        } else {
+            Ok(())
+        };
+        regs.sr
+            .write(|w| w.eop().set_bit().pgerr().set_bit().wrprterr().set_bit());
+        result
+    }
+    fn check_bounds(&self, offset: usize, len: usize) -> Result<(), FlashError> {
+        match offset.checked_add(len) {
+            Some(end) if end <= PAGE_SIZE * self.page_count => Ok(()),
+            _ => Err(FlashError::OutOfBounds),
+        }
+    }
+    fn program(&self, offset: usize, data: &[u8]) -> Result<(), FlashError> {
+        self.unlock();
+        self.regs().cr.modify(|_, w| w.pg().set_bit());
+        let mut result = Ok(());
+        for (i, half_word) in data.chunks_exact(2).enumerate() {
+            let address = (self.start + offset + 2 * i) as *mut u16;
+            unsafe {
+                core::ptr::write_volatile(address, u16::from_le_bytes([half_word[0], half_word[1]]))
+            };
+            result = self.wait_ready();
+            if result.is_err() {
+                break;
+            }
+        }
+        self.regs().cr.modify(|_, w| w.pg().clear_bit());
+        self.lock();
+        result
+    }
+    fn erase(&self, page: usize) -> Result<(), FlashError> {
+        self.unlock();
+        let regs = self.regs();
+        regs.cr.modify(|_, w| w.per().set_bit());
+        regs.ar
+            .write(|w| unsafe { w.bits((self.start + page * PAGE_SIZE) as u32) });
+        regs.cr.modify(|_, w| w.strt().set_bit());
+        let result = self.wait_ready();
+        regs.cr.modify(|_, w| w.per().clear_bit());
+        self.lock();
+        result
+    }
+}
+
+impl FlashRegion for InternalFlash {
+    type Error = FlashError;
+
+    fn page_size(&self) -> usize {
+        PAGE_SIZE
+    }
+    fn page_count(&self) -> usize {
+        self.page_count
+    }
+    fn read(&mut self, offset: usize, buf: &mut [u8]) -> Result<(), Self::Error> {
+        self.check_bounds(offset, buf.len())?;
+        for (i, byte) in buf.iter_mut().enumerate() {
+            *byte = unsafe { core::ptr::read_volatile((self.start + offset + i) as *const u8) };
+        }
+        Ok(())
+    }
+    fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), Self::Error> {
+        self.check_bounds(offset, data.len())?;
+        if !offset.is_multiple_of(2) || !data.len().is_multiple_of(2) {
+            return Err(FlashError::Unaligned);
+        }
+        interrupt::free(|_| self.program(offset, data))
+    }
+    fn erase_page(&mut self, page: usize) -> Result<(), Self::Error> {
+        if page >= self.page_count {
+            return Err(FlashError::OutOfBounds);
+        }
+        interrupt::free(|_| self.erase(page))
+    }
+}
@@ -0,0 +1,69 @@
+use alarm_core::rotation::RawRate;
+use embedded_hal::{blocking::spi::Transfer, digital::v2::OutputPin};
+
+const CTRL_REG1: u8 = 0x20;
+const CTRL_REG4: u8 = 0x23;
+const OUT_X_L: u8 = 0x28;
+/// Set in the register address to read rather than write.
+const READ: u8 = 0x80;
+/// Set in the register address to access several registers in one go.
+const AUTO_INCREMENT: u8 = 0x40;
+
+/// 95 Hz output rate, 12.5 Hz bandwidth, powered up with X, Y and Z on.
+const ODR_95HZ_ON: u8 = 0b0000_1111;
+const POWER_DOWN: u8 = 0;
+/// Block data update at ±250 dps, the range `alarm_core::rotation` assumes.
+const BDU_250DPS: u8 = 1 << 7;
+
+/// The L3GD20 gyroscope on SPI1, selected by pulling `cs` low.
+///
+/// It draws several milliamps while running, so it stays powered down until
+/// rotation detection asks for it.
+pub struct L3gd20<SPI, CS> {
+    spi: SPI,
+    cs: CS,
+}
+
+impl<SPI, CS, E> L3gd20<SPI, CS>
+where
+    SPI: Transfer<u8, Error = E>,
+    CS: OutputPin,
+{
+    /// Powers the gyroscope down. One that does not answer yet is kept, and
+    /// [`enable`](Self::enable) sets it up when rotation detection needs it.
+    pub fn new(spi: SPI, cs: CS) -> Self {
+        let mut gyroscope = L3gd20 { spi, cs };
+        let _ = gyroscope.cs.set_high();
+        let _ = gyroscope.disable();
+        gyroscope
+    }
+    /// Sets the range and powers the gyroscope up. The first readings after
+    /// this settle within a quarter of a second.
+    pub fn enable(&mut self) -> Result<(), E> {
+        self.write(CTRL_REG4, BDU_250DPS)?;
+        self.write(CTRL_REG1, ODR_95HZ_ON)
+    }
+    pub fn disable(&mut self) -> Result<(), E> {
+        self.write(CTRL_REG1, POWER_DOWN)
+    }
+    /// The latest rotation rate in raw counts.
+    pub fn rate(&mut self) -> Result<RawRate, E> {
+        let mut buf = [OUT_X_L | READ | AUTO_INCREMENT, 0, 0, 0, 0, 0, 0];
+        self.transfer(&mut buf)?;
+        Ok([
+            i16::from_le_bytes([buf[1], buf[2]]),
+            i16::from_le_bytes([buf[3], buf[4]]),
+            i16::from_le_bytes([buf[5], buf[6]]),
+        ])
+    }
+    fn write(&mut self, register: u8, value: u8) -> Result<(), E> {
+        self.transfer(&mut [register, value])
+    }
+    fn transfer(&mut self, buf: &mut [u8]) -> Result<(), E> {
+        // The chip select is a plain GPIO, which cannot fail.
+        let _ = self.cs.set_low();
+        let result = self.spi.transfer(buf).map(|_| ());
+        let _ = self.cs.set_high();
+        result
+    }
+}
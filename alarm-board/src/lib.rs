@@ -0,0 +1,7 @@
+#![no_std]
+
+use stm32f3::stm32f303 as pac;
+
+pub mod flash;
+pub mod l3gd20;
+pub mod rtc;
@@ -0,0 +1,199 @@
+use crate::pac;
+use alarm_core::{Calendar, DateTime, WeekTime};
+use cortex_m::interrupt;
+
+/// How long to wait for the LSE crystal before falling back to the LSI. The
+/// crystal takes up to two seconds to start; the Discovery board ships without
+/// one.
+const LSE_STARTUP_SPINS: u32 = 4_000_000;
+const RTCSEL_LSE: u32 = 0b01;
+const RTCSEL_LSI: u32 = 0b10;
+const EXTI_RTC_ALARM: u32 = 1 << 17;
+
+/// The oscillator the RTC runs from. Both keep running in Stop mode.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum RtcClock {
+    /// 32.768 kHz crystal, accurate to a few seconds a day.
+    Lse,
+    /// Internal 40 kHz RC oscillator, off by up to a few minutes a day.
+    Lsi,
+}
+
+impl RtcClock {
+    /// Asynchronous and synchronous prescalers that bring the clock down to
+    /// 1 Hz.
+    fn prescalers(self) -> (u32, u32) {
+        match self {
+            RtcClock::Lse => (127, 255),
+            RtcClock::Lsi => (124, 319),
+        }
+    }
+}
+
+/// The STM32F303's calendar RTC, driven through its registers since the HAL
+/// only supports it on the LSE.
+///
+/// The calendar lives in the backup domain, so it keeps its time across
+/// resets. Alarm A fires the `RTCALARM` interrupt through EXTI line 17, which
+/// also wakes the core from Stop mode.
+pub struct Rtc {
+    clock: RtcClock,
+}
+
+impl Rtc {
+    /// Starts the RTC, keeping the calendar if it is already running.
+    pub fn init() -> Rtc {
+        let (rcc, pwr, rtc) = regs();
+        rcc.apb1enr.modify(|_, w| w.pwren().set_bit());
+        pwr.cr.modify(|_, w| w.dbp().set_bit());
+        let running = rtc.isr.read().inits().bit_is_set() && rcc.bdcr.read().rtcen().bit_is_set();
+        let clock = if running {
+            match u32::from(rcc.bdcr.read().rtcsel().bits()) {
+                RTCSEL_LSE => RtcClock::Lse,
+                _ => {
+                    // The LSI is outside the backup domain and stops on reset.
+                    start_lsi();
+                    RtcClock::Lsi
+                }
+            }
+        } else {
+            // The clock source can only be chosen after a backup domain reset.
+            rcc.bdcr.modify(|_, w| w.bdrst().set_bit());
+            rcc.bdcr.modify(|_, w| w.bdrst().clear_bit());
+            let (clock, rtcsel) = if start_lse() {
+                (RtcClock::Lse, RTCSEL_LSE)
+            } else {
+                start_lsi();
+                (RtcClock::Lsi, RTCSEL_LSI)
+            };
+            rcc.bdcr
+                .modify(|r, w| unsafe { w.bits(r.bits() & !(0b11 << 8) | rtcsel << 8) });
+            rcc.bdcr.modify(|_, w| w.rtcen().set_bit());
+            clock
+        };
+        let exti = unsafe { &*pac::EXTI::ptr() };
+        exti.imr1
+            .modify(|r, w| unsafe { w.bits(r.bits() | EXTI_RTC_ALARM) });
+        exti.rtsr1
+            .modify(|r, w| unsafe { w.bits(r.bits() | EXTI_RTC_ALARM) });
+        let mut rtc = Rtc { clock };
+        if !running {
+            rtc.set(DateTime::EPOCH);
+        }
+        rtc
+    }
+    /// Fires the alarm interrupt at `at` or, with `None`, turns it off.
+    pub fn set_alarm(&mut self, at: Option<WeekTime>) {
+        let (_, _, rtc) = regs();
+        unlocked(|| {
+            rtc.cr
+                .modify(|_, w| w.alrae().clear_bit().alraie().clear_bit());
+            if let Some(at) = at {
+                while rtc.isr.read().alrawf().bit_is_clear() {}
+                // Match the weekday, hour and minute at second zero.
+                let alarm = 1 << 30
+                    | u32::from(at.weekday.index() + 1) << 24
+                    | bcd(at.hour) << 16
+                    | bcd(at.minute) << 8;
+                rtc.alrmar.write(|w| unsafe { w.bits(alarm) });
+                rtc.cr.modify(|_, w| w.alrae().set_bit().alraie().set_bit());
+            }
+        });
+        Rtc::clear_alarm();
+    }
+    /// Acknowledges a fired alarm. Called from the `RTCALARM` handler.
+    pub fn clear_alarm() {
+        let (_, _, rtc) = regs();
+        rtc.isr.modify(|_, w| w.alraf().clear_bit());
+        let exti = unsafe { &*pac::EXTI::ptr() };
+        exti.pr1.write(|w| unsafe { w.bits(EXTI_RTC_ALARM) });
+    }
+}
+
+impl Calendar for Rtc {
+    fn now(&mut self) -> DateTime {
+        let (_, _, rtc) = regs();
+        // Reading TR freezes DR until it is read too, so the pair is
+        // consistent.
+        let tr = rtc.tr.read().bits();
+        let dr = rtc.dr.read().bits();
+        DateTime::new(
+            2000 + u16::from(from_bcd(dr >> 16)),
+            from_bcd(dr >> 8 & 0x1F),
+            from_bcd(dr & 0x3F),
+            from_bcd(tr >> 16 & 0x3F),
+            from_bcd(tr >> 8),
+            from_bcd(tr),
+        )
+        .unwrap_or(DateTime::EPOCH)
+    }
+    fn set(&mut self, at: DateTime) {
+        let (_, _, rtc) = regs();
+        let (prediv_a, prediv_s) = self.clock.prescalers();
+        let tr = bcd(at.hour()) << 16 | bcd(at.minute()) << 8 | bcd(at.second());
+        let dr = bcd((at.year() - 2000) as u8) << 16
+            | u32::from(at.weekday().index() + 1) << 13
+            | bcd(at.month()) << 8
+            | bcd(at.day());
+        unlocked(|| {
+            rtc.isr.modify(|_, w| w.init().set_bit());
+            while rtc.isr.read().initf().bit_is_clear() {}
+            // The prescalers take two separate writes.
+            rtc.prer.write(|w| unsafe { w.bits(prediv_s) });
+            rtc.prer
+                .write(|w| unsafe { w.bits(prediv_a << 16 | prediv_s) });
+            rtc.tr.write(|w| unsafe { w.bits(tr) });
+            rtc.dr.write(|w| unsafe { w.bits(dr) });
+            rtc.isr
+                .modify(|_, w| w.init().clear_bit().rsf().clear_bit());
+        });
+        while rtc.isr.read().rsf().bit_is_clear() {}
+    }
+}
+
+fn regs() -> (
+    &'static pac::rcc::RegisterBlock,
+    &'static pac::pwr::RegisterBlock,
+    &'static pac::rtc::RegisterBlock,
+) {
+    unsafe { (&*pac::RCC::ptr(), &*pac::PWR::ptr(), &*pac::RTC::ptr()) }
+}
+
+/// Runs `f` with the RTC registers write-enabled.
+fn unlocked(f: impl FnOnce()) {
+    let (_, _, rtc) = regs();
+    interrupt::free(|_| {
+        rtc.wpr.write(|w| unsafe { w.bits(0xCA) });
+        rtc.wpr.write(|w| unsafe { w.bits(0x53) });
+        f();
+        rtc.wpr.write(|w| unsafe { w.bits(0xFF) });
+    });
+}
+
+fn start_lse() -> bool {
+    let (rcc, _, _) = regs();
+    rcc.bdcr.modify(|_, w| w.lseon().set_bit());
+    for _ in 0..LSE_STARTUP_SPINS {
+        if rcc.bdcr.read().lserdy().bit_is_set() {
+            return true;
+        }
+    }
+    rcc.bdcr.modify(|_, w| w.lseon().clear_bit());
+    false
+}
+
+fn start_lsi() {
+    let (rcc, _, _) = regs();
+    rcc.csr.modify(|_, w| w.lsion().set_bit());
+    while rcc.csr.read().lsirdy().bit_is_clear() {}
+}
+
+fn bcd(value: u8) -> u32 {
+    u32::from(value / 10) << 4 | u32::from(value % 10)
+}
+
+/// Decodes the two BCD digits in the low byte of `bits`.
+fn from_bcd(bits: u32) -> u8 {
+    let bits = bits as u8;
+    (bits >> 4) * 10 + (bits & 0x0F)
+}
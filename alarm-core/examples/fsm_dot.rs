@@ -0,0 +1,21 @@
+//! Prints the alarm state machine as a Graphviz DOT diagram, after checking
+//! that its transition table is complete.
+//!
+//! ```text
+//! cargo run --example fsm_dot | dot -Tsvg > fsm.svg
+//! ```
+
+use std::process::ExitCode;
+
+use alarm_core::fsm;
+
+fn main() -> ExitCode {
+    if let Err(e) = fsm::check(fsm::TRANSITIONS) {
+        eprintln!("error: {}", e);
+        return ExitCode::FAILURE;
+    }
+    let mut dot = String::new();
+    fsm::write_dot(fsm::TRANSITIONS, &mut dot).unwrap();
+    print!("{}", dot);
+    ExitCode::SUCCESS
+}
@@ -0,0 +1,246 @@
+//! Replays recorded accelerometer traces through motion, fall and tip-over
+//! detection, activity classification, vibration masking and the state
+//! machine, printing when the firmware would have reset the inactivity
+//! timeout, started the pre-alarm and fired the alarm, what it took the
+//! wearer to be doing, and which machine vibration it masked.
+//!
+//! ```text
+//! cargo run --example replay -- [--set <key> <value>]... <trace>...
+//! cargo run --example replay -- --sweep 20:300:10 \
+//!     --still testdata/fan.csv --moving testdata/pick_up.csv > sweep.csv
+//! ```
+//!
+//! Traces are CSV, one `x,y,z` sample of raw counts per line as in
+//! `testdata`, or, when named `*.bin`, a capture of little-endian `i16`
+//! triples. Either is taken to be sampled every `sample_period_ms` of the
+//! profile. `--set` takes the keys of the console's `set` command.
+//!
+//! `--sweep <from>:<to>:<step>` runs the traces at each `motion_threshold_mg`
+//! of the range instead, and prints the false-alarm rate, the share of the
+//! traces after `--still` in which motion was detected anyway, and the
+//! missed-motion rate, the share of those after `--moving` in which it was
+//! not. Both come twice: for the motion that reset the timeout, after the
+//! activity and vibration gates, and for what the detector saw before them.
+
+use std::{env, fs, ops::RangeInclusive, process::ExitCode};
+
+use alarm_core::{
+    motion::RawSample, profile::Setting, sensor::TraceError, AlarmProfile, AppResetMessage,
+    Command, MotionSensor, Replay, StateKind, TraceSensor,
+};
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Label {
+    Unlabelled,
+    Still,
+    Moving,
+}
+
+struct Trace {
+    path: String,
+    label: Label,
+    samples: Vec<RawSample>,
+}
+
+fn load(path: &str) -> Result<Vec<RawSample>, String> {
+    let error = |e: &dyn std::fmt::Display| format!("{}: {}", path, e);
+    if path.ends_with(".bin") {
+        let bytes = fs::read(path).map_err(|e| error(&e))?;
+        if bytes.len() % 6 != 0 {
+            return Err(error(&"capture ends within a sample"));
+        }
+        return Ok(bytes
+            .chunks_exact(6)
+            .map(|sample| {
+                [0, 2, 4].map(|axis| i16::from_le_bytes([sample[axis], sample[axis + 1]]))
+            })
+            .collect());
+    }
+    let csv = fs::read_to_string(path).map_err(|e| error(&e))?;
+    let mut trace = TraceSensor::new(&csv);
+    let mut samples = Vec::new();
+    loop {
+        match trace.accel() {
+            Ok(sample) => samples.push(sample),
+            Err(TraceError::Ended) => return Ok(samples),
+            Err(e) => return Err(error(&e)),
+        }
+    }
+}
+
+/// Parses `<from>:<to>:<step>`.
+fn parse_sweep(value: &str) -> Option<(RangeInclusive<u16>, usize)> {
+    let mut numbers = value.split(':').map(|number| number.parse().ok());
+    let (from, to, step) = (numbers.next()??, numbers.next()??, numbers.next()??);
+    (numbers.next().is_none() && from > 0 && step > 0).then_some((from..=to, usize::from(step)))
+}
+
+fn print_timeline(trace: &Trace, profile: &AlarmProfile) {
+    println!("{}", trace.path);
+    let mut replay = Replay::new(profile);
+    let (mut resets, mut pre_alarms, mut alarms) = (0, 0, 0);
+    let mut activity = replay.activity();
+    let mut vibration = replay.vibration();
+    for &raw in &trace.samples {
+        let before = replay.state().kind();
+        let message = replay.step(raw);
+        let after = replay.state().kind();
+        let at = replay.now().as_millis() as f64 / 1000.0;
+        if replay.activity() != activity {
+            activity = replay.activity();
+            println!("{:>10.2} s  {:<16} {}", at, "activity", activity);
+        }
+        if replay.vibration().is_some() != vibration.is_some() {
+            vibration = replay.vibration();
+            match vibration {
+                Some(v) => println!(
+                    "{:>10.2} s  {:<16} {:.1} Hz, {} mg",
+                    at,
+                    "vibration masked",
+                    v.milli_hz as f64 / 1000.0,
+                    v.amplitude_mg
+                ),
+                None => println!("{:>10.2} s  {:<16}", at, "vibration gone"),
+            }
+        }
+        let what = match message {
+            Some(AppResetMessage::FromAccelerometer { magnitude }) => {
+                format!("motion {} mg", magnitude)
+            }
+            Some(AppResetMessage::FromFall { magnitude }) => format!("fall {} mg", magnitude),
+            Some(AppResetMessage::FromTipOver { angle }) => format!("tip-over {} deg", angle),
+            Some(message) => format!("{:?}", message),
+            None if after != before => "timeout".into(),
+            None => continue,
+        };
+        if after == before {
+            println!("{:>10.2} s  {:<16} ignored while {}", at, what, after);
+            continue;
+        }
+        resets += usize::from(message.is_some() && after == StateKind::Active);
+        pre_alarms += usize::from(after == StateKind::PreAlarm);
+        alarms += usize::from(after == StateKind::Alarm);
+        println!("{:>10.2} s  {:<16} {} -> {}", at, what, before, after);
+    }
+    println!(
+        "{} samples, {} resets, {} pre-alarms, {} alarms\n",
+        trace.samples.len(),
+        resets,
+        pre_alarms,
+        alarms
+    );
+}
+
+/// Whether the trace reset the timeout with motion, and whether the detector
+/// fired at all.
+fn detects_motion(trace: &Trace, profile: &AlarmProfile) -> (bool, bool) {
+    let mut replay = Replay::new(profile);
+    let (mut gated, mut raw) = (false, false);
+    for &sample in &trace.samples {
+        gated |= matches!(
+            replay.step(sample),
+            Some(AppResetMessage::FromAccelerometer { .. })
+        );
+        raw |= replay.raw_motion();
+    }
+    (gated, raw)
+}
+
+fn print_sweep(
+    traces: &[Trace],
+    profile: &AlarmProfile,
+    (thresholds, step): (RangeInclusive<u16>, usize),
+) -> Result<(), String> {
+    let count = |label| traces.iter().filter(|trace| trace.label == label).count();
+    let (still, moving) = (count(Label::Still), count(Label::Moving));
+    if still == 0 || moving == 0 {
+        return Err("--sweep needs --still and --moving traces".into());
+    }
+    println!(
+        "motion_threshold_mg,false_alarm_rate,missed_motion_rate,\
+         raw_false_alarm_rate,raw_missed_motion_rate"
+    );
+    for threshold in thresholds.step_by(step) {
+        let mut profile = *profile;
+        profile
+            .apply(Setting::MotionThreshold(threshold))
+            .map_err(|e| e.to_string())?;
+        // Gated, then raw.
+        let (mut false_alarms, mut missed) = ([0; 2], [0; 2]);
+        for trace in traces {
+            let (gated, raw) = detects_motion(trace, &profile);
+            for (i, detected) in [gated, raw].into_iter().enumerate() {
+                match (trace.label, detected) {
+                    (Label::Still, true) => false_alarms[i] += 1,
+                    (Label::Moving, false) => missed[i] += 1,
+                    _ => {}
+                }
+            }
+        }
+        println!(
+            "{},{:.3},{:.3},{:.3},{:.3}",
+            threshold,
+            false_alarms[0] as f64 / still as f64,
+            missed[0] as f64 / moving as f64,
+            false_alarms[1] as f64 / still as f64,
+            missed[1] as f64 / moving as f64
+        );
+    }
+    Ok(())
+}
+
+fn run() -> Result<(), String> {
+    let mut profile = AlarmProfile::default();
+    let mut sweep = None;
+    let mut label = Label::Unlabelled;
+    let mut traces = Vec::new();
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--set" => {
+                let (key, value) = args.next().zip(args.next()).ok_or("--set <key> <value>")?;
+                let setting = match Command::parse(&format!("set {} {}", key, value)) {
+                    Ok(Command::Set(setting)) => setting,
+                    Ok(_) => unreachable!(),
+                    Err(e) => return Err(format!("{} {}: {}", key, value, e)),
+                };
+                profile
+                    .apply(setting)
+                    .map_err(|e| format!("{} {}: {}", key, value, e))?;
+            }
+            "--sweep" => {
+                let value = args.next().unwrap_or_default();
+                sweep = Some(parse_sweep(&value).ok_or("--sweep <from>:<to>:<step>")?);
+            }
+            "--still" => label = Label::Still,
+            "--moving" => label = Label::Moving,
+            path => traces.push(Trace {
+                path: path.into(),
+                label,
+                samples: load(path)?,
+            }),
+        }
+    }
+    if traces.is_empty() {
+        return Err("no traces given".into());
+    }
+    match sweep {
+        Some(sweep) => print_sweep(&traces, &profile, sweep)?,
+        None => {
+            for trace in &traces {
+                print_timeline(trace, &profile);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,493 @@
+//! Step counting and a coarse guess at what the wearer is doing.
+//!
+//! Both work on the magnitude of the acceleration less gravity, which a slow
+//! average of the magnitude estimates. A step is a swing above
+//! [`STEP_THRESHOLD_MG`] after the signal has been below gravity, and steps
+//! only count once [`MIN_STEP_RUN`] of them have come at a walking pace, so a
+//! bumped table is not a walk. Every [`ACTIVITY_WINDOW`] the samples of the
+//! window are summed up as an [`Activity`] by how much the signal moves, how
+//! often it crosses gravity and how many steps were taken.
+
+use core::{fmt, time::Duration};
+
+use crate::{
+    app_state::AppResetMessage,
+    motion::{self, RawSample},
+    profile::{AccelSensitivity, AlarmProfile},
+};
+
+/// How much of the signal each classification covers.
+pub const ACTIVITY_WINDOW: Duration = Duration::from_secs(2);
+/// How far above gravity the magnitude has to swing for a step, in milli-g.
+pub const STEP_THRESHOLD_MG: i32 = 120;
+/// Steps closer together than this are one step.
+pub const MIN_STEP_INTERVAL: Duration = Duration::from_millis(250);
+/// A longer pause ends a run of steps.
+pub const MAX_STEP_INTERVAL: Duration = Duration::from_millis(1_500);
+/// Steps in a row before any of them counts.
+pub const MIN_STEP_RUN: u32 = 4;
+/// Mean deviation from gravity below which the wearer is still, in milli-g.
+pub const STILL_MG: u32 = 8;
+/// Mean deviation from gravity below which a window without steps or
+/// vibration is still too, apart from a knock or two, in milli-g.
+pub const MOVING_MG: u32 = 40;
+/// Mean deviation from gravity from which stepping is running, in milli-g.
+pub const RUNNING_MG: u32 = 400;
+/// Steps per second from which stepping is running, in thousandths.
+pub const RUNNING_CADENCE_MILLI_HZ: u32 = 2_500;
+/// Vibration at this frequency or above without steps comes from a vehicle
+/// or machine rather than from the wearer, in thousandths of a hertz.
+pub const VEHICLE_MILLI_HZ: u32 = 5_000;
+/// Mean deviation from gravity up to which fast vibration counts as a
+/// vehicle, in milli-g.
+pub const VEHICLE_MAX_MG: u32 = 150;
+/// The gravity estimate moves 1/2^`LEVEL_SHIFT` of the way towards each
+/// magnitude.
+const LEVEL_SHIFT: u32 = 6;
+const LEVEL_FRACTION_BITS: u32 = 8;
+/// Swings smaller than this either side of gravity do not count as crossing
+/// it, so sensor noise is not vibration.
+const CROSSING_HYSTERESIS_MG: i32 = 10;
+/// Steps a window has to hold to be walking or running.
+const MIN_WINDOW_STEPS: u32 = 3;
+
+/// What the wearer seems to be doing.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Activity {
+    Still,
+    Walking,
+    Running,
+    /// Moving without taking steps, such as getting up or reaching for
+    /// something.
+    Moving,
+    /// Fast, weak vibration without steps, from a car, a train or a machine
+    /// the device lies on.
+    Vehicle,
+}
+
+impl Activity {
+    pub const ALL: [Activity; 5] = [
+        Activity::Still,
+        Activity::Walking,
+        Activity::Running,
+        Activity::Moving,
+        Activity::Vehicle,
+    ];
+
+    /// Whether this is the wearer moving, which motion has to be to count.
+    pub fn is_human(self) -> bool {
+        matches!(
+            self,
+            Activity::Walking | Activity::Running | Activity::Moving
+        )
+    }
+}
+
+impl fmt::Display for Activity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Activity::Still => "still",
+            Activity::Walking => "walking",
+            Activity::Running => "running",
+            Activity::Moving => "moving",
+            Activity::Vehicle => "vehicle",
+        })
+    }
+}
+
+/// The latest activity and the steps since boot, as the console reports them.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct ActivityReport {
+    pub activity: Activity,
+    pub steps: u32,
+}
+
+impl ActivityReport {
+    pub const fn new() -> Self {
+        ActivityReport {
+            activity: Activity::Still,
+            steps: 0,
+        }
+    }
+}
+
+impl Default for ActivityReport {
+    fn default() -> Self {
+        ActivityReport::new()
+    }
+}
+
+impl fmt::Display for ActivityReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "activity {}\nsteps {}", self.activity, self.steps)
+    }
+}
+
+/// Counts steps and classifies the activity.
+///
+/// Feed it every sample, taken every `sample_period` of the profile. Until
+/// the first window is complete the wearer counts as still.
+#[derive(Debug, Clone)]
+pub struct ActivityMonitor {
+    sensitivity: AccelSensitivity,
+    period_ms: u32,
+    window_len: usize,
+    min_step: usize,
+    max_step: usize,
+    /// Gravity with `LEVEL_FRACTION_BITS` fractional bits, or `None` before
+    /// the first sample.
+    level: Option<i32>,
+    /// Whether the signal has been below gravity since the last step.
+    primed: bool,
+    since_step: usize,
+    run: u32,
+    steps: u32,
+    /// The window so far: samples, summed deviation, crossings of gravity,
+    /// which side of gravity the signal is on, and steps.
+    len: usize,
+    deviation: u32,
+    crossings: u32,
+    above: bool,
+    window_steps: u32,
+    activity: Activity,
+}
+
+impl ActivityMonitor {
+    pub fn new(profile: &AlarmProfile) -> Self {
+        let mut monitor = ActivityMonitor {
+            sensitivity: profile.accel_sensitivity,
+            period_ms: 1,
+            window_len: 1,
+            min_step: 1,
+            max_step: 1,
+            level: None,
+            primed: false,
+            since_step: 0,
+            run: 0,
+            steps: 0,
+            len: 0,
+            deviation: 0,
+            crossings: 0,
+            above: false,
+            window_steps: 0,
+            activity: Activity::Still,
+        };
+        monitor.configure(profile);
+        monitor
+    }
+    /// Picks up a changed profile. The window starts over; the steps and the
+    /// gravity estimate, already in milli-g, are kept.
+    pub fn configure(&mut self, profile: &AlarmProfile) {
+        let period = profile.sample_period.as_millis().max(1);
+        let samples = |duration: Duration| (duration.as_millis().div_ceil(period) as usize).max(1);
+        self.sensitivity = profile.accel_sensitivity;
+        self.period_ms = u32::try_from(period).unwrap_or(u32::MAX);
+        self.window_len = samples(ACTIVITY_WINDOW);
+        self.min_step = samples(MIN_STEP_INTERVAL);
+        self.max_step = samples(MAX_STEP_INTERVAL);
+        self.run = 0;
+        self.restart_window();
+    }
+    pub fn activity(&self) -> Activity {
+        self.activity
+    }
+    /// Whether motion comes from the wearer: the last window was human
+    /// activity, or this one already holds enough movement to be, so that
+    /// getting up does not wait for the window to end.
+    pub fn is_human(&self) -> bool {
+        self.activity.is_human()
+            || self.deviation >= MOVING_MG.saturating_mul(self.window_len as u32)
+    }
+    /// Picks up after a pause in the samples, such as while the wake-up
+    /// interrupt watched for motion instead. What was seen before the pause
+    /// no longer says what the wearer is doing, so the window and the
+    /// activity start over; the steps and the gravity estimate are kept.
+    pub fn resume(&mut self) {
+        self.activity = Activity::Still;
+        self.primed = false;
+        self.run = 0;
+        self.restart_window();
+    }
+    /// Steps counted since boot.
+    pub fn steps(&self) -> u32 {
+        self.steps
+    }
+    pub fn report(&self) -> ActivityReport {
+        ActivityReport {
+            activity: self.activity,
+            steps: self.steps,
+        }
+    }
+    /// Takes a sample. Returns the activity when it completes a window.
+    pub fn update(&mut self, raw: RawSample) -> Option<Activity> {
+        let magnitude = i32::from(motion::magnitude(motion::to_milli_g(raw, self.sensitivity)));
+        let level = self.level.get_or_insert(magnitude << LEVEL_FRACTION_BITS);
+        *level += ((magnitude << LEVEL_FRACTION_BITS) - *level) >> LEVEL_SHIFT;
+        let swing = magnitude - (*level >> LEVEL_FRACTION_BITS);
+        self.count_step(swing);
+
+        self.len += 1;
+        self.deviation = self.deviation.saturating_add(swing.unsigned_abs());
+        if self.above && swing < -CROSSING_HYSTERESIS_MG
+            || !self.above && swing > CROSSING_HYSTERESIS_MG
+        {
+            self.above = !self.above;
+            self.crossings += 1;
+        }
+        if self.len < self.window_len {
+            return None;
+        }
+        self.activity = self.classify();
+        self.restart_window();
+        Some(self.activity)
+    }
+    fn count_step(&mut self, swing: i32) {
+        self.since_step = self.since_step.saturating_add(1);
+        if self.since_step > self.max_step {
+            self.run = 0;
+        }
+        if swing < 0 {
+            self.primed = true;
+            return;
+        }
+        if !self.primed || swing < STEP_THRESHOLD_MG || self.since_step < self.min_step {
+            return;
+        }
+        self.primed = false;
+        self.since_step = 0;
+        self.run += 1;
+        self.window_steps += 1;
+        // The steps of a run count from its first once it is long enough.
+        match self.run {
+            run if run == MIN_STEP_RUN => self.steps = self.steps.saturating_add(run),
+            run if run > MIN_STEP_RUN => self.steps = self.steps.saturating_add(1),
+            _ => {}
+        }
+    }
+    fn classify(&self) -> Activity {
+        let window_ms = (self.len as u32).saturating_mul(self.period_ms).max(1);
+        let mean = self.deviation / self.len as u32;
+        let per_second = |count: u32| count.saturating_mul(1_000_000) / window_ms;
+        // Two crossings make one period.
+        let frequency = per_second(self.crossings) / 2;
+        let cadence = per_second(self.window_steps);
+        if mean < STILL_MG {
+            Activity::Still
+        } else if self.window_steps >= MIN_WINDOW_STEPS {
+            if cadence >= RUNNING_CADENCE_MILLI_HZ || mean >= RUNNING_MG {
+                Activity::Running
+            } else {
+                Activity::Walking
+            }
+        } else if frequency >= VEHICLE_MILLI_HZ && mean <= VEHICLE_MAX_MG {
+            Activity::Vehicle
+        } else if mean < MOVING_MG {
+            Activity::Still
+        } else {
+            Activity::Moving
+        }
+    }
+    fn restart_window(&mut self) {
+        self.len = 0;
+        self.deviation = 0;
+        self.crossings = 0;
+        self.window_steps = 0;
+    }
+}
+
+/// Holds the accelerometer's wake-up interrupt back until the activity shows
+/// the wearer moving, since the sensor cannot tell them from a bumped table
+/// or a running engine.
+///
+/// [`open`](Self::open) it when the interrupt fires and feed the samples that
+/// follow to the monitor, then to [`update`](Self::update). Motion is
+/// reported as soon as [`ActivityMonitor::is_human`] holds, and the interrupt
+/// is dropped once a whole [`ACTIVITY_WINDOW`] has passed without it.
+#[derive(Debug, Clone)]
+pub struct WakeUpGate {
+    window_len: usize,
+    /// Samples left to confirm the interrupt in, or zero while closed.
+    left: usize,
+}
+
+impl WakeUpGate {
+    pub fn new(profile: &AlarmProfile) -> Self {
+        let mut gate = WakeUpGate {
+            window_len: 1,
+            left: 0,
+        };
+        gate.configure(profile);
+        gate
+    }
+    /// Picks up a changed profile, dropping an interrupt being confirmed.
+    pub fn configure(&mut self, profile: &AlarmProfile) {
+        let period = profile.sample_period.as_millis().max(1);
+        self.window_len = (ACTIVITY_WINDOW.as_millis().div_ceil(period) as usize).max(1);
+        self.left = 0;
+    }
+    /// Whether an interrupt is waiting to be confirmed, so the samples have
+    /// to be read.
+    pub fn is_open(&self) -> bool {
+        self.left > 0
+    }
+    /// The wake-up interrupt fired. If the samples were not being read, call
+    /// [`ActivityMonitor::resume`] too.
+    pub fn open(&mut self) {
+        self.left = self.window_len;
+    }
+    /// Takes the activity after a sample. Returns the motion to report, with
+    /// no magnitude as the interrupt measures none, once the activity is
+    /// human.
+    pub fn update(&mut self, activity: &ActivityMonitor) -> Option<AppResetMessage> {
+        if self.left == 0 {
+            return None;
+        }
+        self.left -= 1;
+        if !activity.is_human() {
+            return None;
+        }
+        self.left = 0;
+        Some(AppResetMessage::FromAccelerometer { magnitude: 0 })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use std::vec::Vec;
+
+    use super::*;
+    use crate::sensor::{MotionSensor, TraceSensor};
+
+    /// 50 Hz at ±16 g, as the traces in `testdata`.
+    fn profile() -> AlarmProfile {
+        AlarmProfile {
+            sample_period: Duration::from_millis(20),
+            accel_sensitivity: AccelSensitivity::G12,
+            ..AlarmProfile::default()
+        }
+    }
+
+    /// Raw counts at ±16 g of gravity along Z plus `swing` milli-g.
+    fn raw(swing: i32) -> RawSample {
+        [0, 0, ((1000 + swing) * 16 / 12) as i16]
+    }
+
+    /// A swing of `amplitude` milli-g at `milli_hz`, for `seconds`, as
+    /// triangles so that no trigonometry is needed.
+    fn wave(milli_hz: u32, amplitude: i32, seconds: u32) -> impl Iterator<Item = RawSample> {
+        let period = 50_000 / milli_hz as i32;
+        (0..50 * seconds as i32).map(move |i| {
+            let phase = i % period;
+            let up = (4 * phase * amplitude / period) - amplitude;
+            raw(if phase < period / 2 {
+                up
+            } else {
+                2 * amplitude - up
+            })
+        })
+    }
+
+    fn classify(samples: impl Iterator<Item = RawSample>) -> (Vec<Activity>, u32) {
+        let mut monitor = ActivityMonitor::new(&profile());
+        let activities = samples.filter_map(|raw| monitor.update(raw)).collect();
+        (activities, monitor.steps())
+    }
+
+    fn trace(csv: &str) -> impl Iterator<Item = RawSample> + '_ {
+        let mut sensor = TraceSensor::new(csv);
+        core::iter::from_fn(move || sensor.accel().ok())
+    }
+
+    #[test]
+    fn counts_steps_of_a_walk() {
+        let (activities, steps) = classify(wave(1_800, 300, 10));
+        // 18 steps, but the very first swing has no gravity estimate yet.
+        assert!((16..=18).contains(&steps), "{}", steps);
+        assert!(activities[1..].iter().all(|&a| a == Activity::Walking));
+    }
+
+    #[test]
+    fn tells_running_from_walking() {
+        let (activities, steps) = classify(wave(3_000, 900, 6));
+        assert!(steps >= 16, "{}", steps);
+        assert_eq!(activities.last(), Some(&Activity::Running));
+    }
+
+    #[test]
+    fn a_few_bumps_are_not_steps() {
+        let bumps = (0..750).map(|i| raw(if i % 150 == 75 { 800 } else { 0 }));
+        let (activities, steps) = classify(bumps);
+        assert_eq!(steps, 0);
+        assert!(activities.iter().all(|a| !a.is_human()));
+    }
+
+    #[test]
+    fn recorded_traces() {
+        // Next to a fan, nobody is moving.
+        let (activities, steps) = classify(trace(include_str!("../testdata/fan.csv")));
+        assert_eq!(steps, 0);
+        // The first window goes to settling the gravity estimate.
+        assert!(
+            activities[1..].iter().all(|&a| a == Activity::Vehicle),
+            "{:?}",
+            activities
+        );
+        assert!(!activities[0].is_human());
+        // Lying still, then picked up and shaken.
+        let (activities, _) = classify(trace(include_str!("../testdata/pick_up.csv")));
+        assert_eq!(activities.first(), Some(&Activity::Still));
+        assert!(activities.iter().any(|a| a.is_human()), "{:?}", activities);
+    }
+
+    /// Samples `trace` only from `wake` on, as in interrupt mode, and returns
+    /// how many samples after it the gate let the interrupt through.
+    fn confirm(trace: &[RawSample], wake: usize) -> Option<usize> {
+        let mut monitor = ActivityMonitor::new(&profile());
+        let mut gate = WakeUpGate::new(&profile());
+        // The samples before the pause settle the gravity estimate.
+        for &raw in &trace[..wake.min(50)] {
+            monitor.update(raw);
+        }
+        monitor.resume();
+        gate.open();
+        for (i, &raw) in trace[wake..].iter().enumerate() {
+            monitor.update(raw);
+            if gate.update(&monitor).is_some() {
+                return Some(i);
+            }
+            if !gate.is_open() {
+                return None;
+            }
+        }
+        None
+    }
+
+    #[test]
+    fn wake_up_needs_the_wearer_moving() {
+        // A knock on the table the device lies on.
+        let knock: Vec<_> = (0..250)
+            .map(|i| raw(if i == 100 { 800 } else { 0 }))
+            .collect();
+        assert_eq!(confirm(&knock, 100), None);
+        let fan: Vec<_> = trace(include_str!("../testdata/fan.csv")).collect();
+        assert_eq!(confirm(&fan, 100), None);
+        // Getting up passes within the window, without waiting for it to end.
+        let pick_up: Vec<_> = trace(include_str!("../testdata/pick_up.csv")).collect();
+        let wake = pick_up
+            .iter()
+            .position(|&raw| raw[2].abs_diff(pick_up[0][2]) > 200)
+            .unwrap();
+        let after = confirm(&pick_up, wake).unwrap();
+        assert!(after < 100, "{}", after);
+    }
+
+    #[test]
+    fn reports_for_the_console() {
+        let report = ActivityReport {
+            activity: Activity::Walking,
+            steps: 42,
+        };
+        assert_eq!(std::format!("{}", report), "activity walking\nsteps 42");
+    }
+}
@@ -0,0 +1,604 @@
+use core::{fmt, time::Duration};
+
+use crate::{
+    button::Gesture,
+    fsm::{self, Action, Event},
+    profile::AlarmProfile,
+    time::Instant,
+};
+
+/// Capacity of the reset message queue. Fixed at compile time since RTIC sizes
+/// its channel with it.
+pub const MAX_QUEUE_SIZE: usize = 5;
+
+/// The escalation tiers, each with the deadline at which it gives way to the
+/// next one: no motion for too long starts a pre-alarm, an unanswered
+/// pre-alarm fires the alarm, and an alarm nobody acknowledges becomes an
+/// emergency. `Disarmed` pauses monitoring until the device is re-armed, and
+/// `SensorFault` waits for an accelerometer that stopped answering to come
+/// back, since without it stillness cannot be told from a dead sensor.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum AppState {
+    Active { pre_alarm_at: Instant },
+    PreAlarm { alarm_at: Instant },
+    Alarm { emergency_at: Instant },
+    Emergency,
+    Disarmed,
+    SensorFault,
+}
+
+impl AppState {
+    pub fn new(now: Instant, profile: &AlarmProfile) -> AppState {
+        AppState::enter(fsm::INITIAL, now, profile)
+    }
+    pub fn kind(&self) -> StateKind {
+        match self {
+            AppState::Active { .. } => StateKind::Active,
+            AppState::PreAlarm { .. } => StateKind::PreAlarm,
+            AppState::Alarm { .. } => StateKind::Alarm,
+            AppState::Emergency => StateKind::Emergency,
+            AppState::Disarmed => StateKind::Disarmed,
+            AppState::SensorFault => StateKind::SensorFault,
+        }
+    }
+    pub fn reset(&mut self, now: Instant, profile: &AlarmProfile) {
+        *self = AppState::new(now, profile);
+    }
+    /// The instant at which the current state escalates, if it ever does.
+    pub fn deadline(&self) -> Option<Instant> {
+        match *self {
+            AppState::Active { pre_alarm_at } => Some(pre_alarm_at),
+            AppState::PreAlarm { alarm_at } => Some(alarm_at),
+            AppState::Alarm { emergency_at } => Some(emergency_at),
+            AppState::Emergency | AppState::Disarmed | AppState::SensorFault => None,
+        }
+    }
+    /// Time left until the current state escalates.
+    pub fn time_until_escalation(&self, now: Instant) -> Result<Duration, TransitionError> {
+        self.deadline()
+            .map(|deadline| deadline.saturating_duration_since(now))
+            .ok_or(TransitionError::NoDeadline)
+    }
+    /// Time left until the pre-alarm starts, zero if it already has, or
+    /// `Duration::MAX` while disarmed or faulted.
+    pub fn time_until_pre_alarm(&self, now: Instant) -> Duration {
+        match *self {
+            AppState::Active { pre_alarm_at } => pre_alarm_at.saturating_duration_since(now),
+            AppState::PreAlarm { .. } | AppState::Alarm { .. } | AppState::Emergency => {
+                Duration::ZERO
+            }
+            AppState::Disarmed | AppState::SensorFault => Duration::MAX,
+        }
+    }
+    /// Time left until the alarm fires, zero if it already has, or
+    /// `Duration::MAX` while disarmed or faulted.
+    pub fn time_until_alarm(&self, now: Instant, profile: &AlarmProfile) -> Duration {
+        match *self {
+            AppState::Active { pre_alarm_at } => {
+                pre_alarm_at.saturating_duration_since(now) + profile.pre_alarm_timeout
+            }
+            AppState::PreAlarm { alarm_at } => alarm_at.saturating_duration_since(now),
+            AppState::Alarm { .. } | AppState::Emergency => Duration::ZERO,
+            AppState::Disarmed | AppState::SensorFault => Duration::MAX,
+        }
+    }
+    /// The state `kind` with its deadline counted from `base`.
+    fn enter(kind: StateKind, base: Instant, profile: &AlarmProfile) -> AppState {
+        match kind {
+            StateKind::Active => AppState::Active {
+                pre_alarm_at: base + profile.inactivity_timeout,
+            },
+            StateKind::PreAlarm => AppState::PreAlarm {
+                alarm_at: base + profile.pre_alarm_timeout,
+            },
+            StateKind::Alarm => AppState::Alarm {
+                emergency_at: base + profile.emergency_timeout,
+            },
+            StateKind::Emergency => AppState::Emergency,
+            StateKind::Disarmed => AppState::Disarmed,
+            StateKind::SensorFault => AppState::SensorFault,
+        }
+    }
+    /// Moves to the next state once the deadline of the current one has
+    /// passed, as [`fsm::TRANSITIONS`] says.
+    pub fn escalate(
+        &mut self,
+        now: Instant,
+        profile: &AlarmProfile,
+    ) -> Result<(), TransitionError> {
+        let rule = fsm::lookup(self.kind(), Event::Timeout);
+        let deadline = match (rule.action, self.deadline()) {
+            (Action::Ignore, _) | (_, None) => return Err(TransitionError::NothingToEscalate),
+            (_, Some(deadline)) if deadline > now => {
+                return Err(TransitionError::DeadlineNotReached(
+                    deadline.saturating_duration_since(now),
+                ))
+            }
+            (_, Some(deadline)) => deadline,
+        };
+        let base = match rule.action {
+            Action::Restart => now,
+            _ => deadline,
+        };
+        *self = AppState::enter(rule.next, base, profile);
+        Ok(())
+    }
+    /// Escalates as many times as the deadlines that have passed by `now`
+    /// allow. Returns whether the state changed.
+    pub fn update(&mut self, now: Instant, profile: &AlarmProfile) -> bool {
+        let mut changed = false;
+        while self.escalate(now, profile).is_ok() {
+            changed = true;
+        }
+        changed
+    }
+    /// Applies a reset message as [`fsm::TRANSITIONS`] says: a short press
+    /// acknowledges an alarm or emergency, motion cancels a pre-alarm, a fall
+    /// raises the alarm at once, tipping over starts the pre-alarm early, a
+    /// long press, console command or the schedule disarms and re-arms, and
+    /// the accelerometer's health moves in and out of `SensorFault`. Any
+    /// other combination is rejected.
+    pub fn handle_reset(
+        &mut self,
+        message: AppResetMessage,
+        now: Instant,
+        profile: &AlarmProfile,
+    ) -> Result<(), TransitionError> {
+        let rule = fsm::lookup(self.kind(), Event::from(message));
+        match rule.action {
+            Action::Ignore => Err(TransitionError::ResetIgnored(message)),
+            Action::Escalate | Action::Restart => {
+                *self = AppState::enter(rule.next, now, profile);
+                Ok(())
+            }
+        }
+    }
+}
+
+/// An [`AppState`] without its deadline, for logging and display.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum StateKind {
+    Active,
+    PreAlarm,
+    Alarm,
+    Emergency,
+    Disarmed,
+    SensorFault,
+}
+
+impl StateKind {
+    pub const ALL: [StateKind; 6] = [
+        StateKind::Active,
+        StateKind::PreAlarm,
+        StateKind::Alarm,
+        StateKind::Emergency,
+        StateKind::Disarmed,
+        StateKind::SensorFault,
+    ];
+}
+
+impl fmt::Display for StateKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            StateKind::Active => "active",
+            StateKind::PreAlarm => "pre-alarm",
+            StateKind::Alarm => "alarm",
+            StateKind::Emergency => "emergency",
+            StateKind::Disarmed => "disarmed",
+            StateKind::SensorFault => "sensor-fault",
+        })
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum TransitionError {
+    /// `Emergency`, `Disarmed` and `SensorFault` have no deadline to wait for.
+    NoDeadline,
+    /// Escalation was requested with this much time left.
+    DeadlineNotReached(Duration),
+    /// The current state does not escalate.
+    NothingToEscalate,
+    /// The message does not apply to the current state.
+    ResetIgnored(AppResetMessage),
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum AppResetMessage {
+    FromButton {
+        gesture: Gesture,
+    },
+    /// `magnitude` is the peak of the motion that was detected, in milli-g,
+    /// or zero when the accelerometer's wake-up interrupt reported it.
+    FromAccelerometer {
+        magnitude: u16,
+    },
+    /// `rate` is the peak rotation rate that was detected, in degrees per
+    /// second.
+    FromGyroscope {
+        rate: u16,
+    },
+    /// A fall followed by stillness; `magnitude` is the peak of the impact,
+    /// in milli-g.
+    FromFall {
+        magnitude: u16,
+    },
+    /// The device stayed tilted away from where it lay when armed;
+    /// `angle` is how far, in degrees.
+    FromTipOver {
+        angle: u16,
+    },
+    /// `arm` or `disarm` typed on the console.
+    FromConsole {
+        arm: bool,
+    },
+    /// A monitoring window of the schedule opened (`arm`) or closed.
+    FromSchedule {
+        arm: bool,
+    },
+    /// The accelerometer kept failing even after recovering the bus
+    /// (`healthy: false`), or answered again.
+    FromSensor {
+        healthy: bool,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const P: AlarmProfile = AlarmProfile {
+        inactivity_timeout: Duration::from_secs(5),
+        pre_alarm_timeout: Duration::from_secs(16),
+        motion_threshold: 100,
+        sample_period: Duration::from_millis(20),
+        motion_source: crate::profile::MotionSource::Interrupt,
+        accel_odr: crate::profile::AccelOdr::Hz100,
+        accel_sensitivity: crate::profile::AccelSensitivity::G12,
+        emergency_timeout: Duration::from_secs(300),
+        free_fall_threshold: 350,
+        impact_threshold: 2500,
+        fall_stillness: Duration::from_secs(2),
+        rotation_threshold: 30,
+        tip_over_angle: 60,
+        tip_over_hold: Duration::from_secs(5),
+        vibration_band_hz: 0,
+        vibration_peak_pct: 60,
+        schedule: crate::schedule::Schedule {
+            windows: [None; crate::schedule::MAX_WINDOWS],
+        },
+    };
+    const ACTIVE_TIMEOUT: Duration = P.inactivity_timeout;
+    const PRE_ALARM_TIMEOUT: Duration = P.pre_alarm_timeout;
+    const EMERGENCY_TIMEOUT: Duration = P.emergency_timeout;
+
+    fn at(millis: u64) -> Instant {
+        Instant::from_millis(millis)
+    }
+
+    fn press(gesture: Gesture) -> AppResetMessage {
+        AppResetMessage::FromButton { gesture }
+    }
+
+    #[test]
+    fn starts_active_with_full_timeout() {
+        let s = AppState::new(at(1_000), &P);
+        assert_eq!(s.deadline(), Some(at(1_000) + ACTIVE_TIMEOUT));
+        assert_eq!(s.time_until_pre_alarm(at(1_000)), ACTIVE_TIMEOUT);
+        assert_eq!(
+            s.time_until_alarm(at(1_000), &P),
+            ACTIVE_TIMEOUT + PRE_ALARM_TIMEOUT
+        );
+    }
+
+    #[test]
+    fn remaining_time_shrinks_and_saturates() {
+        let s = AppState::new(at(0), &P);
+        assert_eq!(
+            s.time_until_escalation(at(2_000)),
+            Ok(ACTIVE_TIMEOUT - Duration::from_secs(2))
+        );
+        assert_eq!(s.time_until_escalation(at(60_000)), Ok(Duration::ZERO));
+        assert_eq!(
+            AppState::Emergency.time_until_escalation(at(0)),
+            Err(TransitionError::NoDeadline)
+        );
+        let alarm = AppState::Alarm {
+            emergency_at: at(10),
+        };
+        assert_eq!(alarm.time_until_alarm(at(0), &P), Duration::ZERO);
+        assert_eq!(
+            alarm.time_until_escalation(at(4)),
+            Ok(Duration::from_millis(6))
+        );
+    }
+
+    #[test]
+    fn escalates_only_when_deadline_passed() {
+        let mut s = AppState::new(at(0), &P);
+        assert_eq!(
+            s.escalate(at(4_000), &P),
+            Err(TransitionError::DeadlineNotReached(Duration::from_secs(1)))
+        );
+        assert_eq!(s, AppState::new(at(0), &P));
+
+        let mut s = AppState::PreAlarm { alarm_at: at(500) };
+        assert_eq!(
+            s.escalate(at(100), &P),
+            Err(TransitionError::DeadlineNotReached(Duration::from_millis(
+                400
+            )))
+        );
+        assert_eq!(s, AppState::PreAlarm { alarm_at: at(500) });
+    }
+
+    #[test]
+    fn escalates_through_all_states() {
+        let mut s = AppState::new(at(0), &P);
+        let pre_alarm_at = at(0) + ACTIVE_TIMEOUT;
+        assert_eq!(s.escalate(pre_alarm_at, &P), Ok(()));
+        assert_eq!(
+            s,
+            AppState::PreAlarm {
+                alarm_at: pre_alarm_at + PRE_ALARM_TIMEOUT
+            }
+        );
+        let alarm_at = pre_alarm_at + PRE_ALARM_TIMEOUT;
+        assert_eq!(s.escalate(alarm_at, &P), Ok(()));
+        assert_eq!(
+            s,
+            AppState::Alarm {
+                emergency_at: alarm_at + EMERGENCY_TIMEOUT
+            }
+        );
+        assert_eq!(
+            s.escalate(alarm_at, &P),
+            Err(TransitionError::DeadlineNotReached(EMERGENCY_TIMEOUT))
+        );
+        assert_eq!(s.escalate(alarm_at + EMERGENCY_TIMEOUT, &P), Ok(()));
+        assert_eq!(s, AppState::Emergency);
+        assert_eq!(
+            s.escalate(at(u64::MAX), &P),
+            Err(TransitionError::NothingToEscalate)
+        );
+    }
+
+    #[test]
+    fn late_update_does_not_stretch_timeout() {
+        let mut s = AppState::new(at(0), &P);
+        let late = at(0) + ACTIVE_TIMEOUT + Duration::from_secs(1);
+        assert!(s.update(late, &P));
+        assert_eq!(
+            s.time_until_alarm(late, &P),
+            PRE_ALARM_TIMEOUT - Duration::from_secs(1)
+        );
+        assert!(s.update(at(0) + ACTIVE_TIMEOUT + PRE_ALARM_TIMEOUT, &P));
+        assert_eq!(s.kind(), StateKind::Alarm);
+        assert!(s.update(at(u64::MAX), &P));
+        assert_eq!(s, AppState::Emergency);
+        assert!(!s.update(at(u64::MAX), &P));
+    }
+
+    #[test]
+    fn update_skips_straight_to_alarm_when_both_deadlines_passed() {
+        let mut s = AppState::new(at(0), &P);
+        assert!(s.update(at(0) + ACTIVE_TIMEOUT + PRE_ALARM_TIMEOUT, &P));
+        assert_eq!(s.kind(), StateKind::Alarm);
+        let mut s = AppState::new(at(0), &P);
+        assert!(s.update(
+            at(0) + ACTIVE_TIMEOUT + PRE_ALARM_TIMEOUT + EMERGENCY_TIMEOUT,
+            &P
+        ));
+        assert_eq!(s, AppState::Emergency);
+    }
+
+    #[test]
+    fn button_only_acknowledges_alarm() {
+        for mut s in [
+            AppState::Alarm {
+                emergency_at: at(9_000),
+            },
+            AppState::Emergency,
+        ] {
+            assert_eq!(s.handle_reset(press(Gesture::Short), at(7_000), &P), Ok(()));
+            assert_eq!(s, AppState::new(at(7_000), &P));
+        }
+
+        for mut s in [
+            AppState::PreAlarm { alarm_at: at(3) },
+            AppState::new(at(1), &P),
+        ] {
+            let before = s;
+            assert_eq!(
+                s.handle_reset(press(Gesture::Short), at(2), &P),
+                Err(TransitionError::ResetIgnored(press(Gesture::Short)))
+            );
+            assert_eq!(s, before);
+        }
+    }
+
+    #[test]
+    fn only_short_press_acknowledges() {
+        for gesture in [Gesture::Long, Gesture::Double] {
+            let mut s = AppState::Emergency;
+            assert_eq!(
+                s.handle_reset(press(gesture), at(2), &P),
+                Err(TransitionError::ResetIgnored(press(gesture)))
+            );
+            assert_eq!(s, AppState::Emergency);
+        }
+    }
+
+    #[test]
+    fn motion_only_cancels_pre_alarm() {
+        let mut s = AppState::PreAlarm { alarm_at: at(10) };
+        assert_eq!(
+            s.handle_reset(
+                AppResetMessage::FromAccelerometer { magnitude: 1200 },
+                at(9),
+                &P
+            ),
+            Ok(())
+        );
+        assert_eq!(s, AppState::new(at(9), &P));
+
+        for mut s in [
+            AppState::Alarm {
+                emergency_at: at(5),
+            },
+            AppState::Emergency,
+            AppState::new(at(1), &P),
+        ] {
+            let before = s;
+            assert_eq!(
+                s.handle_reset(
+                    AppResetMessage::FromAccelerometer { magnitude: 1200 },
+                    at(2),
+                    &P
+                ),
+                Err(TransitionError::ResetIgnored(
+                    AppResetMessage::FromAccelerometer { magnitude: 1200 }
+                ))
+            );
+            assert_eq!(s, before);
+        }
+    }
+
+    #[test]
+    fn fall_raises_alarm_at_once() {
+        let fall = AppResetMessage::FromFall { magnitude: 4000 };
+        for mut s in [
+            AppState::new(at(1), &P),
+            AppState::PreAlarm { alarm_at: at(10) },
+        ] {
+            assert_eq!(s.handle_reset(fall, at(3), &P), Ok(()));
+            assert_eq!(
+                s,
+                AppState::Alarm {
+                    emergency_at: at(3) + EMERGENCY_TIMEOUT
+                }
+            );
+        }
+        for mut s in [AppState::Emergency, AppState::Disarmed] {
+            assert_eq!(
+                s.handle_reset(fall, at(3), &P),
+                Err(TransitionError::ResetIgnored(fall))
+            );
+        }
+    }
+
+    #[test]
+    fn tip_over_cuts_inactivity_short() {
+        let tip_over = AppResetMessage::FromTipOver { angle: 85 };
+        let mut s = AppState::new(at(1), &P);
+        assert_eq!(s.handle_reset(tip_over, at(3), &P), Ok(()));
+        assert_eq!(
+            s,
+            AppState::PreAlarm {
+                alarm_at: at(3) + PRE_ALARM_TIMEOUT
+            }
+        );
+        // Once the pre-alarm runs, tipping over changes nothing.
+        for mut s in [s, AppState::Emergency, AppState::Disarmed] {
+            assert_eq!(
+                s.handle_reset(tip_over, at(4), &P),
+                Err(TransitionError::ResetIgnored(tip_over))
+            );
+        }
+    }
+
+    #[test]
+    fn long_press_disarms_and_rearms() {
+        let mut s = AppState::PreAlarm { alarm_at: at(10) };
+        assert_eq!(s.handle_reset(press(Gesture::Long), at(5), &P), Ok(()));
+        assert_eq!(s, AppState::Disarmed);
+        assert_eq!(s.deadline(), None);
+        assert!(!s.update(at(u64::MAX), &P));
+        assert_eq!(s.time_until_alarm(at(5), &P), Duration::MAX);
+        for message in [
+            press(Gesture::Short),
+            AppResetMessage::FromAccelerometer { magnitude: 1200 },
+        ] {
+            assert_eq!(
+                s.handle_reset(message, at(6), &P),
+                Err(TransitionError::ResetIgnored(message))
+            );
+        }
+        assert_eq!(s.handle_reset(press(Gesture::Long), at(60), &P), Ok(()));
+        assert_eq!(s, AppState::new(at(60), &P));
+    }
+
+    #[test]
+    fn long_press_does_not_silence_alarm() {
+        let mut s = AppState::Emergency;
+        assert_eq!(
+            s.handle_reset(press(Gesture::Long), at(2), &P),
+            Err(TransitionError::ResetIgnored(press(Gesture::Long)))
+        );
+    }
+
+    #[test]
+    fn console_disarms_from_any_state() {
+        let disarm = AppResetMessage::FromConsole { arm: false };
+        let arm = AppResetMessage::FromConsole { arm: true };
+        for mut s in [
+            AppState::new(at(0), &P),
+            AppState::PreAlarm { alarm_at: at(10) },
+            AppState::Alarm {
+                emergency_at: at(10),
+            },
+            AppState::Emergency,
+        ] {
+            assert_eq!(
+                s.handle_reset(arm, at(1), &P),
+                Err(TransitionError::ResetIgnored(arm))
+            );
+            assert_eq!(s.handle_reset(disarm, at(1), &P), Ok(()));
+            assert_eq!(s, AppState::Disarmed);
+            assert_eq!(s.handle_reset(arm, at(2), &P), Ok(()));
+            assert_eq!(s, AppState::new(at(2), &P));
+        }
+    }
+
+    #[test]
+    fn sensor_fault_stops_the_countdown_until_it_recovers() {
+        let fault = AppResetMessage::FromSensor { healthy: false };
+        let ok = AppResetMessage::FromSensor { healthy: true };
+        for mut s in [
+            AppState::new(at(0), &P),
+            AppState::PreAlarm { alarm_at: at(10) },
+        ] {
+            assert_eq!(s.handle_reset(fault, at(1), &P), Ok(()));
+            assert_eq!(s, AppState::SensorFault);
+            assert!(!s.update(at(u64::MAX), &P));
+            assert_eq!(s.time_until_alarm(at(1), &P), Duration::MAX);
+            for message in [
+                AppResetMessage::FromAccelerometer { magnitude: 1200 },
+                fault,
+            ] {
+                assert_eq!(
+                    s.handle_reset(message, at(2), &P),
+                    Err(TransitionError::ResetIgnored(message))
+                );
+            }
+            assert_eq!(s.handle_reset(ok, at(30), &P), Ok(()));
+            assert_eq!(s, AppState::new(at(30), &P));
+        }
+    }
+
+    #[test]
+    fn sensor_fault_leaves_raised_alarm_alone() {
+        let fault = AppResetMessage::FromSensor { healthy: false };
+        let mut s = AppState::Alarm {
+            emergency_at: at(10),
+        };
+        assert_eq!(
+            s.handle_reset(fault, at(1), &P),
+            Err(TransitionError::ResetIgnored(fault))
+        );
+        assert_eq!(s.handle_reset(press(Gesture::Short), at(2), &P), Ok(()));
+        assert_eq!(s.handle_reset(fault, at(3), &P), Ok(()));
+        assert_eq!(s, AppState::SensorFault);
+        assert_eq!(s.handle_reset(press(Gesture::Long), at(4), &P), Ok(()));
+        assert_eq!(s, AppState::Disarmed);
+    }
+}
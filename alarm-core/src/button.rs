@@ -0,0 +1,236 @@
+use core::time::Duration;
+
+use crate::time::Instant;
+
+/// Edges closer together than this are contact bounce and are ignored.
+pub const DEBOUNCE: Duration = Duration::from_millis(20);
+/// How long the button has to be held for a long press.
+pub const LONG_PRESS: Duration = Duration::from_millis(1000);
+/// How soon after a short press the second press of a double press has to
+/// start.
+pub const DOUBLE_PRESS_WINDOW: Duration = Duration::from_millis(300);
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Gesture {
+    Short,
+    Long,
+    Double,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Phase {
+    Idle,
+    /// The button is down. `second` is set for the second press of a
+    /// double press.
+    Down {
+        since: Instant,
+        second: bool,
+    },
+    /// A short press ended and a second one may follow.
+    Up {
+        released_at: Instant,
+    },
+    /// A long press was reported and the button is still down.
+    Held,
+}
+
+/// Turns the raw edges of the user button into gestures.
+///
+/// The firmware feeds every edge to [`edge`](Self::edge) and calls
+/// [`poll`](Self::poll) once [`deadline`](Self::deadline) passes, since a long
+/// press is reported while the button is still held and a short press only
+/// once no second press followed it.
+#[derive(Debug, Clone, Copy)]
+pub struct GestureRecognizer {
+    phase: Phase,
+    last_edge: Option<Instant>,
+}
+
+impl Default for GestureRecognizer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl GestureRecognizer {
+    pub const fn new() -> Self {
+        GestureRecognizer {
+            phase: Phase::Idle,
+            last_edge: None,
+        }
+    }
+    /// Feeds the level of the button after an edge.
+    pub fn edge(&mut self, pressed: bool, now: Instant) -> Option<Gesture> {
+        if self.last_edge.is_some_and(|last| now < last + DEBOUNCE) {
+            return None;
+        }
+        self.last_edge = Some(now);
+        let (phase, gesture) = match (self.phase, pressed) {
+            (Phase::Idle, true) => (
+                Phase::Down {
+                    since: now,
+                    second: false,
+                },
+                None,
+            ),
+            (
+                Phase::Down {
+                    since,
+                    second: false,
+                },
+                false,
+            ) => {
+                if now < since + LONG_PRESS {
+                    (Phase::Up { released_at: now }, None)
+                } else {
+                    (Phase::Idle, Some(Gesture::Long))
+                }
+            }
+            (Phase::Down { second: true, .. }, false) => (Phase::Idle, Some(Gesture::Double)),
+            (Phase::Up { released_at }, true) => {
+                if now < released_at + DOUBLE_PRESS_WINDOW {
+                    (
+                        Phase::Down {
+                            since: now,
+                            second: true,
+                        },
+                        None,
+                    )
+                } else {
+                    // The window was missed by `poll`, so the first press
+                    // still has to be reported.
+                    (
+                        Phase::Down {
+                            since: now,
+                            second: false,
+                        },
+                        Some(Gesture::Short),
+                    )
+                }
+            }
+            (Phase::Held, false) => (Phase::Idle, None),
+            // An edge that does not change the level, most likely because
+            // its counterpart fell inside the debounce time.
+            (phase, _) => (phase, None),
+        };
+        self.phase = phase;
+        gesture
+    }
+    /// Reports gestures that complete without an edge.
+    pub fn poll(&mut self, now: Instant) -> Option<Gesture> {
+        match self.phase {
+            Phase::Down {
+                since,
+                second: false,
+            } if now >= since + LONG_PRESS => {
+                self.phase = Phase::Held;
+                Some(Gesture::Long)
+            }
+            Phase::Up { released_at } if now >= released_at + DOUBLE_PRESS_WINDOW => {
+                self.phase = Phase::Idle;
+                Some(Gesture::Short)
+            }
+            _ => None,
+        }
+    }
+    /// When [`poll`](Self::poll) next needs to run, if at all.
+    pub fn deadline(&self) -> Option<Instant> {
+        match self.phase {
+            Phase::Down {
+                since,
+                second: false,
+            } => Some(since + LONG_PRESS),
+            Phase::Up { released_at } => Some(released_at + DOUBLE_PRESS_WINDOW),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use std::vec::Vec;
+
+    use super::*;
+
+    fn at(millis: u64) -> Instant {
+        Instant::from_millis(millis)
+    }
+
+    /// Feeds `(millis, pressed)` edges, polling at every deadline in between,
+    /// and collects the gestures up to `end`.
+    fn run(edges: &[(u64, bool)], end: u64) -> Vec<Gesture> {
+        let mut button = GestureRecognizer::new();
+        let mut gestures = Vec::new();
+        for &(millis, pressed) in edges.iter().chain([(end, false)].iter()) {
+            while let Some(deadline) = button.deadline().filter(|&d| d <= at(millis)) {
+                gestures.extend(button.poll(deadline));
+            }
+            if millis < end {
+                gestures.extend(button.edge(pressed, at(millis)));
+            }
+        }
+        gestures
+    }
+
+    #[test]
+    fn short_press_waits_out_double_window() {
+        let mut button = GestureRecognizer::new();
+        assert_eq!(button.edge(true, at(0)), None);
+        assert_eq!(button.edge(false, at(120)), None);
+        assert_eq!(button.poll(at(419)), None);
+        assert_eq!(button.deadline(), Some(at(420)));
+        assert_eq!(button.poll(at(420)), Some(Gesture::Short));
+        assert_eq!(button.deadline(), None);
+    }
+
+    #[test]
+    fn long_press_fires_while_held() {
+        let mut button = GestureRecognizer::new();
+        button.edge(true, at(0));
+        assert_eq!(button.deadline(), Some(at(1000)));
+        assert_eq!(button.poll(at(999)), None);
+        assert_eq!(button.poll(at(1000)), Some(Gesture::Long));
+        assert_eq!(button.edge(false, at(3000)), None);
+        assert_eq!(button.poll(at(9000)), None);
+    }
+
+    #[test]
+    fn recognizes_double_press() {
+        assert_eq!(
+            run(&[(0, true), (100, false), (250, true), (350, false)], 2000),
+            [Gesture::Double]
+        );
+    }
+
+    #[test]
+    fn slow_second_press_is_two_short_presses() {
+        assert_eq!(
+            run(&[(0, true), (100, false), (500, true), (600, false)], 2000),
+            [Gesture::Short, Gesture::Short]
+        );
+    }
+
+    #[test]
+    fn ignores_contact_bounce() {
+        let bouncy = [
+            (0, true),
+            (2, false),
+            (5, true),
+            (150, false),
+            (153, true),
+            (158, false),
+        ];
+        assert_eq!(run(&bouncy, 2000), [Gesture::Short]);
+    }
+
+    #[test]
+    fn late_edge_still_reports_pending_gestures() {
+        // Without polling, the next edge has to settle what came before it.
+        let mut button = GestureRecognizer::new();
+        button.edge(true, at(0));
+        button.edge(false, at(100));
+        assert_eq!(button.edge(true, at(5000)), Some(Gesture::Short));
+        assert_eq!(button.edge(false, at(7000)), Some(Gesture::Long));
+    }
+}
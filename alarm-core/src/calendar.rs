@@ -0,0 +1,218 @@
+use core::fmt;
+
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub enum Weekday {
+    Monday,
+    Tuesday,
+    Wednesday,
+    Thursday,
+    Friday,
+    Saturday,
+    Sunday,
+}
+
+impl Weekday {
+    /// Monday first, the way the RTC numbers them (from 1).
+    pub const ALL: [Weekday; 7] = [
+        Weekday::Monday,
+        Weekday::Tuesday,
+        Weekday::Wednesday,
+        Weekday::Thursday,
+        Weekday::Friday,
+        Weekday::Saturday,
+        Weekday::Sunday,
+    ];
+
+    /// Days since Monday.
+    pub fn index(self) -> u8 {
+        self as u8
+    }
+    pub fn from_index(index: u8) -> Option<Weekday> {
+        Weekday::ALL.get(usize::from(index)).copied()
+    }
+    pub fn abbreviation(self) -> &'static str {
+        match self {
+            Weekday::Monday => "mon",
+            Weekday::Tuesday => "tue",
+            Weekday::Wednesday => "wed",
+            Weekday::Thursday => "thu",
+            Weekday::Friday => "fri",
+            Weekday::Saturday => "sat",
+            Weekday::Sunday => "sun",
+        }
+    }
+    pub fn from_abbreviation(abbreviation: &str) -> Option<Weekday> {
+        Weekday::ALL
+            .into_iter()
+            .find(|day| day.abbreviation() == abbreviation)
+    }
+}
+
+/// A wall-clock date and time as kept by the RTC.
+///
+/// The RTC stores a two-digit year, so only 2000 to 2099 can be represented.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub struct DateTime {
+    year: u16,
+    month: u8,
+    day: u8,
+    hour: u8,
+    minute: u8,
+    second: u8,
+}
+
+pub const MIN_YEAR: u16 = 2000;
+pub const MAX_YEAR: u16 = 2099;
+
+impl DateTime {
+    /// Where the RTC starts after a backup domain reset.
+    pub const EPOCH: DateTime = DateTime {
+        year: MIN_YEAR,
+        month: 1,
+        day: 1,
+        hour: 0,
+        minute: 0,
+        second: 0,
+    };
+
+    /// Returns `None` for anything that is not a real date and time.
+    pub fn new(
+        year: u16,
+        month: u8,
+        day: u8,
+        hour: u8,
+        minute: u8,
+        second: u8,
+    ) -> Option<DateTime> {
+        let valid = (MIN_YEAR..=MAX_YEAR).contains(&year)
+            && (1..=12).contains(&month)
+            && (1..=days_in_month(year, month)).contains(&day)
+            && hour < 24
+            && minute < 60
+            && second < 60;
+        valid.then_some(DateTime {
+            year,
+            month,
+            day,
+            hour,
+            minute,
+            second,
+        })
+    }
+    /// Parses the `YYYY-MM-DD` and `HH:MM:SS` printed by `Display`.
+    pub fn parse(date: &str, time: &str) -> Option<DateTime> {
+        let mut date = date.split('-').map(str::parse::<u16>);
+        let mut time = time.split(':').map(str::parse::<u8>);
+        let mut next_date = || date.next()?.ok();
+        let mut next_time = || time.next()?.ok();
+        let year = next_date()?;
+        let month = u8::try_from(next_date()?).ok()?;
+        let day = u8::try_from(next_date()?).ok()?;
+        let parsed = DateTime::new(year, month, day, next_time()?, next_time()?, next_time()?)?;
+        match (next_date(), next_time()) {
+            (None, None) => Some(parsed),
+            _ => None,
+        }
+    }
+    pub fn year(&self) -> u16 {
+        self.year
+    }
+    pub fn month(&self) -> u8 {
+        self.month
+    }
+    pub fn day(&self) -> u8 {
+        self.day
+    }
+    pub fn hour(&self) -> u8 {
+        self.hour
+    }
+    pub fn minute(&self) -> u8 {
+        self.minute
+    }
+    pub fn second(&self) -> u8 {
+        self.second
+    }
+    pub fn weekday(&self) -> Weekday {
+        // Zeller's congruence with January and February counted as months 13
+        // and 14 of the previous year; `h` is 0 on Saturday.
+        let (year, month) = match self.month {
+            1 | 2 => (u32::from(self.year) - 1, u32::from(self.month) + 12),
+            month => (u32::from(self.year), u32::from(month)),
+        };
+        let h = (u32::from(self.day) + 13 * (month + 1) / 5 + year + year / 4 - year / 100
+            + year / 400)
+            % 7;
+        Weekday::ALL[((h + 5) % 7) as usize]
+    }
+    /// Minutes since midnight.
+    pub fn minute_of_day(&self) -> u16 {
+        u16::from(self.hour) * 60 + u16::from(self.minute)
+    }
+}
+
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+fn days_in_month(year: u16, month: u8) -> u8 {
+    match month {
+        2 if year.is_multiple_of(4) && (!year.is_multiple_of(100) || year.is_multiple_of(400)) => {
+            29
+        }
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// A running wall clock, i.e. the RTC.
+pub trait Calendar {
+    fn now(&mut self) -> DateTime;
+    fn set(&mut self, at: DateTime);
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use std::string::ToString;
+
+    use super::*;
+
+    fn date(year: u16, month: u8, day: u8) -> DateTime {
+        DateTime::new(year, month, day, 0, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn rejects_impossible_dates() {
+        assert!(DateTime::new(2024, 2, 29, 23, 59, 59).is_some());
+        assert_eq!(DateTime::new(2023, 2, 29, 0, 0, 0), None);
+        assert_eq!(DateTime::new(2026, 4, 31, 0, 0, 0), None);
+        assert_eq!(DateTime::new(2026, 13, 1, 0, 0, 0), None);
+        assert_eq!(DateTime::new(2026, 1, 1, 24, 0, 0), None);
+        assert_eq!(DateTime::new(1999, 12, 31, 0, 0, 0), None);
+    }
+
+    #[test]
+    fn computes_weekday() {
+        assert_eq!(date(2000, 1, 1).weekday(), Weekday::Saturday);
+        assert_eq!(date(2024, 2, 29).weekday(), Weekday::Thursday);
+        assert_eq!(date(2026, 10, 18).weekday(), Weekday::Sunday);
+        assert_eq!(date(2026, 10, 19).weekday(), Weekday::Monday);
+    }
+
+    #[test]
+    fn parses_what_it_prints() {
+        let at = DateTime::new(2026, 3, 7, 22, 5, 9).unwrap();
+        assert_eq!(at.to_string(), "2026-03-07 22:05:09");
+        assert_eq!(DateTime::parse("2026-03-07", "22:05:09"), Some(at));
+        assert_eq!(DateTime::parse("2026-03-07", "22:05"), None);
+        assert_eq!(DateTime::parse("2026-03-07-01", "22:05:09"), None);
+        assert_eq!(DateTime::parse("2026-02-30", "22:05:09"), None);
+    }
+}
@@ -0,0 +1,389 @@
+use crate::{
+    compass::{MagCalibration, SCALE_ONE},
+    flash::FlashRegion,
+    motion::RawSample,
+    profile::AccelSensitivity,
+    ring::{FlashRing, StorageError, RECORD_OVERHEAD},
+};
+
+/// Layout version of the stored payload. Bump it whenever the payload changes
+/// and teach [`decode_payload`] how to read the previous one.
+pub const CALIBRATION_VERSION: u16 = 1;
+const MAGIC: u16 = 0xCA1B;
+pub const RECORD_SIZE: usize = 64;
+
+/// A face counts as down when gravity along its axis reads at least this
+/// much, in milli-g. The worst zero-g offset in the datasheet is 60 mg.
+const MIN_FACE_MG: i32 = 800;
+/// ...and the other two axes read no more than this each.
+const MAX_CROSS_MG: i32 = 250;
+/// The gravity estimate moves 1/2^`LEVEL_SHIFT` of the way towards each
+/// sample, so at the compass's 10 Hz it settles within three seconds of the
+/// board being put down.
+const LEVEL_SHIFT: u32 = 2;
+/// Fractional bits kept in the gravity estimate.
+const LEVEL_FRACTION_BITS: u32 = 4;
+
+/// Everything measured on one particular board.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub struct Calibration {
+    pub mag: MagCalibration,
+    pub accel: AccelCalibration,
+}
+
+/// Zero-g offset and gain correction for the accelerometer.
+///
+/// The offset is kept in milli-g rather than counts, so one calibration holds
+/// at every range.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct AccelCalibration {
+    /// Zero-g offset per axis, in milli-g.
+    pub offset: [i16; 3],
+    /// Gain correction per axis, with [`SCALE_ONE`] meaning 1.
+    pub scale: [u16; 3],
+}
+
+impl AccelCalibration {
+    pub const UNCALIBRATED: AccelCalibration = AccelCalibration {
+        offset: [0; 3],
+        scale: [SCALE_ONE; 3],
+    };
+
+    /// Corrects a sample read at `sensitivity`. It stays in raw counts, so
+    /// the sensor driver can apply it before anything else sees the sample.
+    pub fn apply(&self, raw: RawSample, sensitivity: AccelSensitivity) -> RawSample {
+        let mut sample = [0; 3];
+        for (axis, out) in sample.iter_mut().enumerate() {
+            // Raw counts are left-justified, 16 to the least significant bit.
+            let offset = i32::from(self.offset[axis]) * 16 / i32::from(sensitivity.mg_per_lsb());
+            let counts = (i32::from(raw[axis]) - offset) * i32::from(self.scale[axis])
+                / i32::from(SCALE_ONE);
+            *out = counts.clamp(i16::MIN.into(), i16::MAX.into()) as i16;
+        }
+        sample
+    }
+}
+
+impl Default for AccelCalibration {
+    fn default() -> Self {
+        AccelCalibration::UNCALIBRATED
+    }
+}
+
+/// The six ways the board is put down for [`AccelCalibrator`], in the order
+/// it asks for them. `XUp` means the +X axis points up, away from the table.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Orientation {
+    ZUp,
+    ZDown,
+    XUp,
+    XDown,
+    YUp,
+    YDown,
+}
+
+impl Orientation {
+    pub const ALL: [Orientation; 6] = [
+        Orientation::ZUp,
+        Orientation::ZDown,
+        Orientation::XUp,
+        Orientation::XDown,
+        Orientation::YUp,
+        Orientation::YDown,
+    ];
+
+    fn axis(self) -> usize {
+        match self {
+            Orientation::XUp | Orientation::XDown => 0,
+            Orientation::YUp | Orientation::YDown => 1,
+            Orientation::ZUp | Orientation::ZDown => 2,
+        }
+    }
+    /// Lying still, the sensor reads +1 g on the axis pointing up.
+    fn up(self) -> bool {
+        matches!(self, Orientation::XUp | Orientation::YUp | Orientation::ZUp)
+    }
+}
+
+/// Works out an [`AccelCalibration`] from the board resting on each of its
+/// six faces in turn.
+///
+/// Gravity reads +1 g on an axis pointing up and -1 g pointing down. The
+/// middle of the two readings is the zero-g offset and their spread gives the
+/// gain. Samples are fed in continuously and [`confirm`](Self::confirm) takes
+/// the settled reading when the user says the board is in place.
+#[derive(Debug, Clone, Copy)]
+pub struct AccelCalibrator {
+    /// Low-passed milli-g with `LEVEL_FRACTION_BITS` fractional bits, or
+    /// `None` before the first sample.
+    level: Option<[i32; 3]>,
+    step: usize,
+    up: [i32; 3],
+    down: [i32; 3],
+}
+
+impl AccelCalibrator {
+    pub const fn new() -> Self {
+        AccelCalibrator {
+            level: None,
+            step: 0,
+            up: [0; 3],
+            down: [0; 3],
+        }
+    }
+    /// The orientation to put the board in next, or `None` once all six have
+    /// been taken.
+    pub fn next(&self) -> Option<Orientation> {
+        Orientation::ALL.get(self.step).copied()
+    }
+    /// Feeds one uncalibrated sample, in milli-g.
+    pub fn update(&mut self, mg: [i32; 3]) {
+        let sample = mg.map(|axis| axis << LEVEL_FRACTION_BITS);
+        self.level = Some(match self.level {
+            None => sample,
+            Some(mut level) => {
+                for (level, sample) in level.iter_mut().zip(sample) {
+                    *level += (sample - *level) >> LEVEL_SHIFT;
+                }
+                level
+            }
+        });
+    }
+    /// Takes the reading for [`next`](Self::next). Returns `false`, leaving
+    /// the step to be retried, if the board is not resting that way up.
+    pub fn confirm(&mut self) -> bool {
+        let (Some(level), Some(orientation)) = (self.level, self.next()) else {
+            return false;
+        };
+        // Rounded, as the estimate can settle just under a sample it
+        // approaches from below.
+        let half = 1 << (LEVEL_FRACTION_BITS - 1);
+        let mg = level.map(|axis| (axis + half) >> LEVEL_FRACTION_BITS);
+        let axis = orientation.axis();
+        let along = if orientation.up() {
+            mg[axis]
+        } else {
+            -mg[axis]
+        };
+        let level_elsewhere = (0..3)
+            .filter(|&other| other != axis)
+            .all(|other| mg[other].abs() <= MAX_CROSS_MG);
+        if along < MIN_FACE_MG || !level_elsewhere {
+            return false;
+        }
+        if orientation.up() {
+            self.up[axis] = mg[axis];
+        } else {
+            self.down[axis] = mg[axis];
+        }
+        self.step += 1;
+        true
+    }
+    /// The calibration, or `None` until all six orientations are in.
+    pub fn finish(&self) -> Option<AccelCalibration> {
+        self.next().is_none().then(|| AccelCalibration {
+            offset: [0, 1, 2].map(|axis| ((self.up[axis] + self.down[axis]) / 2) as i16),
+            scale: [0, 1, 2].map(|axis| {
+                (2000 * i32::from(SCALE_ONE) / (self.up[axis] - self.down[axis])) as u16
+            }),
+        })
+    }
+}
+
+impl Default for AccelCalibrator {
+    fn default() -> Self {
+        AccelCalibrator::new()
+    }
+}
+
+/// Keeps the [`Calibration`] in its own flash region.
+///
+/// It belongs to the hardware rather than to the profile, so restoring the
+/// factory profile leaves it alone. Saves go into a [`FlashRing`] just like
+/// the [`ConfigStore`](crate::ConfigStore)'s.
+pub struct CalibrationStore<F> {
+    ring: FlashRing<F, RECORD_SIZE>,
+}
+
+impl<F: FlashRegion> CalibrationStore<F> {
+    pub fn new(flash: F) -> Self {
+        CalibrationStore {
+            ring: FlashRing::new(flash, MAGIC),
+        }
+    }
+    pub fn release(self) -> F {
+        self.ring.release()
+    }
+    /// Returns the newest stored calibration, or `None` if the board has
+    /// never been calibrated.
+    pub fn load(&mut self) -> Result<Option<Calibration>, StorageError<F::Error>> {
+        self.ring.scan()?;
+        let mut newest = None;
+        self.ring.for_each(|version, _, payload| {
+            if let Some(calibration) = decode_payload(version, payload) {
+                newest = Some(calibration);
+            }
+        })?;
+        Ok(newest)
+    }
+    /// The stored calibration, or an uncalibrated one when there is none.
+    pub fn load_or_default(&mut self) -> Calibration {
+        self.load().ok().flatten().unwrap_or_default()
+    }
+    pub fn save(&mut self, calibration: &Calibration) -> Result<(), StorageError<F::Error>> {
+        let mut payload = [0; RECORD_SIZE - RECORD_OVERHEAD];
+        encode_payload(calibration, &mut payload);
+        self.ring.append(CALIBRATION_VERSION, &payload)?;
+        Ok(())
+    }
+}
+
+/// Payload layout, little endian:
+///
+/// ```text
+/// 0  mag offset i16 x3   | 6  mag scale u16 x3
+/// 12 accel offset i16 x3 | 18 accel scale u16 x3
+/// ```
+fn encode_payload(calibration: &Calibration, payload: &mut [u8]) {
+    for axis in 0..3 {
+        let i = 2 * axis;
+        payload[i..i + 2].copy_from_slice(&calibration.mag.offset[axis].to_le_bytes());
+        payload[6 + i..8 + i].copy_from_slice(&calibration.mag.scale[axis].to_le_bytes());
+        payload[12 + i..14 + i].copy_from_slice(&calibration.accel.offset[axis].to_le_bytes());
+        payload[18 + i..20 + i].copy_from_slice(&calibration.accel.scale[axis].to_le_bytes());
+    }
+}
+
+fn decode_payload(version: u16, payload: &[u8]) -> Option<Calibration> {
+    let bytes = |i: usize| [payload[i], payload[i + 1]];
+    let mag = MagCalibration {
+        offset: [0, 1, 2].map(|axis| i16::from_le_bytes(bytes(2 * axis))),
+        scale: [0, 1, 2].map(|axis| u16::from_le_bytes(bytes(6 + 2 * axis))),
+    };
+    match version {
+        1 => Some(Calibration {
+            mag,
+            accel: AccelCalibration {
+                offset: [0, 1, 2].map(|axis| i16::from_le_bytes(bytes(12 + 2 * axis))),
+                scale: [0, 1, 2].map(|axis| u16::from_le_bytes(bytes(18 + 2 * axis))),
+            },
+        }),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flash::mock::MockFlash;
+
+    const PAGE_SIZE: usize = 256;
+
+    #[test]
+    fn uncalibrated_until_saved() {
+        let mut store = CalibrationStore::new(MockFlash::new(PAGE_SIZE, 2));
+        assert_eq!(store.load(), Ok(None));
+        let calibration = store.load_or_default();
+        assert_eq!(calibration.mag, MagCalibration::UNCALIBRATED);
+        assert_eq!(calibration.accel, AccelCalibration::UNCALIBRATED);
+    }
+
+    #[test]
+    fn round_trips_through_reboot() {
+        let mut store = CalibrationStore::new(MockFlash::new(PAGE_SIZE, 2));
+        store.load().unwrap();
+        let saved = Calibration {
+            mag: MagCalibration {
+                offset: [120, -80, -32768],
+                scale: [853, 1024, 1138],
+            },
+            accel: AccelCalibration {
+                offset: [35, -12, 60],
+                scale: [1004, 1031, 1024],
+            },
+        };
+        store.save(&saved).unwrap();
+
+        let mut rebooted = CalibrationStore::new(store.release());
+        assert_eq!(rebooted.load(), Ok(Some(saved)));
+    }
+
+    #[test]
+    fn uncalibrated_accel_passes_samples_through() {
+        let raw = [1600, -16000, i16::MIN];
+        assert_eq!(
+            AccelCalibration::UNCALIBRATED.apply(raw, AccelSensitivity::G4),
+            raw
+        );
+    }
+
+    #[test]
+    fn accel_offset_holds_at_every_range() {
+        let calibration = AccelCalibration {
+            offset: [40, -20, 0],
+            scale: [SCALE_ONE, SCALE_ONE, SCALE_ONE * 2],
+        };
+        // 40 mg is 640 counts at ±2 g and 160 counts at ±8 g.
+        assert_eq!(
+            calibration.apply([640, -320, 100], AccelSensitivity::G1),
+            [0, 0, 200]
+        );
+        assert_eq!(
+            calibration.apply([160, -80, 20_000], AccelSensitivity::G4),
+            [0, 0, i16::MAX]
+        );
+    }
+
+    /// Feeds a board whose axes read `offset` at zero g and `gain` times
+    /// gravity, lying still in `orientation`.
+    fn settle(
+        calibrator: &mut AccelCalibrator,
+        orientation: Orientation,
+        offset: [i32; 3],
+        gain: [i32; 3],
+    ) {
+        let mut mg = [0; 3];
+        let axis = orientation.axis();
+        mg[axis] = if orientation.up() { 1000 } else { -1000 };
+        for _ in 0..64 {
+            calibrator.update([0, 1, 2].map(|i| offset[i] + mg[i] * gain[i] / 1000));
+        }
+    }
+
+    #[test]
+    fn six_orientations_give_offset_and_gain() {
+        let offset = [50, -30, 10];
+        let gain = [1000, 1024, 976];
+        let mut calibrator = AccelCalibrator::new();
+        for orientation in Orientation::ALL {
+            assert_eq!(calibrator.finish(), None);
+            assert_eq!(calibrator.next(), Some(orientation));
+            settle(&mut calibrator, orientation, offset, gain);
+            assert!(calibrator.confirm());
+        }
+        assert_eq!(calibrator.next(), None);
+        let calibration = calibrator.finish().unwrap();
+        assert_eq!(calibration.offset, [50, -30, 10]);
+        assert_eq!(calibration.scale, [1024, 1000, 1049]);
+        // A reading of 1 g on the stretched Y axis comes out as 1 g.
+        let raw = [50 * 16, (-30 + 1024) * 16, 10 * 16];
+        assert_eq!(calibration.apply(raw, AccelSensitivity::G1), [0, 16000, 0]);
+    }
+
+    #[test]
+    fn wrong_orientation_is_retried() {
+        let mut calibrator = AccelCalibrator::new();
+        assert!(!calibrator.confirm());
+        settle(&mut calibrator, Orientation::ZDown, [0; 3], [1000; 3]);
+        assert!(!calibrator.confirm());
+        assert_eq!(calibrator.next(), Some(Orientation::ZUp));
+        // Tilted halfway between two faces.
+        for _ in 0..64 {
+            calibrator.update([0, 700, 700]);
+        }
+        assert!(!calibrator.confirm());
+        settle(&mut calibrator, Orientation::ZUp, [0; 3], [1000; 3]);
+        assert!(calibrator.confirm());
+        assert_eq!(calibrator.next(), Some(Orientation::ZDown));
+    }
+}
@@ -0,0 +1,527 @@
+use core::{fmt, str, time::Duration};
+
+use crate::{
+    activity::ActivityReport,
+    app_state::AppResetMessage,
+    calendar::{Calendar, DateTime},
+    flash::FlashRegion,
+    journal::Journal,
+    profile::{AccelOdr, AccelSensitivity, AlarmProfile, MotionSource, Setting},
+    schedule::{self, Days, Window, MAX_WINDOWS},
+    storage::ConfigStore,
+};
+
+/// A line typed on the console.
+///
+/// ```text
+/// show
+/// defaults
+/// save
+/// journal
+/// activity
+/// arm
+/// disarm
+/// time [YYYY-MM-DD HH:MM:SS]
+/// schedule <slot> <days> <HH:MM> <HH:MM>
+/// schedule <slot> off
+/// set <key> <value>
+/// ```
+///
+/// The keys are the ones printed by `show`. Schedule days are `daily`,
+/// `weekdays`, `weekends` or a list such as `mon,wed,fri`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Command {
+    Show,
+    Defaults,
+    /// Writes the live profile to flash so it survives a reset.
+    Save,
+    /// Prints the black-box journal, oldest entry first.
+    Journal,
+    /// Prints what the wearer is doing and the steps counted since boot.
+    Activity,
+    /// Resumes monitoring with a fresh inactivity timeout.
+    Arm,
+    /// Pauses monitoring, e.g. while the device is charged or serviced.
+    Disarm,
+    /// Prints the RTC time, or sets it.
+    Time(Option<DateTime>),
+    Set(Setting),
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum CommandError {
+    Empty,
+    UnknownCommand,
+    UnknownKey,
+    MissingValue,
+    InvalidValue,
+    LineTooLong,
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            CommandError::Empty => "empty command",
+            CommandError::UnknownCommand => "unknown command",
+            CommandError::UnknownKey => "unknown key",
+            CommandError::MissingValue => "missing value",
+            CommandError::InvalidValue => "invalid value",
+            CommandError::LineTooLong => "line too long",
+        })
+    }
+}
+
+impl Command {
+    pub fn parse(line: &str) -> Result<Command, CommandError> {
+        let mut words = line.split_whitespace();
+        let command = match words.next().ok_or(CommandError::Empty)? {
+            "show" => Command::Show,
+            "defaults" => Command::Defaults,
+            "save" => Command::Save,
+            "journal" => Command::Journal,
+            "activity" => Command::Activity,
+            "arm" => Command::Arm,
+            "disarm" => Command::Disarm,
+            "time" => match words.next() {
+                Some(date) => {
+                    let time = words.next().ok_or(CommandError::MissingValue)?;
+                    Command::Time(Some(
+                        DateTime::parse(date, time).ok_or(CommandError::InvalidValue)?,
+                    ))
+                }
+                None => Command::Time(None),
+            },
+            "schedule" => Command::Set(parse_window(&mut words)?),
+            "set" => {
+                let key = words.next().ok_or(CommandError::MissingValue)?;
+                let value = words.next().ok_or(CommandError::MissingValue)?;
+                Command::Set(parse_setting(key, value)?)
+            }
+            _ => return Err(CommandError::UnknownCommand),
+        };
+        match words.next() {
+            Some(_) => Err(CommandError::InvalidValue),
+            None => Ok(command),
+        }
+    }
+    /// The message to queue for the state machine, for commands that change
+    /// the alarm state rather than the profile.
+    pub fn reset_message(&self) -> Option<AppResetMessage> {
+        match self {
+            Command::Arm => Some(AppResetMessage::FromConsole { arm: true }),
+            Command::Disarm => Some(AppResetMessage::FromConsole { arm: false }),
+            _ => None,
+        }
+    }
+    /// Whether the schedule has to be re-evaluated after this command.
+    pub fn affects_schedule(&self) -> bool {
+        matches!(
+            self,
+            Command::Defaults | Command::Time(Some(_)) | Command::Set(Setting::Window { .. })
+        )
+    }
+    /// Runs the command against the live profile and writes the reply to the
+    /// console. Commands with a [`reset_message`](Self::reset_message) only
+    /// acknowledge it here; the caller queues the message.
+    pub fn execute<F: FlashRegion, J: FlashRegion, C: Calendar>(
+        self,
+        context: CommandContext<'_, F, J, C>,
+        out: &mut impl fmt::Write,
+    ) -> fmt::Result {
+        let CommandContext {
+            profile,
+            store,
+            journal,
+            activity,
+            calendar,
+        } = context;
+        match self {
+            Command::Show => writeln!(out, "{}", profile),
+            Command::Defaults => {
+                *profile = AlarmProfile::default();
+                writeln!(out, "ok")
+            }
+            Command::Save => match store.save(profile) {
+                Ok(()) => writeln!(out, "ok"),
+                Err(e) => writeln!(out, "error: {}", e),
+            },
+            Command::Journal => match journal.dump(out) {
+                Ok(result) => result,
+                Err(e) => writeln!(out, "error: {}", e),
+            },
+            Command::Activity => writeln!(out, "{}", activity),
+            Command::Arm | Command::Disarm => writeln!(out, "ok"),
+            Command::Time(None) => writeln!(out, "{}", calendar.now()),
+            Command::Time(Some(at)) => {
+                calendar.set(at);
+                writeln!(out, "ok")
+            }
+            Command::Set(setting) => match profile.apply(setting) {
+                Ok(()) => writeln!(out, "ok"),
+                Err(e) => writeln!(out, "error: {}", e),
+            },
+        }
+    }
+}
+
+/// Everything a [`Command`] runs against, borrowed from the firmware's shared
+/// state for as long as one command takes.
+pub struct CommandContext<'a, F, J, C> {
+    pub profile: &'a mut AlarmProfile,
+    pub store: &'a mut ConfigStore<F>,
+    pub journal: &'a mut Journal<J>,
+    pub activity: &'a ActivityReport,
+    pub calendar: &'a mut C,
+}
+
+fn parse_setting(key: &str, value: &str) -> Result<Setting, CommandError> {
+    let number = || value.parse::<u64>().map_err(|_| CommandError::InvalidValue);
+    Ok(match key {
+        "inactivity_timeout_ms" => Setting::InactivityTimeout(Duration::from_millis(number()?)),
+        "pre_alarm_timeout_ms" => Setting::PreAlarmTimeout(Duration::from_millis(number()?)),
+        "motion_threshold_mg" => Setting::MotionThreshold(
+            u16::try_from(number()?).map_err(|_| CommandError::InvalidValue)?,
+        ),
+        "sample_period_ms" => Setting::SamplePeriod(Duration::from_millis(number()?)),
+        "motion_source" => {
+            Setting::MotionSource(MotionSource::from_name(value).ok_or(CommandError::InvalidValue)?)
+        }
+        "accel_odr_hz" => Setting::AccelOdr(
+            u16::try_from(number()?)
+                .ok()
+                .and_then(AccelOdr::from_hz)
+                .ok_or(CommandError::InvalidValue)?,
+        ),
+        "accel_range_g" => Setting::AccelSensitivity(
+            u8::try_from(number()?)
+                .ok()
+                .and_then(AccelSensitivity::from_range_g)
+                .ok_or(CommandError::InvalidValue)?,
+        ),
+        "emergency_timeout_ms" => Setting::EmergencyTimeout(Duration::from_millis(number()?)),
+        "free_fall_threshold_mg" => Setting::FreeFallThreshold(
+            u16::try_from(number()?).map_err(|_| CommandError::InvalidValue)?,
+        ),
+        "impact_threshold_mg" => Setting::ImpactThreshold(
+            u16::try_from(number()?).map_err(|_| CommandError::InvalidValue)?,
+        ),
+        "fall_stillness_ms" => Setting::FallStillness(Duration::from_millis(number()?)),
+        "rotation_threshold_dps" => Setting::RotationThreshold(
+            u16::try_from(number()?).map_err(|_| CommandError::InvalidValue)?,
+        ),
+        "tip_over_angle_deg" => {
+            Setting::TipOverAngle(u16::try_from(number()?).map_err(|_| CommandError::InvalidValue)?)
+        }
+        "tip_over_hold_ms" => Setting::TipOverHold(Duration::from_millis(number()?)),
+        "vibration_band_hz" => Setting::VibrationBand(
+            u16::try_from(number()?).map_err(|_| CommandError::InvalidValue)?,
+        ),
+        "vibration_peak_pct" => Setting::VibrationPeak(
+            u16::try_from(number()?).map_err(|_| CommandError::InvalidValue)?,
+        ),
+        _ => return Err(CommandError::UnknownKey),
+    })
+}
+
+/// Parses the arguments of `schedule`, numbering slots from one.
+fn parse_window<'a>(words: &mut impl Iterator<Item = &'a str>) -> Result<Setting, CommandError> {
+    let mut next = || words.next().ok_or(CommandError::MissingValue);
+    let slot = next()?
+        .parse::<usize>()
+        .ok()
+        .filter(|slot| (1..=MAX_WINDOWS).contains(slot))
+        .ok_or(CommandError::InvalidValue)?;
+    let window = match next()? {
+        "off" => None,
+        days => Some(Window {
+            days: Days::parse(days).ok_or(CommandError::InvalidValue)?,
+            start: schedule::parse_time_of_day(next()?).ok_or(CommandError::InvalidValue)?,
+            end: schedule::parse_time_of_day(next()?).ok_or(CommandError::InvalidValue)?,
+        }),
+    };
+    Ok(Setting::Window {
+        slot: slot - 1,
+        window,
+    })
+}
+
+/// Collects console bytes until a line ending arrives.
+pub struct LineBuffer<const N: usize> {
+    buf: [u8; N],
+    len: usize,
+    overflowed: bool,
+}
+
+impl<const N: usize> Default for LineBuffer<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> LineBuffer<N> {
+    pub const fn new() -> Self {
+        LineBuffer {
+            buf: [0; N],
+            len: 0,
+            overflowed: false,
+        }
+    }
+    /// Feeds one byte. Returns the finished line when `byte` ends one; blank
+    /// lines are skipped. The buffer is cleared for the next line either way.
+    pub fn push(&mut self, byte: u8) -> Option<Result<&str, CommandError>> {
+        if byte != b'\r' && byte != b'\n' {
+            if self.len < N {
+                self.buf[self.len] = byte;
+                self.len += 1;
+            } else {
+                self.overflowed = true;
+            }
+            return None;
+        }
+        let len = core::mem::take(&mut self.len);
+        if core::mem::take(&mut self.overflowed) {
+            return Some(Err(CommandError::LineTooLong));
+        }
+        if len == 0 {
+            return None;
+        }
+        Some(str::from_utf8(&self.buf[..len]).map_err(|_| CommandError::InvalidValue))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::flash::mock::MockFlash;
+    use std::string::String;
+
+    struct Clock(DateTime);
+
+    impl Calendar for Clock {
+        fn now(&mut self) -> DateTime {
+            self.0
+        }
+        fn set(&mut self, at: DateTime) {
+            self.0 = at;
+        }
+    }
+
+    fn noon() -> DateTime {
+        DateTime::new(2026, 10, 19, 12, 0, 0).unwrap()
+    }
+
+    /// What the firmware's console task owns, set to noon with the defaults.
+    struct Console {
+        profile: AlarmProfile,
+        store: ConfigStore<MockFlash>,
+        journal: Journal<MockFlash>,
+        activity: ActivityReport,
+        clock: Clock,
+    }
+
+    impl Console {
+        fn new() -> Self {
+            Console {
+                profile: AlarmProfile::default(),
+                store: ConfigStore::new(MockFlash::new(256, 2)),
+                journal: Journal::new(MockFlash::new(256, 2)),
+                activity: ActivityReport::new(),
+                clock: Clock(noon()),
+            }
+        }
+        /// Runs `command` and returns what it printed.
+        fn run(&mut self, command: Command) -> String {
+            let mut out = String::new();
+            let context = CommandContext {
+                profile: &mut self.profile,
+                store: &mut self.store,
+                journal: &mut self.journal,
+                activity: &self.activity,
+                calendar: &mut self.clock,
+            };
+            command.execute(context, &mut out).unwrap();
+            out
+        }
+    }
+
+    #[test]
+    fn parses_commands() {
+        assert_eq!(Command::parse("show"), Ok(Command::Show));
+        assert_eq!(Command::parse("save"), Ok(Command::Save));
+        assert_eq!(Command::parse("journal"), Ok(Command::Journal));
+        assert_eq!(Command::parse("activity"), Ok(Command::Activity));
+        assert_eq!(
+            Command::parse("disarm").map(|c| c.reset_message()),
+            Ok(Some(AppResetMessage::FromConsole { arm: false }))
+        );
+        assert_eq!(
+            Command::parse("arm").map(|c| c.reset_message()),
+            Ok(Some(AppResetMessage::FromConsole { arm: true }))
+        );
+        assert_eq!(Command::Show.reset_message(), None);
+        assert_eq!(Command::parse("  defaults "), Ok(Command::Defaults));
+        assert_eq!(
+            Command::parse("set inactivity_timeout_ms 60000"),
+            Ok(Command::Set(Setting::InactivityTimeout(
+                Duration::from_secs(60)
+            )))
+        );
+        assert_eq!(
+            Command::parse("set accel_odr_hz 50"),
+            Ok(Command::Set(Setting::AccelOdr(AccelOdr::Hz50)))
+        );
+        assert_eq!(
+            Command::parse("set motion_source polling"),
+            Ok(Command::Set(Setting::MotionSource(MotionSource::Polling)))
+        );
+        assert_eq!(
+            Command::parse("set accel_range_g 4"),
+            Ok(Command::Set(Setting::AccelSensitivity(
+                AccelSensitivity::G2
+            )))
+        );
+    }
+
+    #[test]
+    fn parses_time_and_schedule() {
+        assert_eq!(Command::parse("time"), Ok(Command::Time(None)));
+        assert_eq!(
+            Command::parse("time 2026-10-19 12:00:00"),
+            Ok(Command::Time(Some(noon())))
+        );
+        assert_eq!(
+            Command::parse("time 2026-10-19"),
+            Err(CommandError::MissingValue)
+        );
+        assert_eq!(
+            Command::parse("time 2026-10-19 25:00:00"),
+            Err(CommandError::InvalidValue)
+        );
+        assert_eq!(
+            Command::parse("schedule 2 weekdays 22:00 06:00"),
+            Ok(Command::Set(Setting::Window {
+                slot: 1,
+                window: Some(Window {
+                    days: Days::WEEKDAYS,
+                    start: 22 * 60,
+                    end: 6 * 60,
+                }),
+            }))
+        );
+        assert_eq!(
+            Command::parse("schedule 4 off"),
+            Ok(Command::Set(Setting::Window {
+                slot: 3,
+                window: None,
+            }))
+        );
+        assert_eq!(
+            Command::parse("schedule 5 off"),
+            Err(CommandError::InvalidValue)
+        );
+        assert_eq!(
+            Command::parse("schedule 1 daily 22:00"),
+            Err(CommandError::MissingValue)
+        );
+        assert!(Command::parse("schedule 1 off").unwrap().affects_schedule());
+        assert!(!Command::Time(None).affects_schedule());
+    }
+
+    #[test]
+    fn rejects_malformed_commands() {
+        assert_eq!(Command::parse(""), Err(CommandError::Empty));
+        assert_eq!(Command::parse("reboot"), Err(CommandError::UnknownCommand));
+        assert_eq!(
+            Command::parse("set motion_threshold_mg"),
+            Err(CommandError::MissingValue)
+        );
+        assert_eq!(
+            Command::parse("set colour 3"),
+            Err(CommandError::UnknownKey)
+        );
+        assert_eq!(
+            Command::parse("set motion_threshold_mg 70000"),
+            Err(CommandError::InvalidValue)
+        );
+        assert_eq!(
+            Command::parse("set accel_odr_hz 42"),
+            Err(CommandError::InvalidValue)
+        );
+        assert_eq!(Command::parse("show all"), Err(CommandError::InvalidValue));
+    }
+
+    #[test]
+    fn executes_against_profile() {
+        let mut console = Console::new();
+        assert_eq!(
+            console.run(Command::Set(Setting::MotionThreshold(250))),
+            "ok\n"
+        );
+        assert_eq!(
+            console.run(Command::Set(Setting::MotionThreshold(0))),
+            "error: motion threshold must be non-zero\n"
+        );
+        assert_eq!(console.profile.motion_threshold, 250);
+        assert!(console
+            .run(Command::Show)
+            .contains("motion_threshold_mg 250\n"));
+        assert_eq!(console.run(Command::Save), "ok\n");
+        assert_eq!(console.run(Command::Defaults), "ok\n");
+        assert_eq!(console.profile, AlarmProfile::default());
+        assert_eq!(console.store.load_or_default().motion_threshold, 250);
+    }
+
+    #[test]
+    fn prints_journal() {
+        let mut console = Console::new();
+        console.journal.open().unwrap();
+        console
+            .journal
+            .record_boot(crate::Instant::from_millis(0), crate::StateKind::Active)
+            .unwrap();
+        assert_eq!(
+            console.run(Command::Journal),
+            "#1 0ms boot active -> active\n"
+        );
+        assert_eq!(console.run(Command::Activity), "activity still\nsteps 0\n");
+    }
+
+    #[test]
+    fn reads_and_sets_time() {
+        let mut console = Console::new();
+        let midnight = DateTime::new(2026, 10, 20, 0, 0, 0).unwrap();
+        assert_eq!(console.run(Command::Time(None)), "2026-10-19 12:00:00\n");
+        assert_eq!(console.run(Command::Time(Some(midnight))), "ok\n");
+        assert_eq!(console.clock.0, midnight);
+    }
+
+    #[test]
+    fn line_buffer_splits_lines() {
+        let mut line = LineBuffer::<16>::new();
+        for &byte in b"sho" {
+            assert_eq!(line.push(byte), None);
+        }
+        assert_eq!(line.push(b'w'), None);
+        assert_eq!(line.push(b'\r'), Some(Ok("show")));
+        assert_eq!(line.push(b'\n'), None);
+        for &byte in b"defaults" {
+            line.push(byte);
+        }
+        assert_eq!(line.push(b'\n'), Some(Ok("defaults")));
+    }
+
+    #[test]
+    fn line_buffer_reports_overflow_once() {
+        let mut line = LineBuffer::<4>::new();
+        for &byte in b"defaults" {
+            assert_eq!(line.push(byte), None);
+        }
+        assert_eq!(line.push(b'\n'), Some(Err(CommandError::LineTooLong)));
+        for &byte in b"show" {
+            line.push(byte);
+        }
+        assert_eq!(line.push(b'\n'), Some(Ok("show")));
+    }
+}
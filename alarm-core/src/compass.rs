@@ -0,0 +1,287 @@
+//! Tilt-compensated compass on the LSM303DLHC magnetometer.
+//!
+//! The accelerometer tells which way is up, so the horizontal part of the
+//! magnetic field can be found however the board is held. Iron near the
+//! sensor adds a constant field (hard iron) and distorts it differently per
+//! axis (soft iron); [`MagCalibrator`] measures both while the board is turned
+//! every which way.
+
+/// One magnetometer sample in raw counts, reordered to X, Y, Z.
+pub type RawMagSample = [i16; 3];
+
+/// Fixed-point one for [`MagCalibration::scale`].
+pub const SCALE_ONE: u16 = 1024;
+/// At ±1.3 gauss the Z axis gives 980 counts per gauss against 1100 for X and
+/// Y, so uncalibrated Z readings are stretched to match.
+const Z_GAIN_SCALE: u16 = 1149;
+/// Smallest spread per axis, in counts, that [`MagCalibrator`] accepts. The
+/// Earth's field alone spans at least 500 counts when the board is turned
+/// all the way round.
+pub const MIN_CALIBRATION_SPAN: u16 = 300;
+
+/// Hard- and soft-iron correction for the magnetometer.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct MagCalibration {
+    /// Hard-iron offset per axis, in counts.
+    pub offset: [i16; 3],
+    /// Soft-iron scale per axis, with [`SCALE_ONE`] meaning 1.
+    pub scale: [u16; 3],
+}
+
+impl MagCalibration {
+    /// Only corrects the gain difference between the axes.
+    pub const UNCALIBRATED: MagCalibration = MagCalibration {
+        offset: [0; 3],
+        scale: [SCALE_ONE, SCALE_ONE, Z_GAIN_SCALE],
+    };
+
+    /// The corrected field, in X and Y counts.
+    pub fn apply(&self, raw: RawMagSample) -> [i32; 3] {
+        let mut field = [0; 3];
+        for (axis, out) in field.iter_mut().enumerate() {
+            *out = (i32::from(raw[axis]) - i32::from(self.offset[axis]))
+                * i32::from(self.scale[axis])
+                / i32::from(SCALE_ONE);
+        }
+        field
+    }
+}
+
+impl Default for MagCalibration {
+    fn default() -> Self {
+        MagCalibration::UNCALIBRATED
+    }
+}
+
+/// Works out a [`MagCalibration`] from samples taken while the board is
+/// rotated through every orientation.
+///
+/// Without iron nearby the samples would lie on a sphere around zero. The
+/// middle of the range seen on each axis is the hard-iron offset, and scaling
+/// each axis to the mean radius undoes the soft-iron stretch.
+#[derive(Debug, Clone, Copy)]
+pub struct MagCalibrator {
+    min: [i16; 3],
+    max: [i16; 3],
+}
+
+impl MagCalibrator {
+    pub const fn new() -> Self {
+        MagCalibrator {
+            min: [i16::MAX; 3],
+            max: [i16::MIN; 3],
+        }
+    }
+    pub fn update(&mut self, raw: RawMagSample) {
+        for ((min, max), axis) in self.min.iter_mut().zip(&mut self.max).zip(raw) {
+            *min = (*min).min(axis);
+            *max = (*max).max(axis);
+        }
+    }
+    /// The calibration, or `None` if some axis has not been turned through
+    /// far enough.
+    pub fn finish(&self) -> Option<MagCalibration> {
+        let mut offset = [0; 3];
+        let mut radius = [0; 3];
+        for axis in 0..3 {
+            let (min, max) = (i32::from(self.min[axis]), i32::from(self.max[axis]));
+            if max - min < i32::from(MIN_CALIBRATION_SPAN) {
+                return None;
+            }
+            offset[axis] = ((min + max) / 2) as i16;
+            radius[axis] = (max - min) / 2;
+        }
+        let mean = radius.iter().sum::<i32>() / 3;
+        Some(MagCalibration {
+            offset,
+            scale: radius.map(|r| (mean * i32::from(SCALE_ONE) / r) as u16),
+        })
+    }
+}
+
+impl Default for MagCalibrator {
+    fn default() -> Self {
+        MagCalibrator::new()
+    }
+}
+
+/// The eight directions of the LED ring.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum CompassPoint {
+    North,
+    NorthEast,
+    East,
+    SouthEast,
+    South,
+    SouthWest,
+    West,
+    NorthWest,
+}
+
+impl CompassPoint {
+    /// Clockwise from north.
+    pub const ALL: [CompassPoint; 8] = [
+        CompassPoint::North,
+        CompassPoint::NorthEast,
+        CompassPoint::East,
+        CompassPoint::SouthEast,
+        CompassPoint::South,
+        CompassPoint::SouthWest,
+        CompassPoint::West,
+        CompassPoint::NorthWest,
+    ];
+
+    /// The point nearest to `degrees` clockwise from north.
+    pub fn from_bearing(degrees: u16) -> CompassPoint {
+        let index = (u32::from(degrees % 360) * 2 + 45) / 90;
+        CompassPoint::ALL[index as usize % 8]
+    }
+}
+
+/// Where magnetic north lies, in degrees clockwise from the board's north LED,
+/// given the acceleration in milli-g and the calibrated field.
+///
+/// The field is projected onto the horizontal plane that the acceleration
+/// defines, so this holds while the board is tilted as long as it is not
+/// being shaken. On the Discovery board the sensor's −X axis points at the
+/// north LED (LD3) and +Y at the east LED (LD7). Returns `None` when north is
+/// straight through the board, or without readings.
+pub fn north_bearing(accel_mg: [i32; 3], field: [i32; 3]) -> Option<u16> {
+    let a = accel_mg.map(i64::from);
+    let m = field.map(i64::from);
+    let dot = |u: [i64; 3], v: [i64; 3]| u[0] * v[0] + u[1] * v[1] + u[2] * v[2];
+    let (aa, am) = (dot(a, a), dot(a, m));
+    if aa == 0 {
+        return None;
+    }
+    // The horizontal part of the field, scaled by |a|² to stay in integers.
+    let mut north = [0; 3];
+    for (axis, out) in north.iter_mut().enumerate() {
+        *out = m[axis] * aa - a[axis] * am;
+    }
+    let (forward, right) = (-north[0], north[1]);
+    if forward == 0 && right == 0 {
+        return None;
+    }
+    Some(atan2_degrees(right, forward))
+}
+
+/// The angle of (`x`, `y`) counter-clockwise from the X axis, in whole degrees
+/// from 0 to 359.
+pub(crate) fn atan2_degrees(y: i64, x: i64) -> u16 {
+    let (ax, ay) = (x.unsigned_abs(), y.unsigned_abs());
+    let (small, large) = if ay <= ax { (ay, ax) } else { (ax, ay) };
+    if large == 0 {
+        return 0;
+    }
+    // atan(r) ≈ 45r + 15.64r(1 - r) degrees on [0, 1], within 0.3°. Work in
+    // hundredths of a degree with r in 16-bit fixed point.
+    let r = (u128::from(small) << 16) / u128::from(large);
+    let r = r as u64;
+    let mut angle = (4500 * r + 1564 * r * ((1 << 16) - r) / (1 << 16)) >> 16;
+    if ay > ax {
+        angle = 9000 - angle;
+    }
+    if x < 0 {
+        angle = 18000 - angle;
+    }
+    if y < 0 {
+        angle = 36000 - angle;
+    }
+    ((angle + 50) / 100 % 360) as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The field in the northern hemisphere with the north LED facing north
+    /// and the board flat: pointing forward (−X) and down.
+    const FIELD: [i32; 3] = [-300, 0, -400];
+    const FLAT: [i32; 3] = [0, 0, 1000];
+
+    #[test]
+    fn angles_from_components() {
+        assert_eq!(atan2_degrees(0, 1), 0);
+        assert_eq!(atan2_degrees(1, 1), 45);
+        assert_eq!(atan2_degrees(1, 0), 90);
+        assert_eq!(atan2_degrees(0, -5), 180);
+        assert_eq!(atan2_degrees(-3, 0), 270);
+        assert_eq!(atan2_degrees(3, 4), 37);
+        assert_eq!(atan2_degrees(-4, 3), 307);
+        assert_eq!(atan2_degrees(0, 0), 0);
+    }
+
+    #[test]
+    fn flat_board_points_at_north() {
+        assert_eq!(north_bearing(FLAT, FIELD), Some(0));
+        // Turned a quarter clockwise, north is to the left.
+        assert_eq!(north_bearing(FLAT, [0, -300, -400]), Some(270));
+        assert_eq!(north_bearing([0; 3], FIELD), None);
+    }
+
+    #[test]
+    fn compensates_for_tilt() {
+        // Rolled 30° about X: rotate gravity and the field together.
+        let roll = |v: [i32; 3]| {
+            [
+                v[0],
+                (v[1] * 866 - v[2] * 500) / 1000,
+                (v[1] * 500 + v[2] * 866) / 1000,
+            ]
+        };
+        let (accel, field) = (roll(FLAT), roll(FIELD));
+        // The raw X/Y of the field would light the north-east LED.
+        let uncompensated = atan2_degrees(i64::from(field[1]), -i64::from(field[0]));
+        assert_eq!(
+            CompassPoint::from_bearing(uncompensated),
+            CompassPoint::NorthEast
+        );
+        assert_eq!(north_bearing(accel, field), Some(0));
+    }
+
+    #[test]
+    fn bearings_round_to_the_nearest_point() {
+        assert_eq!(CompassPoint::from_bearing(0), CompassPoint::North);
+        assert_eq!(CompassPoint::from_bearing(22), CompassPoint::North);
+        assert_eq!(CompassPoint::from_bearing(23), CompassPoint::NorthEast);
+        assert_eq!(CompassPoint::from_bearing(270), CompassPoint::West);
+        assert_eq!(CompassPoint::from_bearing(350), CompassPoint::North);
+    }
+
+    #[test]
+    fn calibration_removes_hard_and_soft_iron() {
+        let offset = [120, -80, 40];
+        // X reads 20% strong, Z 10% weak.
+        let distort = |field: [i32; 3]| {
+            [
+                (offset[0] + field[0] * 12 / 10) as i16,
+                (offset[1] + field[1]) as i16,
+                (offset[2] + field[2] * 9 / 10) as i16,
+            ]
+        };
+        let mut calibrator = MagCalibrator::new();
+        calibrator.update(distort([0, 0, 0]));
+        assert_eq!(calibrator.finish(), None);
+        for field in [
+            [500, 0, 0],
+            [-500, 0, 0],
+            [0, 500, 0],
+            [0, -500, 0],
+            [0, 0, 500],
+            [0, 0, -500],
+        ] {
+            calibrator.update(distort(field));
+        }
+        let calibration = calibrator.finish().unwrap();
+        assert_eq!(calibration.offset, offset.map(|o| o as i16));
+        // Every axis comes out at the mean of the distorted radii, 516.
+        for field in [[500, 0, 0], [0, -500, 0], [0, 0, 500]] {
+            let corrected = calibration.apply(distort(field));
+            for axis in 0..3 {
+                let expected = field[axis] * 516 / 500;
+                assert!((corrected[axis] - expected).abs() <= 2, "{:?}", corrected);
+            }
+        }
+    }
+}
@@ -0,0 +1,315 @@
+//! Fall detection on the accelerometer samples.
+//!
+//! A fall shows up as three phases in the magnitude of the acceleration: a
+//! drop well below 1 g while the wearer is falling, a spike when they hit the
+//! ground, and then a stretch where they lie still at 1 g. Only all three in
+//! order count, so jumping, sitting down hard or dropping onto a bed does not
+//! raise the alarm, and someone who gets back up is left alone.
+
+use core::time::Duration;
+
+use crate::{
+    app_state::AppResetMessage,
+    motion::{self, RawSample},
+    profile::{AccelSensitivity, AlarmProfile},
+};
+
+/// How long the magnitude has to stay below `free_fall_threshold` to count as
+/// falling. Tripping over drops the wearer for longer than this; a stumble
+/// that is caught does not.
+pub const MIN_FREE_FALL: Duration = Duration::from_millis(80);
+/// How soon after the free fall the impact has to come.
+pub const IMPACT_WINDOW: Duration = Duration::from_secs(1);
+/// How long after the impact bouncing and rolling may go on before the
+/// stillness has to begin.
+pub const SETTLE_TIME: Duration = Duration::from_secs(2);
+/// How far from 1 g the magnitude may stray while lying still.
+pub const STILLNESS_TOLERANCE_MG: u16 = 200;
+pub(crate) const ONE_G_MG: u16 = 1000;
+
+/// A fall followed by stillness.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct FallEvent {
+    /// Peak magnitude of the impact, in milli-g.
+    pub impact_mg: u16,
+}
+
+impl From<FallEvent> for AppResetMessage {
+    fn from(event: FallEvent) -> AppResetMessage {
+        AppResetMessage::FromFall {
+            magnitude: event.impact_mg,
+        }
+    }
+}
+
+/// Where the detector is in a possible fall. Durations are counted in
+/// samples.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Phase {
+    /// Waiting for a free fall, which has lasted `falling` samples so far.
+    Idle { falling: usize },
+    /// The free fall ended `since` samples ago; waiting for the impact.
+    Falling { since: usize },
+    /// The impact happened `since` samples ago and the wearer has been still
+    /// for the last `still` of them.
+    Landed {
+        impact_mg: u16,
+        since: usize,
+        still: usize,
+    },
+}
+
+/// Turns raw samples into [`FallEvent`]s.
+///
+/// Feed it every sample, taken every `sample_period` of the profile. Nothing
+/// is reported while `impact_threshold` is zero.
+#[derive(Debug, Clone)]
+pub struct FallDetector {
+    sensitivity: AccelSensitivity,
+    free_fall_mg: u16,
+    impact_mg: u16,
+    min_free_fall: usize,
+    impact_window: usize,
+    settle_time: usize,
+    stillness: usize,
+    phase: Phase,
+}
+
+impl FallDetector {
+    pub fn new(profile: &AlarmProfile) -> Self {
+        let mut detector = FallDetector {
+            sensitivity: profile.accel_sensitivity,
+            free_fall_mg: profile.free_fall_threshold,
+            impact_mg: profile.impact_threshold,
+            min_free_fall: 1,
+            impact_window: 1,
+            settle_time: 1,
+            stillness: 1,
+            phase: Phase::Idle { falling: 0 },
+        };
+        detector.configure(profile);
+        detector
+    }
+    /// Picks up a changed profile, forgetting any fall in progress.
+    pub fn configure(&mut self, profile: &AlarmProfile) {
+        let samples = |duration: Duration| {
+            let period = profile.sample_period.as_millis().max(1);
+            (duration.as_millis().div_ceil(period) as usize).max(1)
+        };
+        self.sensitivity = profile.accel_sensitivity;
+        self.free_fall_mg = profile.free_fall_threshold;
+        self.impact_mg = profile.impact_threshold;
+        self.min_free_fall = samples(MIN_FREE_FALL);
+        self.impact_window = samples(IMPACT_WINDOW);
+        self.settle_time = samples(SETTLE_TIME);
+        self.stillness = samples(profile.fall_stillness);
+        self.phase = Phase::Idle { falling: 0 };
+    }
+    pub fn update(&mut self, raw: RawSample) -> Option<FallEvent> {
+        if self.impact_mg == 0 {
+            return None;
+        }
+        let magnitude = motion::magnitude(motion::to_milli_g(raw, self.sensitivity));
+        let (phase, event) = self.next(self.phase, magnitude);
+        self.phase = phase;
+        event
+    }
+    fn next(&self, phase: Phase, magnitude: u16) -> (Phase, Option<FallEvent>) {
+        let idle = Phase::Idle {
+            falling: usize::from(magnitude < self.free_fall_mg),
+        };
+        match phase {
+            Phase::Idle { falling } if magnitude < self.free_fall_mg => (
+                Phase::Idle {
+                    falling: falling + 1,
+                },
+                None,
+            ),
+            // The impact can come in the very sample that ends the free fall.
+            Phase::Idle { falling } if falling >= self.min_free_fall => {
+                self.next(Phase::Falling { since: 0 }, magnitude)
+            }
+            Phase::Idle { .. } => (idle, None),
+            Phase::Falling { .. } if magnitude >= self.impact_mg => (
+                Phase::Landed {
+                    impact_mg: magnitude,
+                    since: 0,
+                    still: 0,
+                },
+                None,
+            ),
+            Phase::Falling { since } if since + 1 < self.impact_window => {
+                (Phase::Falling { since: since + 1 }, None)
+            }
+            Phase::Falling { .. } => (idle, None),
+            Phase::Landed {
+                impact_mg,
+                since,
+                still,
+            } => {
+                let impact_mg = impact_mg.max(magnitude);
+                let still = if magnitude.abs_diff(ONE_G_MG) <= STILLNESS_TOLERANCE_MG {
+                    still + 1
+                } else {
+                    0
+                };
+                if still >= self.stillness {
+                    (Phase::Idle { falling: 0 }, Some(FallEvent { impact_mg }))
+                } else if since + 1 - still >= self.settle_time {
+                    // Still moving once the settle time is up: they got up.
+                    (idle, None)
+                } else {
+                    (
+                        Phase::Landed {
+                            impact_mg,
+                            since: since + 1,
+                            still,
+                        },
+                        None,
+                    )
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use std::vec::Vec;
+
+    use super::*;
+
+    const PERIOD_MS: u64 = 20;
+
+    fn profile() -> AlarmProfile {
+        AlarmProfile {
+            accel_sensitivity: AccelSensitivity::G12,
+            sample_period: Duration::from_millis(PERIOD_MS),
+            impact_threshold: 2_500,
+            ..AlarmProfile::default()
+        }
+    }
+
+    /// A synthetic trace, built up from stretches of constant acceleration
+    /// in milli-g.
+    struct Trace(Vec<RawSample>);
+
+    impl Trace {
+        fn new() -> Self {
+            Trace(Vec::new())
+        }
+        fn hold(mut self, mg: [i32; 3], ms: u64) -> Self {
+            // 0.75 mg per count at ±16 g.
+            let raw = mg.map(|axis| (axis * 16 / 12) as i16);
+            self.0.extend((0..ms / PERIOD_MS).map(|_| raw));
+            self
+        }
+        /// Moving about at around 1 g, as when walking.
+        fn moving(mut self, ms: u64) -> Self {
+            for i in 0..ms / PERIOD_MS {
+                let z = if i % 10 < 5 { 1400 } else { 600 };
+                self = self.hold([300, 0, z], PERIOD_MS);
+            }
+            self
+        }
+        fn standing(self, ms: u64) -> Self {
+            self.hold([0, 0, 1000], ms)
+        }
+        fn falling(self, ms: u64) -> Self {
+            self.hold([80, 40, 150], ms)
+        }
+        fn impact(self, mg: i32) -> Self {
+            self.hold([mg, 0, 0], 2 * PERIOD_MS)
+        }
+        fn lying(self, ms: u64) -> Self {
+            self.hold([1000, 0, 0], ms)
+        }
+        /// Indices of the samples that reported a fall, with the event.
+        fn detect(&self, profile: &AlarmProfile) -> Vec<(usize, FallEvent)> {
+            let mut detector = FallDetector::new(profile);
+            self.0
+                .iter()
+                .enumerate()
+                .filter_map(|(i, &raw)| detector.update(raw).map(|event| (i, event)))
+                .collect()
+        }
+    }
+
+    #[test]
+    fn fall_then_stillness_raises_once() {
+        let trace = Trace::new()
+            .standing(1_000)
+            .falling(300)
+            .impact(4_000)
+            .moving(400)
+            .lying(5_000);
+        let events = trace.detect(&profile());
+        assert_eq!(events.len(), 1);
+        let (at, event) = events[0];
+        assert!(event.impact_mg >= 3_990, "{:?}", event);
+        // Reported once the default stillness has passed after landing.
+        let landed = (1_000 + 300 + 2 * PERIOD_MS + 400) / PERIOD_MS;
+        let stillness = profile().fall_stillness.as_millis() as u64 / PERIOD_MS;
+        assert_eq!(at as u64, landed + stillness - 1);
+    }
+
+    #[test]
+    fn getting_up_after_a_fall_is_ignored() {
+        let trace = Trace::new()
+            .standing(1_000)
+            .falling(300)
+            .impact(4_000)
+            .lying(500)
+            .moving(5_000)
+            .standing(5_000);
+        assert_eq!(trace.detect(&profile()), []);
+    }
+
+    #[test]
+    fn impact_without_free_fall_is_ignored() {
+        // Stamping or knocking the device against a wall, then putting it
+        // down.
+        let trace = Trace::new().standing(1_000).impact(6_000).lying(5_000);
+        assert_eq!(trace.detect(&profile()), []);
+    }
+
+    #[test]
+    fn short_drop_is_ignored() {
+        let trace = Trace::new()
+            .standing(1_000)
+            .falling(40)
+            .impact(4_000)
+            .lying(5_000);
+        assert_eq!(trace.detect(&profile()), []);
+    }
+
+    #[test]
+    fn soft_landing_is_ignored() {
+        let trace = Trace::new()
+            .standing(1_000)
+            .falling(300)
+            .impact(1_800)
+            .lying(5_000);
+        assert_eq!(trace.detect(&profile()), []);
+        let sensitive = AlarmProfile {
+            impact_threshold: 1_500,
+            ..profile()
+        };
+        assert_eq!(trace.detect(&sensitive).len(), 1);
+    }
+
+    #[test]
+    fn zero_impact_threshold_turns_detection_off() {
+        let trace = Trace::new()
+            .standing(1_000)
+            .falling(300)
+            .impact(4_000)
+            .lying(5_000);
+        let off = AlarmProfile {
+            impact_threshold: 0,
+            ..profile()
+        };
+        assert_eq!(trace.detect(&off), []);
+    }
+}
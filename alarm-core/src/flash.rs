@@ -0,0 +1,146 @@
+/// A reserved region of NOR flash, addressed from its first byte.
+///
+/// Erased bytes read as `0xFF` and programming can only clear bits, so a
+/// page has to be erased before it is written again.
+pub trait FlashRegion {
+    type Error;
+
+    /// Size of one erasable page in bytes.
+    fn page_size(&self) -> usize;
+    fn page_count(&self) -> usize;
+    fn read(&mut self, offset: usize, buf: &mut [u8]) -> Result<(), Self::Error>;
+    /// Programs erased bytes. `offset` and `data.len()` are multiples of the
+    /// program width (two bytes on the STM32F3).
+    fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), Self::Error>;
+    fn erase_page(&mut self, page: usize) -> Result<(), Self::Error>;
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum SliceFlashError {
+    OutOfBounds,
+    ReadOnly,
+}
+
+/// A read-only view of a flash dump, so host tools can decode the records in
+/// it with the same code as the firmware.
+pub struct SliceFlash<'a> {
+    data: &'a [u8],
+    page_size: usize,
+}
+
+impl<'a> SliceFlash<'a> {
+    pub fn new(data: &'a [u8], page_size: usize) -> Self {
+        SliceFlash { data, page_size }
+    }
+}
+
+impl FlashRegion for SliceFlash<'_> {
+    type Error = SliceFlashError;
+
+    fn page_size(&self) -> usize {
+        self.page_size
+    }
+    fn page_count(&self) -> usize {
+        self.data.len() / self.page_size
+    }
+    fn read(&mut self, offset: usize, buf: &mut [u8]) -> Result<(), Self::Error> {
+        let end = offset
+            .checked_add(buf.len())
+            .filter(|&end| end <= self.data.len())
+            .ok_or(SliceFlashError::OutOfBounds)?;
+        buf.copy_from_slice(&self.data[offset..end]);
+        Ok(())
+    }
+    fn write(&mut self, _offset: usize, _data: &[u8]) -> Result<(), Self::Error> {
+        Err(SliceFlashError::ReadOnly)
+    }
+    fn erase_page(&mut self, _page: usize) -> Result<(), Self::Error> {
+        Err(SliceFlashError::ReadOnly)
+    }
+}
+
+#[cfg(test)]
+pub mod mock {
+    extern crate std;
+    use std::{vec, vec::Vec};
+
+    use super::FlashRegion;
+
+    #[derive(Debug, PartialEq, Eq)]
+    pub enum MockFlashError {
+        OutOfBounds,
+        Unaligned,
+        NotErased,
+        PowerLoss,
+    }
+
+    /// RAM-backed flash that enforces NOR semantics and can simulate power
+    /// loss after a given number of programmed bytes.
+    pub struct MockFlash {
+        pub data: Vec<u8>,
+        pub page_size: usize,
+        pub erase_counts: Vec<u32>,
+        pub bytes_until_power_loss: Option<usize>,
+    }
+
+    impl MockFlash {
+        pub fn new(page_size: usize, page_count: usize) -> MockFlash {
+            MockFlash {
+                data: vec![0xFF; page_size * page_count],
+                page_size,
+                erase_counts: vec![0; page_count],
+                bytes_until_power_loss: None,
+            }
+        }
+    }
+
+    impl FlashRegion for MockFlash {
+        type Error = MockFlashError;
+
+        fn page_size(&self) -> usize {
+            self.page_size
+        }
+        fn page_count(&self) -> usize {
+            self.erase_counts.len()
+        }
+        fn read(&mut self, offset: usize, buf: &mut [u8]) -> Result<(), Self::Error> {
+            let src = self
+                .data
+                .get(offset..offset + buf.len())
+                .ok_or(MockFlashError::OutOfBounds)?;
+            buf.copy_from_slice(src);
+            Ok(())
+        }
+        fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), Self::Error> {
+            if !offset.is_multiple_of(2) || !data.len().is_multiple_of(2) {
+                return Err(MockFlashError::Unaligned);
+            }
+            let dst = self
+                .data
+                .get_mut(offset..offset + data.len())
+                .ok_or(MockFlashError::OutOfBounds)?;
+            for (dst, &src) in dst.iter_mut().zip(data) {
+                if let Some(left) = self.bytes_until_power_loss.as_mut() {
+                    if *left == 0 {
+                        return Err(MockFlashError::PowerLoss);
+                    }
+                    *left -= 1;
+                }
+                if *dst != 0xFF {
+                    return Err(MockFlashError::NotErased);
+                }
+                *dst = src;
+            }
+            Ok(())
+        }
+        fn erase_page(&mut self, page: usize) -> Result<(), Self::Error> {
+            let start = page * self.page_size;
+            self.data
+                .get_mut(start..start + self.page_size)
+                .ok_or(MockFlashError::OutOfBounds)?
+                .fill(0xFF);
+            self.erase_counts[page] += 1;
+            Ok(())
+        }
+    }
+}
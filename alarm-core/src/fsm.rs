@@ -0,0 +1,378 @@
+//! The alarm logic as a single transition table.
+//!
+//! [`AppState`](crate::AppState) looks up every event here, so this table is
+//! the one place that decides what the alarm does. [`check`] verifies that it
+//! is complete and [`write_dot`] draws it; run
+//! `cargo run --example fsm_dot | dot -Tsvg > fsm.svg` to see it.
+
+use core::fmt;
+
+use crate::{
+    app_state::{AppResetMessage, StateKind},
+    button::Gesture,
+};
+
+use Action::{Escalate, Restart};
+use Event::{
+    Arm, Disarm, DoublePress, Fall, LongPress, Motion, SensorOk, ShortPress, Timeout, TipOver,
+    WindowClose, WindowOpen,
+};
+use StateKind::{Active, Alarm, Disarmed, Emergency, PreAlarm, SensorFault};
+
+/// Everything the state machine reacts to.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Event {
+    /// The deadline of the current state passed.
+    Timeout,
+    ShortPress,
+    LongPress,
+    DoublePress,
+    Motion,
+    /// A fall followed by stillness.
+    Fall,
+    /// The device stayed tilted away from its orientation when armed.
+    TipOver,
+    Arm,
+    Disarm,
+    /// A monitoring window of the schedule opened.
+    WindowOpen,
+    /// A monitoring window of the schedule closed.
+    WindowClose,
+    /// The accelerometer stopped answering.
+    SensorFault,
+    /// The accelerometer answered again.
+    SensorOk,
+}
+
+impl Event {
+    pub const ALL: [Event; 13] = [
+        Event::Timeout,
+        Event::ShortPress,
+        Event::LongPress,
+        Event::DoublePress,
+        Event::Motion,
+        Event::Fall,
+        Event::TipOver,
+        Event::Arm,
+        Event::Disarm,
+        Event::WindowOpen,
+        Event::WindowClose,
+        Event::SensorFault,
+        Event::SensorOk,
+    ];
+}
+
+impl From<AppResetMessage> for Event {
+    fn from(message: AppResetMessage) -> Event {
+        match message {
+            AppResetMessage::FromButton {
+                gesture: Gesture::Short,
+            } => Event::ShortPress,
+            AppResetMessage::FromButton {
+                gesture: Gesture::Long,
+            } => Event::LongPress,
+            AppResetMessage::FromButton {
+                gesture: Gesture::Double,
+            } => Event::DoublePress,
+            AppResetMessage::FromAccelerometer { .. } | AppResetMessage::FromGyroscope { .. } => {
+                Event::Motion
+            }
+            AppResetMessage::FromFall { .. } => Event::Fall,
+            AppResetMessage::FromTipOver { .. } => Event::TipOver,
+            AppResetMessage::FromConsole { arm: true } => Event::Arm,
+            AppResetMessage::FromConsole { arm: false } => Event::Disarm,
+            AppResetMessage::FromSchedule { arm: true } => Event::WindowOpen,
+            AppResetMessage::FromSchedule { arm: false } => Event::WindowClose,
+            AppResetMessage::FromSensor { healthy: false } => Event::SensorFault,
+            AppResetMessage::FromSensor { healthy: true } => Event::SensorOk,
+        }
+    }
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Event::Timeout => "timeout",
+            Event::ShortPress => "short press",
+            Event::LongPress => "long press",
+            Event::DoublePress => "double press",
+            Event::Motion => "motion",
+            Event::Fall => "fall",
+            Event::TipOver => "tip-over",
+            Event::Arm => "arm",
+            Event::Disarm => "disarm",
+            Event::WindowOpen => "window opens",
+            Event::WindowClose => "window closes",
+            Event::SensorFault => "sensor fault",
+            Event::SensorOk => "sensor ok",
+        })
+    }
+}
+
+/// What happens to the deadline when a rule fires.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Action {
+    /// The event does not apply in this state and is rejected.
+    Ignore,
+    /// Enter the next state with its deadline counted from the one that
+    /// expired, so a late caller does not stretch the overall timeout.
+    Escalate,
+    /// Enter the next state with its deadline counted from now.
+    Restart,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Rule {
+    pub state: StateKind,
+    pub event: Event,
+    pub next: StateKind,
+    pub action: Action,
+}
+
+const fn go(state: StateKind, event: Event, next: StateKind, action: Action) -> Rule {
+    Rule {
+        state,
+        event,
+        next,
+        action,
+    }
+}
+
+const fn ignore(state: StateKind, event: Event) -> Rule {
+    go(state, event, state, Action::Ignore)
+}
+
+/// Every (state, event) pair, each exactly once.
+///
+/// A long press only disarms while nothing has been raised yet, so an alarm
+/// has to be acknowledged before it can be silenced. The console, used by
+/// someone servicing the device, can disarm from anywhere. The schedule
+/// leaves a raised alarm alone when its window closes; once acknowledged, the
+/// alarm keeps monitoring until the next window closes. A fall skips the
+/// countdown and raises the alarm straight away, while tipping over only cuts
+/// the inactivity timeout short. A sensor fault stops the countdown, since
+/// stillness means nothing without the accelerometer, but leaves a raised
+/// alarm alone; the fault is reported again once it is acknowledged.
+pub const TRANSITIONS: &[Rule] = &[
+    go(Active, Timeout, PreAlarm, Escalate),
+    ignore(Active, ShortPress),
+    go(Active, LongPress, Disarmed, Restart),
+    ignore(Active, DoublePress),
+    ignore(Active, Motion),
+    go(Active, Fall, Alarm, Restart),
+    go(Active, TipOver, PreAlarm, Restart),
+    ignore(Active, Arm),
+    go(Active, Disarm, Disarmed, Restart),
+    ignore(Active, WindowOpen),
+    go(Active, WindowClose, Disarmed, Restart),
+    go(Active, Event::SensorFault, SensorFault, Restart),
+    ignore(Active, SensorOk),
+    go(PreAlarm, Timeout, Alarm, Escalate),
+    ignore(PreAlarm, ShortPress),
+    go(PreAlarm, LongPress, Disarmed, Restart),
+    ignore(PreAlarm, DoublePress),
+    go(PreAlarm, Motion, Active, Restart),
+    go(PreAlarm, Fall, Alarm, Restart),
+    ignore(PreAlarm, TipOver),
+    ignore(PreAlarm, Arm),
+    go(PreAlarm, Disarm, Disarmed, Restart),
+    ignore(PreAlarm, WindowOpen),
+    go(PreAlarm, WindowClose, Disarmed, Restart),
+    go(PreAlarm, Event::SensorFault, SensorFault, Restart),
+    ignore(PreAlarm, SensorOk),
+    go(Alarm, Timeout, Emergency, Escalate),
+    go(Alarm, ShortPress, Active, Restart),
+    ignore(Alarm, LongPress),
+    ignore(Alarm, DoublePress),
+    ignore(Alarm, Motion),
+    ignore(Alarm, Fall),
+    ignore(Alarm, TipOver),
+    ignore(Alarm, Arm),
+    go(Alarm, Disarm, Disarmed, Restart),
+    ignore(Alarm, WindowOpen),
+    ignore(Alarm, WindowClose),
+    ignore(Alarm, Event::SensorFault),
+    ignore(Alarm, SensorOk),
+    ignore(Emergency, Timeout),
+    go(Emergency, ShortPress, Active, Restart),
+    ignore(Emergency, LongPress),
+    ignore(Emergency, DoublePress),
+    ignore(Emergency, Motion),
+    ignore(Emergency, Fall),
+    ignore(Emergency, TipOver),
+    ignore(Emergency, Arm),
+    go(Emergency, Disarm, Disarmed, Restart),
+    ignore(Emergency, WindowOpen),
+    ignore(Emergency, WindowClose),
+    ignore(Emergency, Event::SensorFault),
+    ignore(Emergency, SensorOk),
+    ignore(Disarmed, Timeout),
+    ignore(Disarmed, ShortPress),
+    go(Disarmed, LongPress, Active, Restart),
+    ignore(Disarmed, DoublePress),
+    ignore(Disarmed, Motion),
+    ignore(Disarmed, Fall),
+    ignore(Disarmed, TipOver),
+    go(Disarmed, Arm, Active, Restart),
+    ignore(Disarmed, Disarm),
+    go(Disarmed, WindowOpen, Active, Restart),
+    ignore(Disarmed, WindowClose),
+    ignore(Disarmed, Event::SensorFault),
+    ignore(Disarmed, SensorOk),
+    ignore(SensorFault, Timeout),
+    ignore(SensorFault, ShortPress),
+    go(SensorFault, LongPress, Disarmed, Restart),
+    ignore(SensorFault, DoublePress),
+    ignore(SensorFault, Motion),
+    go(SensorFault, Fall, Alarm, Restart),
+    ignore(SensorFault, TipOver),
+    ignore(SensorFault, Arm),
+    go(SensorFault, Disarm, Disarmed, Restart),
+    ignore(SensorFault, WindowOpen),
+    go(SensorFault, WindowClose, Disarmed, Restart),
+    ignore(SensorFault, Event::SensorFault),
+    go(SensorFault, SensorOk, Active, Restart),
+];
+
+/// The state every boot starts in.
+pub const INITIAL: StateKind = Active;
+
+/// The rule for `event` in `state`. Events missing from the table are
+/// ignored, though [`check`] makes sure there are none.
+pub fn lookup(state: StateKind, event: Event) -> Rule {
+    TRANSITIONS
+        .iter()
+        .copied()
+        .find(|rule| rule.state == state && rule.event == event)
+        .unwrap_or(ignore(state, event))
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum TableError {
+    Unhandled(StateKind, Event),
+    Duplicate(StateKind, Event),
+    Unreachable(StateKind),
+}
+
+impl fmt::Display for TableError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TableError::Unhandled(state, event) => write!(f, "{} does not handle {}", state, event),
+            TableError::Duplicate(state, event) => {
+                write!(f, "{} handles {} more than once", state, event)
+            }
+            TableError::Unreachable(state) => write!(f, "{} is unreachable", state),
+        }
+    }
+}
+
+/// Checks that `rules` handles every event in every state exactly once and
+/// that every state can be reached from [`INITIAL`].
+pub fn check(rules: &[Rule]) -> Result<(), TableError> {
+    for state in StateKind::ALL {
+        for event in Event::ALL {
+            match rules
+                .iter()
+                .filter(|rule| rule.state == state && rule.event == event)
+                .count()
+            {
+                0 => return Err(TableError::Unhandled(state, event)),
+                1 => {}
+                _ => return Err(TableError::Duplicate(state, event)),
+            }
+        }
+    }
+    let index = |state| StateKind::ALL.iter().position(|&s| s == state).unwrap();
+    let mut reached = [false; StateKind::ALL.len()];
+    reached[index(INITIAL)] = true;
+    // Relax until nothing changes; the table is small enough for this.
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for rule in rules.iter().filter(|rule| rule.action != Action::Ignore) {
+            if reached[index(rule.state)] && !reached[index(rule.next)] {
+                reached[index(rule.next)] = true;
+                changed = true;
+            }
+        }
+    }
+    match StateKind::ALL
+        .into_iter()
+        .find(|&state| !reached[index(state)])
+    {
+        Some(state) => Err(TableError::Unreachable(state)),
+        None => Ok(()),
+    }
+}
+
+/// Writes `rules` as a Graphviz DOT digraph. Ignored events are left out.
+pub fn write_dot(rules: &[Rule], out: &mut impl fmt::Write) -> fmt::Result {
+    writeln!(out, "digraph alarm {{")?;
+    writeln!(out, "    rankdir=LR;")?;
+    writeln!(out, "    \"{}\" [shape=doublecircle];", INITIAL)?;
+    for rule in rules.iter().filter(|rule| rule.action != Action::Ignore) {
+        let style = match rule.action {
+            Action::Restart => " style=dashed",
+            _ => "",
+        };
+        writeln!(
+            out,
+            "    \"{}\" -> \"{}\" [label=\"{}\"{}];",
+            rule.state, rule.next, rule.event, style
+        )?;
+    }
+    writeln!(out, "}}")
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use std::{string::String, vec::Vec};
+
+    use super::*;
+
+    #[test]
+    fn table_is_complete_and_reachable() {
+        assert_eq!(check(TRANSITIONS), Ok(()));
+    }
+
+    #[test]
+    fn flags_unhandled_events() {
+        let rules: Vec<_> = TRANSITIONS
+            .iter()
+            .copied()
+            .filter(|rule| (rule.state, rule.event) != (Alarm, LongPress))
+            .collect();
+        assert_eq!(check(&rules), Err(TableError::Unhandled(Alarm, LongPress)));
+    }
+
+    #[test]
+    fn flags_duplicate_rules() {
+        let mut rules = TRANSITIONS.to_vec();
+        rules.push(ignore(Active, Timeout));
+        assert_eq!(check(&rules), Err(TableError::Duplicate(Active, Timeout)));
+    }
+
+    #[test]
+    fn flags_unreachable_states() {
+        let rules: Vec<_> = TRANSITIONS
+            .iter()
+            .map(|&rule| match (rule.state, rule.event) {
+                (Alarm, Timeout) => ignore(Alarm, Timeout),
+                _ => rule,
+            })
+            .collect();
+        assert_eq!(check(&rules), Err(TableError::Unreachable(Emergency)));
+    }
+
+    #[test]
+    fn dot_lists_live_transitions() {
+        let mut dot = String::new();
+        write_dot(TRANSITIONS, &mut dot).unwrap();
+        assert!(dot.starts_with("digraph alarm {\n"));
+        assert!(dot.contains("\"active\" -> \"pre-alarm\" [label=\"timeout\"];\n"));
+        assert!(dot.contains("\"alarm\" -> \"active\" [label=\"short press\" style=dashed];\n"));
+        assert!(!dot.contains("double press"));
+        assert!(dot.ends_with("}\n"));
+    }
+}
@@ -0,0 +1,128 @@
+//! Keeps track of whether the accelerometer still answers.
+//!
+//! A failed read is simply retried with the next sample. A few failures in a
+//! row call for the bus to be recovered and the sensor set up again, and when
+//! that does not help either the sensor is reported as faulty, so that a dead
+//! sensor is not taken for someone lying still.
+
+use crate::app_state::AppResetMessage;
+
+/// Failures in a row before the bus is recovered.
+pub const RECOVER_AFTER: u16 = 3;
+/// Recoveries in a row that may fail before the sensor counts as faulty.
+pub const MAX_RECOVERIES: u16 = 3;
+
+/// What to do about a failed access.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum HealthAction {
+    /// Try again with the next sample.
+    Retry,
+    /// Recover the bus and set the sensor up again.
+    Recover,
+    /// As `Recover`, and report `AppResetMessage::FromSensor { healthy:
+    /// false }`. Repeats while the sensor stays faulty, so a fault that
+    /// arrived during an alarm is not lost.
+    Fault,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SensorHealth {
+    /// Failures since the last success.
+    consecutive: u16,
+    /// Failures since boot.
+    errors: u32,
+    faulty: bool,
+}
+
+impl SensorHealth {
+    pub const fn new() -> Self {
+        SensorHealth {
+            consecutive: 0,
+            errors: 0,
+            faulty: false,
+        }
+    }
+    /// A sensor that could not be set up at boot. The alarm starts in
+    /// `SensorFault`, and the first access that works clears it.
+    pub const fn faulty() -> Self {
+        SensorHealth {
+            consecutive: 0,
+            errors: 1,
+            faulty: true,
+        }
+    }
+    /// Records an access that worked. Returns the message clearing the fault
+    /// if there was one.
+    pub fn success(&mut self) -> Option<AppResetMessage> {
+        self.consecutive = 0;
+        core::mem::take(&mut self.faulty).then_some(AppResetMessage::FromSensor { healthy: true })
+    }
+    pub fn failure(&mut self) -> HealthAction {
+        self.errors = self.errors.saturating_add(1);
+        self.consecutive = self.consecutive.saturating_add(1);
+        if !self.consecutive.is_multiple_of(RECOVER_AFTER) {
+            HealthAction::Retry
+        } else if self.consecutive < RECOVER_AFTER * MAX_RECOVERIES {
+            HealthAction::Recover
+        } else {
+            self.faulty = true;
+            HealthAction::Fault
+        }
+    }
+    pub fn is_faulty(&self) -> bool {
+        self.faulty
+    }
+    pub fn errors(&self) -> u32 {
+        self.errors
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn single_failures_are_retried() {
+        let mut health = SensorHealth::new();
+        for _ in 0..10 {
+            assert_eq!(health.failure(), HealthAction::Retry);
+            assert_eq!(health.success(), None);
+        }
+        assert_eq!(health.errors(), 10);
+        assert!(!health.is_faulty());
+    }
+
+    #[test]
+    fn recovers_then_reports_a_fault_until_it_answers() {
+        let mut health = SensorHealth::new();
+        let actions: [HealthAction; 12] = core::array::from_fn(|_| health.failure());
+        use HealthAction::{Fault, Recover, Retry};
+        assert_eq!(
+            actions,
+            [
+                Retry, Retry, Recover, Retry, Retry, Recover, Retry, Retry, Fault, Retry, Retry,
+                Fault
+            ]
+        );
+        assert!(health.is_faulty());
+        assert_eq!(
+            health.success(),
+            Some(AppResetMessage::FromSensor { healthy: true })
+        );
+        assert!(!health.is_faulty());
+        assert_eq!(health.success(), None);
+        assert_eq!(health.failure(), Retry);
+    }
+
+    #[test]
+    fn sensor_missing_at_boot_is_cleared_once_it_answers() {
+        let mut health = SensorHealth::faulty();
+        assert!(health.is_faulty());
+        assert_eq!(health.failure(), HealthAction::Retry);
+        assert_eq!(
+            health.success(),
+            Some(AppResetMessage::FromSensor { healthy: true })
+        );
+        assert!(!health.is_faulty());
+    }
+}
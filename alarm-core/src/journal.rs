@@ -0,0 +1,435 @@
+use core::{fmt, time::Duration};
+
+use crate::{
+    app_state::{AppResetMessage, StateKind},
+    button::Gesture,
+    flash::FlashRegion,
+    ring::{FlashRing, StorageError, RECORD_OVERHEAD},
+    time::Instant,
+};
+
+/// Layout version of a journal entry. Bump it whenever the payload changes and
+/// teach [`decode_entry`] how to read the previous one.
+pub const JOURNAL_VERSION: u16 = 1;
+const MAGIC: u16 = 0xB10C;
+pub const ENTRY_SIZE: usize = 32;
+/// Motion keeps arriving for as long as the device is carried around, and the
+/// state machine ignores it outside the pre-alarm. Logging every sample would
+/// wear the journal pages out within months, so ignored motion is only logged
+/// this often.
+pub const IGNORED_MOTION_INTERVAL: Duration = Duration::from_secs(60);
+
+/// What caused a journal entry.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum EventSource {
+    /// The firmware started. The timestamps of the entries after it restart
+    /// from zero.
+    Boot,
+    /// A state deadline passed.
+    Timeout,
+    ShortPress,
+    Accelerometer,
+    LongPress,
+    DoublePress,
+    Console,
+    Schedule,
+    /// Fall detection; the magnitude is the impact.
+    Fall,
+    /// Rotation; the magnitude is the peak rate in degrees per second.
+    Gyroscope,
+    /// The accelerometer failed or recovered.
+    Sensor,
+    /// Tip-over detection; the magnitude is the angle in degrees.
+    TipOver,
+}
+
+impl EventSource {
+    pub const ALL: [EventSource; 12] = [
+        EventSource::Boot,
+        EventSource::Timeout,
+        EventSource::ShortPress,
+        EventSource::Accelerometer,
+        EventSource::LongPress,
+        EventSource::DoublePress,
+        EventSource::Console,
+        EventSource::Schedule,
+        EventSource::Fall,
+        EventSource::Gyroscope,
+        EventSource::Sensor,
+        EventSource::TipOver,
+    ];
+}
+
+impl fmt::Display for EventSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            EventSource::Boot => "boot",
+            EventSource::Timeout => "timeout",
+            EventSource::ShortPress => "short-press",
+            EventSource::Accelerometer => "accelerometer",
+            EventSource::LongPress => "long-press",
+            EventSource::DoublePress => "double-press",
+            EventSource::Console => "console",
+            EventSource::Schedule => "schedule",
+            EventSource::Fall => "fall",
+            EventSource::Gyroscope => "gyroscope",
+            EventSource::Sensor => "sensor",
+            EventSource::TipOver => "tip-over",
+        })
+    }
+}
+
+/// One state transition or reset message, as stored in the journal. A reset
+/// message that did not apply has `from == to`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct JournalEntry {
+    /// Time since the last boot.
+    pub timestamp: Instant,
+    pub source: EventSource,
+    pub from: StateKind,
+    pub to: StateKind,
+    /// Peak motion of an accelerometer message or impact of a fall in
+    /// milli-g, peak rate of a gyroscope message in degrees per second, angle
+    /// of a tip-over in degrees, zero for other sources.
+    pub magnitude: u16,
+}
+
+impl fmt::Display for JournalEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}ms {} {} -> {}",
+            self.timestamp.as_millis(),
+            self.source,
+            self.from,
+            self.to
+        )?;
+        if matches!(
+            self.source,
+            EventSource::Accelerometer
+                | EventSource::Fall
+                | EventSource::Gyroscope
+                | EventSource::TipOver
+        ) {
+            write!(f, " magnitude {}", self.magnitude)?;
+        }
+        Ok(())
+    }
+}
+
+/// Payload layout, little endian:
+///
+/// ```text
+/// 0  timestamp ms u64 | 8  source u8 | 9  from u8 | 10 to u8 | 11 reserved
+/// 12 magnitude u16
+/// ```
+pub fn encode_entry(entry: &JournalEntry, payload: &mut [u8]) {
+    payload[0..8].copy_from_slice(&entry.timestamp.as_millis().to_le_bytes());
+    payload[8] = code(&EventSource::ALL, entry.source);
+    payload[9] = code(&StateKind::ALL, entry.from);
+    payload[10] = code(&StateKind::ALL, entry.to);
+    payload[12..14].copy_from_slice(&entry.magnitude.to_le_bytes());
+}
+
+/// Position of `item` in one of the `ALL` tables, as stored on flash.
+fn code<T: PartialEq>(all: &[T], item: T) -> u8 {
+    all.iter().position(|i| *i == item).unwrap_or(0) as u8
+}
+
+/// Reads an entry written with any journal version up to the current one.
+pub fn decode_entry(version: u16, payload: &[u8]) -> Option<JournalEntry> {
+    let state = |i: usize| StateKind::ALL.get(usize::from(payload[i])).copied();
+    match version {
+        1 => Some(JournalEntry {
+            timestamp: Instant::from_millis(u64::from_le_bytes(payload[0..8].try_into().ok()?)),
+            source: *EventSource::ALL.get(usize::from(payload[8]))?,
+            from: state(9)?,
+            to: state(10)?,
+            magnitude: u16::from_le_bytes([payload[12], payload[13]]),
+        }),
+        _ => None,
+    }
+}
+
+/// A black box of state transitions and reset messages, kept in its own flash
+/// region so it survives power loss and can be read back over the console.
+///
+/// Entries go into a [`FlashRing`], so once the region is full the oldest
+/// page is erased to make room.
+pub struct Journal<F> {
+    ring: FlashRing<F, ENTRY_SIZE>,
+    last_ignored_motion: Option<Instant>,
+}
+
+impl<F: FlashRegion> Journal<F> {
+    pub fn new(flash: F) -> Self {
+        Journal {
+            ring: FlashRing::new(flash, MAGIC),
+            last_ignored_motion: None,
+        }
+    }
+    pub fn release(self) -> F {
+        self.ring.release()
+    }
+    /// Finds the end of the journal. Must run once before anything is
+    /// recorded.
+    pub fn open(&mut self) -> Result<(), StorageError<F::Error>> {
+        self.ring.scan()
+    }
+    pub fn record(&mut self, entry: &JournalEntry) -> Result<(), StorageError<F::Error>> {
+        let mut payload = [0; ENTRY_SIZE - RECORD_OVERHEAD];
+        encode_entry(entry, &mut payload);
+        self.ring.append(JOURNAL_VERSION, &payload)?;
+        Ok(())
+    }
+    pub fn record_boot(
+        &mut self,
+        now: Instant,
+        state: StateKind,
+    ) -> Result<(), StorageError<F::Error>> {
+        self.record(&JournalEntry {
+            timestamp: now,
+            source: EventSource::Boot,
+            from: state,
+            to: state,
+            magnitude: 0,
+        })
+    }
+    /// Records an escalation caused by a passed deadline.
+    pub fn record_timeout(
+        &mut self,
+        now: Instant,
+        from: StateKind,
+        to: StateKind,
+    ) -> Result<(), StorageError<F::Error>> {
+        self.record(&JournalEntry {
+            timestamp: now,
+            source: EventSource::Timeout,
+            from,
+            to,
+            magnitude: 0,
+        })
+    }
+    /// Records a reset message along with the states before and after it was
+    /// handled. Ignored motion is rate limited to one entry per
+    /// [`IGNORED_MOTION_INTERVAL`].
+    pub fn record_reset(
+        &mut self,
+        now: Instant,
+        message: AppResetMessage,
+        from: StateKind,
+        to: StateKind,
+    ) -> Result<(), StorageError<F::Error>> {
+        let (source, magnitude) = match message {
+            AppResetMessage::FromButton { gesture } => (
+                match gesture {
+                    Gesture::Short => EventSource::ShortPress,
+                    Gesture::Long => EventSource::LongPress,
+                    Gesture::Double => EventSource::DoublePress,
+                },
+                0,
+            ),
+            AppResetMessage::FromAccelerometer { magnitude } => {
+                (EventSource::Accelerometer, magnitude)
+            }
+            AppResetMessage::FromFall { magnitude } => (EventSource::Fall, magnitude),
+            AppResetMessage::FromTipOver { angle } => (EventSource::TipOver, angle),
+            AppResetMessage::FromGyroscope { rate } => (EventSource::Gyroscope, rate),
+            AppResetMessage::FromConsole { .. } => (EventSource::Console, 0),
+            AppResetMessage::FromSchedule { .. } => (EventSource::Schedule, 0),
+            AppResetMessage::FromSensor { .. } => (EventSource::Sensor, 0),
+        };
+        let motion = matches!(source, EventSource::Accelerometer | EventSource::Gyroscope);
+        if motion && from == to {
+            if self
+                .last_ignored_motion
+                .is_some_and(|last| now < last + IGNORED_MOTION_INTERVAL)
+            {
+                return Ok(());
+            }
+            self.last_ignored_motion = Some(now);
+        }
+        self.record(&JournalEntry {
+            timestamp: now,
+            source,
+            from,
+            to,
+            magnitude,
+        })
+    }
+    /// Calls `visit` with the sequence number and contents of every entry
+    /// this firmware can read, oldest first.
+    pub fn for_each(
+        &mut self,
+        mut visit: impl FnMut(u32, JournalEntry),
+    ) -> Result<(), StorageError<F::Error>> {
+        self.ring.for_each(|version, sequence, payload| {
+            if let Some(entry) = decode_entry(version, payload) {
+                visit(sequence, entry);
+            }
+        })
+    }
+    /// Writes every entry on its own line, oldest first.
+    pub fn dump(
+        &mut self,
+        out: &mut impl fmt::Write,
+    ) -> Result<fmt::Result, StorageError<F::Error>> {
+        let mut result = Ok(());
+        self.for_each(|sequence, entry| {
+            if result.is_ok() {
+                result = writeln!(out, "#{} {}", sequence, entry);
+            }
+        })?;
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use std::{string::String, vec::Vec};
+
+    use super::*;
+    use crate::flash::{mock::MockFlash, SliceFlash};
+
+    const PAGE_SIZE: usize = 128;
+
+    fn journal() -> Journal<MockFlash> {
+        let mut journal = Journal::new(MockFlash::new(PAGE_SIZE, 2));
+        journal.open().unwrap();
+        journal
+    }
+
+    fn entries(journal: &mut Journal<MockFlash>) -> Vec<JournalEntry> {
+        let mut entries = Vec::new();
+        journal.for_each(|_, entry| entries.push(entry)).unwrap();
+        entries
+    }
+
+    fn at(millis: u64) -> Instant {
+        Instant::from_millis(millis)
+    }
+
+    const MOTION: AppResetMessage = AppResetMessage::FromAccelerometer { magnitude: 1500 };
+    const ROTATION: AppResetMessage = AppResetMessage::FromGyroscope { rate: 90 };
+
+    #[test]
+    fn entry_round_trips() {
+        let entry = JournalEntry {
+            timestamp: at(0x1_0000_0001),
+            source: EventSource::Accelerometer,
+            from: StateKind::PreAlarm,
+            to: StateKind::Active,
+            magnitude: 1500,
+        };
+        let mut payload = [0; ENTRY_SIZE - RECORD_OVERHEAD];
+        encode_entry(&entry, &mut payload);
+        assert_eq!(decode_entry(JOURNAL_VERSION, &payload), Some(entry));
+        assert_eq!(decode_entry(JOURNAL_VERSION + 1, &payload), None);
+        payload[8] = 0xFF;
+        assert_eq!(decode_entry(JOURNAL_VERSION, &payload), None);
+    }
+
+    #[test]
+    fn survives_reboot_in_order() {
+        let mut journal = journal();
+        journal.record_boot(at(0), StateKind::Active).unwrap();
+        journal
+            .record_timeout(at(5_000), StateKind::Active, StateKind::PreAlarm)
+            .unwrap();
+        journal
+            .record_reset(at(6_000), MOTION, StateKind::PreAlarm, StateKind::Active)
+            .unwrap();
+
+        let mut rebooted = Journal::new(journal.release());
+        rebooted.open().unwrap();
+        let sources: Vec<_> = entries(&mut rebooted).iter().map(|e| e.source).collect();
+        assert_eq!(
+            sources,
+            [
+                EventSource::Boot,
+                EventSource::Timeout,
+                EventSource::Accelerometer
+            ]
+        );
+        rebooted.record_boot(at(0), StateKind::Active).unwrap();
+        assert_eq!(entries(&mut rebooted).len(), 4);
+    }
+
+    #[test]
+    fn ignored_motion_is_rate_limited() {
+        let mut journal = journal();
+        // Both motion sensors share the limit.
+        for second in 0..120 {
+            let message = if second % 2 == 0 { MOTION } else { ROTATION };
+            journal
+                .record_reset(
+                    at(second * 1_000),
+                    message,
+                    StateKind::Active,
+                    StateKind::Active,
+                )
+                .unwrap();
+        }
+        // Motion that cancels a pre-alarm is always logged.
+        journal
+            .record_reset(at(120_500), MOTION, StateKind::PreAlarm, StateKind::Active)
+            .unwrap();
+        let times: Vec<_> = entries(&mut journal)
+            .iter()
+            .map(|e| e.timestamp.as_millis())
+            .collect();
+        assert_eq!(times, [0, 60_000, 120_500]);
+    }
+
+    #[test]
+    fn ignored_button_presses_are_logged() {
+        let mut journal = journal();
+        for second in 0..3 {
+            journal
+                .record_reset(
+                    at(second),
+                    AppResetMessage::FromButton {
+                        gesture: Gesture::Double,
+                    },
+                    StateKind::Active,
+                    StateKind::Active,
+                )
+                .unwrap();
+        }
+        assert_eq!(entries(&mut journal).len(), 3);
+    }
+
+    #[test]
+    fn oldest_entries_are_dropped_when_full() {
+        let mut journal = journal();
+        let slots = 2 * PAGE_SIZE / ENTRY_SIZE;
+        for second in 0..=slots as u64 {
+            journal
+                .record_timeout(at(second), StateKind::Active, StateKind::PreAlarm)
+                .unwrap();
+        }
+        let entries = entries(&mut journal);
+        assert_eq!(entries.len(), PAGE_SIZE / ENTRY_SIZE + 1);
+        assert_eq!(entries.last().unwrap().timestamp, at(slots as u64));
+    }
+
+    #[test]
+    fn dumps_from_a_flash_image() {
+        let mut journal = journal();
+        journal.record_boot(at(0), StateKind::Active).unwrap();
+        journal
+            .record_reset(at(7_250), MOTION, StateKind::PreAlarm, StateKind::Active)
+            .unwrap();
+        let image = journal.release().data;
+
+        let mut journal = Journal::new(SliceFlash::new(&image, PAGE_SIZE));
+        let mut out = String::new();
+        assert_eq!(journal.dump(&mut out), Ok(Ok(())));
+        assert_eq!(
+            out,
+            "#1 0ms boot active -> active\n\
+             #2 7250ms accelerometer pre-alarm -> active magnitude 1500\n"
+        );
+    }
+}
@@ -0,0 +1,52 @@
+#![no_std]
+
+pub mod activity;
+pub mod app_state;
+pub mod button;
+pub mod calendar;
+pub mod calibration;
+pub mod command;
+pub mod compass;
+pub mod crc;
+pub mod fall;
+pub mod flash;
+pub mod fsm;
+pub mod health;
+pub mod journal;
+pub mod motion;
+pub mod profile;
+pub mod replay;
+pub mod ring;
+pub mod rotation;
+pub mod schedule;
+pub mod sensor;
+pub mod spectrum;
+pub mod storage;
+pub mod tilt;
+pub mod time;
+
+pub use activity::{Activity, ActivityMonitor, ActivityReport, WakeUpGate};
+pub use app_state::{AppResetMessage, AppState, StateKind, TransitionError, MAX_QUEUE_SIZE};
+pub use button::{Gesture, GestureRecognizer};
+pub use calendar::{Calendar, DateTime, Weekday};
+pub use calibration::{
+    AccelCalibration, AccelCalibrator, Calibration, CalibrationStore, Orientation,
+};
+pub use command::{Command, CommandContext, CommandError, LineBuffer};
+pub use compass::{CompassPoint, MagCalibration, MagCalibrator};
+pub use fall::{FallDetector, FallEvent};
+pub use flash::{FlashRegion, SliceFlash};
+pub use fsm::Event;
+pub use health::{HealthAction, SensorHealth};
+pub use journal::{EventSource, Journal, JournalEntry};
+pub use motion::{MotionDetector, MotionEvent, WakeUpConfig};
+pub use profile::{AccelOdr, AccelSensitivity, AlarmProfile, MotionSource, ProfileError, Setting};
+pub use replay::Replay;
+pub use ring::{FlashRing, StorageError};
+pub use rotation::{RotationDetector, RotationEvent};
+pub use schedule::{Schedule, Scheduler, WeekTime};
+pub use sensor::{AsyncMotionSensor, MotionSensor, ScriptedSensor, TraceSensor};
+pub use spectrum::{Vibration, VibrationMask};
+pub use storage::ConfigStore;
+pub use tilt::{Tilt, TipOverDetector, TipOverEvent};
+pub use time::Instant;
@@ -0,0 +1,403 @@
+//! Motion detection on the accelerometer samples.
+//!
+//! Raw counts are converted to milli-g with the configured sensitivity, a
+//! high-pass filter takes gravity out, and the magnitude of what is left is
+//! averaged over a short sliding window. That keeps the threshold meaning the
+//! same at every range and sample rate, and lets motion that happens between
+//! two reports still count.
+//!
+//! With [`MotionSource::Interrupt`](crate::profile::MotionSource) the sensor
+//! does the detecting itself; [`WakeUpConfig`] translates the same threshold
+//! into its registers.
+
+use core::time::Duration;
+
+use crate::{
+    app_state::AppResetMessage,
+    profile::{AccelSensitivity, AlarmProfile},
+};
+
+/// How much recent motion the detector averages over.
+pub const DETECTION_WINDOW: Duration = Duration::from_millis(500);
+/// Upper bound on the samples in the window, for short sample periods.
+pub const MAX_WINDOW: usize = 32;
+/// The gravity estimate moves 1/2^`HIGH_PASS_SHIFT` of the way towards each
+/// sample, i.e. a time constant of 16 samples.
+const HIGH_PASS_SHIFT: u32 = 4;
+
+/// How long motion has to last before the wake-up interrupt fires, so a
+/// single knock does not count.
+pub const WAKE_UP_DURATION: Duration = Duration::from_millis(50);
+/// How often the firmware checks the profile, the state and that the sensor
+/// still answers while the wake-up interrupt reports motion. Longer saves
+/// power; a profile change takes up to this long to reach the sensor.
+pub const WAKE_UP_POLL_PERIOD: Duration = Duration::from_secs(1);
+/// The threshold and duration registers are 7 bits wide.
+const WAKE_UP_MAX: u32 = 0x7F;
+
+/// One accelerometer sample in raw counts, as read from the LSM303DLHC.
+pub type RawSample = [i16; 3];
+
+/// Converts a raw sample to milli-g per axis. The LSM303DLHC left-justifies
+/// its 12-bit result, hence the division by 16.
+pub fn to_milli_g(raw: RawSample, sensitivity: AccelSensitivity) -> [i32; 3] {
+    raw.map(|counts| i32::from(counts) * i32::from(sensitivity.mg_per_lsb()) / 16)
+}
+
+/// Motion above the profile's threshold.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct MotionEvent {
+    /// Largest filtered magnitude in the window, in milli-g.
+    pub peak_mg: u16,
+    /// Mean filtered magnitude over the window, in milli-g.
+    pub mean_mg: u16,
+}
+
+impl From<MotionEvent> for AppResetMessage {
+    fn from(event: MotionEvent) -> AppResetMessage {
+        AppResetMessage::FromAccelerometer {
+            magnitude: event.peak_mg,
+        }
+    }
+}
+
+/// `INT1_THS_A` and `INT1_DURATION_A` of the LSM303DLHC for a wake-up
+/// interrupt at the profile's threshold.
+///
+/// The sensor high-pass filters the samples too, but compares each axis on
+/// its own rather than the magnitude, so it fires at the threshold along an
+/// axis and somewhat above it diagonally.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct WakeUpConfig {
+    pub threshold: u8,
+    pub duration: u8,
+}
+
+impl WakeUpConfig {
+    pub fn new(profile: &AlarmProfile) -> WakeUpConfig {
+        // One threshold count in milli-g, per the datasheet.
+        let lsb_mg = match profile.accel_sensitivity {
+            AccelSensitivity::G1 => 16,
+            AccelSensitivity::G2 => 32,
+            AccelSensitivity::G4 => 62,
+            AccelSensitivity::G12 => 186,
+        };
+        let threshold = u32::from(profile.motion_threshold).div_ceil(lsb_mg);
+        let samples = (WAKE_UP_DURATION.as_micros() as u32)
+            .div_ceil(profile.accel_odr.period().as_micros() as u32);
+        WakeUpConfig {
+            threshold: threshold.clamp(1, WAKE_UP_MAX) as u8,
+            duration: samples.min(WAKE_UP_MAX) as u8,
+        }
+    }
+}
+
+/// Turns raw samples into [`MotionEvent`]s.
+///
+/// Feed it every sample, taken every `sample_period` of the profile. An event
+/// is reported when the mean magnitude over the window reaches
+/// `motion_threshold`; the window then starts over, so continuous motion
+/// reports about once per [`DETECTION_WINDOW`].
+#[derive(Debug, Clone)]
+pub struct MotionDetector {
+    sensitivity: AccelSensitivity,
+    threshold_mg: u16,
+    gravity: HighPass,
+    window: SlidingWindow,
+}
+
+impl MotionDetector {
+    pub fn new(profile: &AlarmProfile) -> Self {
+        let mut detector = MotionDetector {
+            sensitivity: profile.accel_sensitivity,
+            threshold_mg: profile.motion_threshold,
+            gravity: HighPass::new(HIGH_PASS_SHIFT),
+            window: SlidingWindow::new(),
+        };
+        detector.configure(profile);
+        detector
+    }
+    /// Picks up a changed profile. The window starts over, but the gravity
+    /// estimate is kept since it is already in milli-g.
+    pub fn configure(&mut self, profile: &AlarmProfile) {
+        self.sensitivity = profile.accel_sensitivity;
+        self.threshold_mg = profile.motion_threshold;
+        self.window.resize(window_len(profile));
+    }
+    pub fn update(&mut self, raw: RawSample) -> Option<MotionEvent> {
+        let filtered = self.gravity.filter(to_milli_g(raw, self.sensitivity));
+        let (peak_mg, mean_mg) = self.window.push(magnitude(filtered), self.threshold_mg)?;
+        Some(MotionEvent { peak_mg, mean_mg })
+    }
+}
+
+/// Samples in [`DETECTION_WINDOW`] at the profile's sample period.
+pub(crate) fn window_len(profile: &AlarmProfile) -> usize {
+    let samples = DETECTION_WINDOW.as_millis() / profile.sample_period.as_millis().max(1);
+    samples as usize
+}
+
+/// Takes the slowly moving level out of a signal per axis, such as gravity
+/// out of acceleration.
+#[derive(Debug, Clone)]
+pub(crate) struct HighPass {
+    shift: u32,
+    /// The level with `FRACTION_BITS` fractional bits, or `None` before the
+    /// first sample.
+    level: Option<[i32; 3]>,
+}
+
+impl HighPass {
+    /// Fractional bits kept in the level.
+    const FRACTION_BITS: u32 = 8;
+
+    /// The level moves 1/2^`shift` of the way towards each sample.
+    pub(crate) const fn new(shift: u32) -> Self {
+        HighPass { shift, level: None }
+    }
+    pub(crate) fn filter(&mut self, sample: [i32; 3]) -> [i32; 3] {
+        let level = self
+            .level
+            .get_or_insert(sample.map(|axis| axis << Self::FRACTION_BITS));
+        let mut filtered = [0; 3];
+        for ((l, axis), out) in level.iter_mut().zip(sample).zip(filtered.iter_mut()) {
+            *l += ((axis << Self::FRACTION_BITS) - *l) >> self.shift;
+            *out = axis - (*l >> Self::FRACTION_BITS);
+        }
+        filtered
+    }
+}
+
+/// The last few magnitudes, for their mean and peak.
+#[derive(Debug, Clone)]
+pub(crate) struct SlidingWindow {
+    values: [u16; MAX_WINDOW],
+    len: usize,
+    /// Next slot to write and how many slots hold values.
+    next: usize,
+    filled: usize,
+    sum: u32,
+}
+
+impl SlidingWindow {
+    pub(crate) const fn new() -> Self {
+        SlidingWindow {
+            values: [0; MAX_WINDOW],
+            len: 1,
+            next: 0,
+            filled: 0,
+            sum: 0,
+        }
+    }
+    /// Starts over with room for `len` values, up to [`MAX_WINDOW`].
+    pub(crate) fn resize(&mut self, len: usize) {
+        self.len = len.clamp(1, MAX_WINDOW);
+        self.next = 0;
+        self.filled = 0;
+        self.sum = 0;
+    }
+    /// Adds `value`. Once the window is full and its mean reaches
+    /// `threshold`, returns the peak and the mean and starts over.
+    pub(crate) fn push(&mut self, value: u16, threshold: u16) -> Option<(u16, u16)> {
+        if self.filled == self.len {
+            self.sum -= u32::from(self.values[self.next]);
+        } else {
+            self.filled += 1;
+        }
+        self.values[self.next] = value;
+        self.sum += u32::from(value);
+        self.next = (self.next + 1) % self.len;
+        if self.filled < self.len {
+            return None;
+        }
+        let mean = (self.sum / self.len as u32) as u16;
+        if mean < threshold {
+            return None;
+        }
+        let peak = self.values[..self.len].iter().copied().max().unwrap_or(0);
+        self.resize(self.len);
+        Some((peak, mean))
+    }
+}
+
+/// Length of `v` in milli-g, saturating at `u16::MAX`.
+pub(crate) fn magnitude(v: [i32; 3]) -> u16 {
+    let squared: u64 = v.iter().map(|&axis| i64::from(axis).pow(2) as u64).sum();
+    u16::try_from(isqrt(squared)).unwrap_or(u16::MAX)
+}
+
+pub(crate) fn isqrt(n: u64) -> u64 {
+    if n < 2 {
+        return n;
+    }
+    // Newton's method from an estimate that is never too small.
+    let mut x = 1 << ((64 - n.leading_zeros()).div_ceil(2));
+    loop {
+        let y = (x + n / x) / 2;
+        if y >= x {
+            return x;
+        }
+        x = y;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use std::vec::Vec;
+
+    use super::*;
+    use crate::profile::AccelOdr;
+
+    /// Raw samples at ±16 g and 50 Hz, with sensor noise: the board lies
+    /// still, is picked up and shaken, and is put down again at a tilt.
+    const PICK_UP: &str = include_str!("../testdata/pick_up.csv");
+    /// Raw samples at ±16 g and 50 Hz of the board on a desk vibrating at
+    /// 7 Hz and a few tens of milli-g, like next to a running fan.
+    const FAN: &str = include_str!("../testdata/fan.csv");
+
+    fn profile() -> AlarmProfile {
+        AlarmProfile {
+            sample_period: Duration::from_millis(20),
+            accel_sensitivity: AccelSensitivity::G12,
+            motion_threshold: 100,
+            ..AlarmProfile::default()
+        }
+    }
+
+    fn samples(csv: &str) -> impl Iterator<Item = RawSample> + '_ {
+        csv.lines().skip(1).map(|line| {
+            let mut axes = line.split(',').map(|axis| axis.trim().parse().unwrap());
+            [
+                axes.next().unwrap(),
+                axes.next().unwrap(),
+                axes.next().unwrap(),
+            ]
+        })
+    }
+
+    /// The sample indices at which `csv` reports motion, with the events.
+    fn detect(csv: &str) -> Vec<(usize, MotionEvent)> {
+        let mut detector = MotionDetector::new(&profile());
+        samples(csv)
+            .enumerate()
+            .filter_map(|(i, raw)| detector.update(raw).map(|event| (i, event)))
+            .collect()
+    }
+
+    #[test]
+    fn converts_counts_to_milli_g() {
+        // About 1 g at ±2 g and ±16 g.
+        assert_eq!(
+            to_milli_g([0, 0, 16_384], AccelSensitivity::G1),
+            [0, 0, 1024]
+        );
+        assert_eq!(
+            to_milli_g([0, 0, -1_336], AccelSensitivity::G12),
+            [0, 0, -1002]
+        );
+    }
+
+    #[test]
+    fn gravity_alone_is_not_motion() {
+        let mut detector = MotionDetector::new(&profile());
+        for _ in 0..200 {
+            assert_eq!(detector.update([0, 0, 1_336]), None);
+        }
+        // Turning the board over slowly is not motion either.
+        let mut z = 1_336;
+        while z > -1_336 {
+            z -= 4;
+            assert_eq!(detector.update([0, 0, z]), None);
+        }
+    }
+
+    #[test]
+    fn threshold_means_the_same_in_every_range() {
+        // A square wave on x swings half its height either side of its mean.
+        let events = |sensitivity: AccelSensitivity, height_mg: i32| {
+            let mut detector = MotionDetector::new(&AlarmProfile {
+                accel_sensitivity: sensitivity,
+                ..profile()
+            });
+            let counts = (height_mg * 16 / i32::from(sensitivity.mg_per_lsb())) as i16;
+            (0..100)
+                .filter_map(|i| detector.update([if i % 2 == 0 { counts } else { 0 }, 0, 0]))
+                .count()
+        };
+        for sensitivity in AccelSensitivity::ALL {
+            assert!(events(sensitivity, 240) > 0, "{:?}", sensitivity);
+            assert_eq!(events(sensitivity, 160), 0, "{:?}", sensitivity);
+        }
+    }
+
+    #[test]
+    fn detects_pick_up_in_recording() {
+        let events = detect(PICK_UP);
+        assert!(!events.is_empty());
+        // Nothing while it lies still for the first two seconds.
+        assert!(events.iter().all(|&(i, _)| i >= 100));
+        assert!(events.iter().any(|(_, event)| event.peak_mg > 500));
+        for (_, event) in events {
+            assert!(event.peak_mg >= event.mean_mg);
+            assert!(event.mean_mg >= 100);
+        }
+    }
+
+    #[test]
+    fn ignores_fan_vibration_in_recording() {
+        assert_eq!(detect(FAN), []);
+    }
+
+    #[test]
+    fn continuous_motion_reports_once_per_window() {
+        let mut detector = MotionDetector::new(&profile());
+        let events = (0..250)
+            .filter_map(|i| detector.update([if i % 2 == 0 { 2_000 } else { -2_000 }, 0, 0]))
+            .count();
+        // 25 samples per window, and the first window has to fill.
+        assert_eq!(events, 10);
+    }
+
+    #[test]
+    fn wake_up_registers_follow_profile() {
+        let config = |threshold, accel_sensitivity, accel_odr| {
+            WakeUpConfig::new(&AlarmProfile {
+                motion_threshold: threshold,
+                accel_sensitivity,
+                accel_odr,
+                ..profile()
+            })
+        };
+        assert_eq!(
+            config(100, AccelSensitivity::G12, AccelOdr::Hz100),
+            WakeUpConfig {
+                threshold: 1,
+                duration: 5,
+            }
+        );
+        assert_eq!(
+            config(100, AccelSensitivity::G1, AccelOdr::Hz400),
+            WakeUpConfig {
+                threshold: 7,
+                duration: 20,
+            }
+        );
+        // Saturates rather than wrapping.
+        assert_eq!(
+            config(u16::MAX, AccelSensitivity::G1, AccelOdr::Hz1),
+            WakeUpConfig {
+                threshold: 127,
+                duration: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn integer_square_root() {
+        for n in [0, 1, 2, 3, 4, 15, 16, 17, 1 << 40, u64::from(u32::MAX)] {
+            let root = isqrt(n);
+            assert!(root * root <= n && (root + 1) * (root + 1) > n, "{}", n);
+        }
+    }
+}
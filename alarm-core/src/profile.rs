@@ -0,0 +1,624 @@
+use core::{fmt, time::Duration};
+
+use crate::schedule::{Schedule, Window};
+
+/// Output data rate of the LSM303DLHC accelerometer.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum AccelOdr {
+    Hz1,
+    Hz10,
+    Hz25,
+    Hz50,
+    Hz100,
+    Hz200,
+    Hz400,
+}
+
+impl AccelOdr {
+    pub const ALL: [AccelOdr; 7] = [
+        AccelOdr::Hz1,
+        AccelOdr::Hz10,
+        AccelOdr::Hz25,
+        AccelOdr::Hz50,
+        AccelOdr::Hz100,
+        AccelOdr::Hz200,
+        AccelOdr::Hz400,
+    ];
+
+    pub fn hz(self) -> u16 {
+        match self {
+            AccelOdr::Hz1 => 1,
+            AccelOdr::Hz10 => 10,
+            AccelOdr::Hz25 => 25,
+            AccelOdr::Hz50 => 50,
+            AccelOdr::Hz100 => 100,
+            AccelOdr::Hz200 => 200,
+            AccelOdr::Hz400 => 400,
+        }
+    }
+    pub fn from_hz(hz: u16) -> Option<AccelOdr> {
+        AccelOdr::ALL.into_iter().find(|odr| odr.hz() == hz)
+    }
+    /// Time between two fresh samples from the sensor.
+    pub fn period(self) -> Duration {
+        Duration::from_micros(1_000_000 / u64::from(self.hz()))
+    }
+}
+
+/// Full-scale range of the LSM303DLHC accelerometer. Each setting is named
+/// after its resolution in mg per LSB, as the `lsm303dlhc` crate did; the doc
+/// comments give the range.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum AccelSensitivity {
+    /// ±2 g
+    G1,
+    /// ±4 g
+    G2,
+    /// ±8 g
+    G4,
+    /// ±16 g
+    G12,
+}
+
+impl AccelSensitivity {
+    pub const ALL: [AccelSensitivity; 4] = [
+        AccelSensitivity::G1,
+        AccelSensitivity::G2,
+        AccelSensitivity::G4,
+        AccelSensitivity::G12,
+    ];
+
+    /// Full-scale range in g.
+    pub fn range_g(self) -> u8 {
+        match self {
+            AccelSensitivity::G1 => 2,
+            AccelSensitivity::G2 => 4,
+            AccelSensitivity::G4 => 8,
+            AccelSensitivity::G12 => 16,
+        }
+    }
+    /// Resolution of a 12-bit reading.
+    pub fn mg_per_lsb(self) -> u8 {
+        match self {
+            AccelSensitivity::G1 => 1,
+            AccelSensitivity::G2 => 2,
+            AccelSensitivity::G4 => 4,
+            AccelSensitivity::G12 => 12,
+        }
+    }
+    pub fn from_range_g(range_g: u8) -> Option<AccelSensitivity> {
+        AccelSensitivity::ALL
+            .into_iter()
+            .find(|sensitivity| sensitivity.range_g() == range_g)
+    }
+}
+
+/// How the firmware learns about motion.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum MotionSource {
+    /// The accelerometer's wake-up interrupt on INT1 reports motion, so the
+    /// CPU only wakes when something moves.
+    Interrupt,
+    /// Every sample is read and run through the
+    /// [`MotionDetector`](crate::motion::MotionDetector), for when INT1 is
+    /// not wired up or the detector's filtering is wanted.
+    Polling,
+}
+
+impl MotionSource {
+    pub const ALL: [MotionSource; 2] = [MotionSource::Interrupt, MotionSource::Polling];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            MotionSource::Interrupt => "interrupt",
+            MotionSource::Polling => "polling",
+        }
+    }
+    pub fn from_name(name: &str) -> Option<MotionSource> {
+        MotionSource::ALL
+            .into_iter()
+            .find(|source| source.name() == name)
+    }
+}
+
+/// Everything that tunes the alarm, taken by both runtimes at startup and
+/// replaceable while they run.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct AlarmProfile {
+    /// How long the device may stay still before the pre-alarm starts.
+    pub inactivity_timeout: Duration,
+    /// How long the pre-alarm lasts before the alarm fires.
+    pub pre_alarm_timeout: Duration,
+    /// Mean acceleration, less gravity, in milli-g above which the device
+    /// counts as moving. See [`MotionDetector`](crate::motion::MotionDetector).
+    pub motion_threshold: u16,
+    /// Time between two accelerometer samples while polling.
+    pub sample_period: Duration,
+    pub motion_source: MotionSource,
+    pub accel_odr: AccelOdr,
+    pub accel_sensitivity: AccelSensitivity,
+    /// How long the alarm may go unacknowledged before it becomes an
+    /// emergency.
+    pub emergency_timeout: Duration,
+    /// Magnitude in milli-g below which the wearer counts as falling. See
+    /// [`FallDetector`](crate::fall::FallDetector).
+    pub free_fall_threshold: u16,
+    /// Magnitude in milli-g a fall has to land with. Zero turns fall
+    /// detection off.
+    pub impact_threshold: u16,
+    /// How long the wearer has to lie still after a fall before the alarm is
+    /// raised.
+    pub fall_stillness: Duration,
+    /// Mean rotation rate, less the gyroscope's zero-rate level, in degrees
+    /// per second above which the device counts as moving. Zero turns the
+    /// gyroscope off. See [`RotationDetector`](crate::rotation::RotationDetector).
+    pub rotation_threshold: u16,
+    /// Degrees the device may tilt away from how it lay when armed before it
+    /// counts as tipped over. Zero turns tip-over detection off. See
+    /// [`TipOverDetector`](crate::tilt::TipOverDetector).
+    pub tip_over_angle: u16,
+    /// How long the device has to stay tipped over before the pre-alarm
+    /// starts.
+    pub tip_over_hold: Duration,
+    /// Half the width, in hertz, of the band masked out of the motion
+    /// decision around a steady machine vibration. Zero turns masking off.
+    /// See [`VibrationMask`](crate::spectrum::VibrationMask).
+    pub vibration_band_hz: u16,
+    /// Share in percent of the signal a single band has to carry to count as
+    /// a machine vibration.
+    pub vibration_peak_pct: u16,
+    /// When to monitor; empty means always.
+    pub schedule: Schedule,
+}
+
+pub const MIN_TIMEOUT: Duration = Duration::from_secs(1);
+pub const MAX_INACTIVITY_TIMEOUT: Duration = Duration::from_secs(24 * 60 * 60);
+pub const MAX_PRE_ALARM_TIMEOUT: Duration = Duration::from_secs(60 * 60);
+pub const MAX_SAMPLE_PERIOD: Duration = Duration::from_secs(10);
+pub const MAX_EMERGENCY_TIMEOUT: Duration = Duration::from_secs(24 * 60 * 60);
+pub const MAX_FALL_STILLNESS: Duration = Duration::from_secs(60);
+/// Full scale of the gyroscope at the range the firmware sets.
+pub const MAX_ROTATION_THRESHOLD: u16 = 250;
+/// Tilted any further, the device would be turning back towards its baseline.
+pub const MAX_TIP_OVER_ANGLE: u16 = 180;
+pub const MAX_TIP_OVER_HOLD: Duration = Duration::from_secs(60);
+/// Wider, and the band would swallow the motion it is meant to let through.
+pub const MAX_VIBRATION_BAND_HZ: u16 = 10;
+/// Gravity alone; falling is below it and landing above.
+const ONE_G_MG: u16 = 1000;
+
+impl Default for AlarmProfile {
+    fn default() -> Self {
+        AlarmProfile {
+            inactivity_timeout: Duration::from_secs(5),
+            pre_alarm_timeout: Duration::from_secs(16),
+            motion_threshold: 100,
+            sample_period: Duration::from_millis(20),
+            motion_source: MotionSource::Interrupt,
+            accel_odr: AccelOdr::Hz100,
+            accel_sensitivity: AccelSensitivity::G12,
+            emergency_timeout: Duration::from_secs(5 * 60),
+            free_fall_threshold: 350,
+            impact_threshold: 0,
+            fall_stillness: Duration::from_secs(2),
+            rotation_threshold: 0,
+            tip_over_angle: 0,
+            tip_over_hold: Duration::from_secs(5),
+            vibration_band_hz: 0,
+            vibration_peak_pct: 60,
+            schedule: Schedule::default(),
+        }
+    }
+}
+
+impl AlarmProfile {
+    pub fn validate(&self) -> Result<(), ProfileError> {
+        if !(MIN_TIMEOUT..=MAX_INACTIVITY_TIMEOUT).contains(&self.inactivity_timeout) {
+            return Err(ProfileError::InactivityTimeoutOutOfRange);
+        }
+        if !(MIN_TIMEOUT..=MAX_PRE_ALARM_TIMEOUT).contains(&self.pre_alarm_timeout) {
+            return Err(ProfileError::PreAlarmTimeoutOutOfRange);
+        }
+        if !(MIN_TIMEOUT..=MAX_EMERGENCY_TIMEOUT).contains(&self.emergency_timeout) {
+            return Err(ProfileError::EmergencyTimeoutOutOfRange);
+        }
+        if self.motion_threshold == 0 {
+            return Err(ProfileError::MotionThresholdZero);
+        }
+        // Sampling faster than the sensor produces data only re-reads the
+        // same sample.
+        if !(self.accel_odr.period()..=MAX_SAMPLE_PERIOD).contains(&self.sample_period) {
+            return Err(ProfileError::SamplePeriodOutOfRange);
+        }
+        if !(1..ONE_G_MG).contains(&self.free_fall_threshold)
+            || (self.impact_threshold != 0 && self.impact_threshold <= ONE_G_MG)
+        {
+            return Err(ProfileError::FallThresholdOutOfRange);
+        }
+        if !(MIN_TIMEOUT..=MAX_FALL_STILLNESS).contains(&self.fall_stillness) {
+            return Err(ProfileError::FallStillnessOutOfRange);
+        }
+        if self.rotation_threshold > MAX_ROTATION_THRESHOLD {
+            return Err(ProfileError::RotationThresholdOutOfRange);
+        }
+        if self.tip_over_angle > MAX_TIP_OVER_ANGLE
+            || !(MIN_TIMEOUT..=MAX_TIP_OVER_HOLD).contains(&self.tip_over_hold)
+        {
+            return Err(ProfileError::TipOverOutOfRange);
+        }
+        if self.vibration_band_hz > MAX_VIBRATION_BAND_HZ
+            || !(1..=100).contains(&self.vibration_peak_pct)
+        {
+            return Err(ProfileError::VibrationOutOfRange);
+        }
+        if !self.schedule.windows.iter().flatten().all(Window::is_valid) {
+            return Err(ProfileError::InvalidWindow);
+        }
+        Ok(())
+    }
+    pub fn detects_falls(&self) -> bool {
+        self.impact_threshold != 0
+    }
+    pub fn detects_rotation(&self) -> bool {
+        self.rotation_threshold != 0
+    }
+    pub fn detects_tip_over(&self) -> bool {
+        self.tip_over_angle != 0
+    }
+    pub fn masks_vibration(&self) -> bool {
+        self.vibration_band_hz != 0
+    }
+    /// Applies a single setting, leaving the profile untouched if the result
+    /// would not be valid.
+    pub fn apply(&mut self, setting: Setting) -> Result<(), ProfileError> {
+        let mut updated = *self;
+        match setting {
+            Setting::InactivityTimeout(timeout) => updated.inactivity_timeout = timeout,
+            Setting::PreAlarmTimeout(timeout) => updated.pre_alarm_timeout = timeout,
+            Setting::MotionThreshold(threshold) => updated.motion_threshold = threshold,
+            Setting::SamplePeriod(period) => updated.sample_period = period,
+            Setting::MotionSource(source) => updated.motion_source = source,
+            Setting::AccelOdr(odr) => updated.accel_odr = odr,
+            Setting::AccelSensitivity(sensitivity) => updated.accel_sensitivity = sensitivity,
+            Setting::EmergencyTimeout(timeout) => updated.emergency_timeout = timeout,
+            Setting::FreeFallThreshold(threshold) => updated.free_fall_threshold = threshold,
+            Setting::ImpactThreshold(threshold) => updated.impact_threshold = threshold,
+            Setting::FallStillness(stillness) => updated.fall_stillness = stillness,
+            Setting::RotationThreshold(threshold) => updated.rotation_threshold = threshold,
+            Setting::TipOverAngle(angle) => updated.tip_over_angle = angle,
+            Setting::TipOverHold(hold) => updated.tip_over_hold = hold,
+            Setting::VibrationBand(hz) => updated.vibration_band_hz = hz,
+            Setting::VibrationPeak(pct) => updated.vibration_peak_pct = pct,
+            Setting::Window { slot, window } => match updated.schedule.windows.get_mut(slot) {
+                Some(slot) => *slot = window,
+                None => return Err(ProfileError::InvalidWindow),
+            },
+        }
+        updated.validate()?;
+        *self = updated;
+        Ok(())
+    }
+}
+
+impl fmt::Display for AlarmProfile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "inactivity_timeout_ms {}",
+            self.inactivity_timeout.as_millis()
+        )?;
+        writeln!(
+            f,
+            "pre_alarm_timeout_ms {}",
+            self.pre_alarm_timeout.as_millis()
+        )?;
+        writeln!(f, "motion_threshold_mg {}", self.motion_threshold)?;
+        writeln!(f, "sample_period_ms {}", self.sample_period.as_millis())?;
+        writeln!(f, "motion_source {}", self.motion_source.name())?;
+        writeln!(f, "accel_odr_hz {}", self.accel_odr.hz())?;
+        writeln!(f, "accel_range_g {}", self.accel_sensitivity.range_g())?;
+        writeln!(
+            f,
+            "emergency_timeout_ms {}",
+            self.emergency_timeout.as_millis()
+        )?;
+        writeln!(f, "free_fall_threshold_mg {}", self.free_fall_threshold)?;
+        writeln!(f, "impact_threshold_mg {}", self.impact_threshold)?;
+        writeln!(f, "fall_stillness_ms {}", self.fall_stillness.as_millis())?;
+        writeln!(f, "rotation_threshold_dps {}", self.rotation_threshold)?;
+        writeln!(f, "tip_over_angle_deg {}", self.tip_over_angle)?;
+        writeln!(f, "tip_over_hold_ms {}", self.tip_over_hold.as_millis())?;
+        writeln!(f, "vibration_band_hz {}", self.vibration_band_hz)?;
+        write!(f, "vibration_peak_pct {}", self.vibration_peak_pct)?;
+        for (slot, window) in self.schedule.windows.iter().enumerate() {
+            if let Some(window) = window {
+                write!(f, "\nschedule {} {}", slot + 1, window)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A single field of an [`AlarmProfile`] with its new value.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Setting {
+    InactivityTimeout(Duration),
+    PreAlarmTimeout(Duration),
+    MotionThreshold(u16),
+    SamplePeriod(Duration),
+    MotionSource(MotionSource),
+    AccelOdr(AccelOdr),
+    AccelSensitivity(AccelSensitivity),
+    EmergencyTimeout(Duration),
+    FreeFallThreshold(u16),
+    ImpactThreshold(u16),
+    FallStillness(Duration),
+    RotationThreshold(u16),
+    TipOverAngle(u16),
+    TipOverHold(Duration),
+    VibrationBand(u16),
+    VibrationPeak(u16),
+    /// Replaces or, with `None`, clears the window in `slot` (from zero) of
+    /// the schedule.
+    Window {
+        slot: usize,
+        window: Option<Window>,
+    },
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ProfileError {
+    InactivityTimeoutOutOfRange,
+    PreAlarmTimeoutOutOfRange,
+    EmergencyTimeoutOutOfRange,
+    MotionThresholdZero,
+    SamplePeriodOutOfRange,
+    FallThresholdOutOfRange,
+    FallStillnessOutOfRange,
+    RotationThresholdOutOfRange,
+    TipOverOutOfRange,
+    VibrationOutOfRange,
+    InvalidWindow,
+}
+
+impl fmt::Display for ProfileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ProfileError::InactivityTimeoutOutOfRange => "inactivity timeout out of range",
+            ProfileError::PreAlarmTimeoutOutOfRange => "pre-alarm timeout out of range",
+            ProfileError::EmergencyTimeoutOutOfRange => "emergency timeout out of range",
+            ProfileError::MotionThresholdZero => "motion threshold must be non-zero",
+            ProfileError::SamplePeriodOutOfRange => "sample period out of range",
+            ProfileError::FallThresholdOutOfRange => "fall threshold out of range",
+            ProfileError::FallStillnessOutOfRange => "fall stillness out of range",
+            ProfileError::RotationThresholdOutOfRange => "rotation threshold out of range",
+            ProfileError::TipOverOutOfRange => "tip-over setting out of range",
+            ProfileError::VibrationOutOfRange => "vibration setting out of range",
+            ProfileError::InvalidWindow => "invalid schedule window",
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schedule::{Days, MAX_WINDOWS};
+
+    #[test]
+    fn default_profile_is_valid() {
+        assert_eq!(AlarmProfile::default().validate(), Ok(()));
+    }
+
+    #[test]
+    fn default_profile_leaves_detection_to_the_interrupt() {
+        // Each of these has the firmware read samples every sample period,
+        // which costs far more power than the wake-up interrupt.
+        let profile = AlarmProfile::default();
+        assert_eq!(profile.motion_source, MotionSource::Interrupt);
+        assert!(!profile.detects_falls());
+        assert!(!profile.detects_tip_over());
+        assert!(!profile.detects_rotation());
+        assert!(!profile.masks_vibration());
+    }
+
+    #[test]
+    fn rejects_out_of_range_timeouts() {
+        let mut profile = AlarmProfile::default();
+        assert_eq!(
+            profile.apply(Setting::InactivityTimeout(Duration::ZERO)),
+            Err(ProfileError::InactivityTimeoutOutOfRange)
+        );
+        assert_eq!(
+            profile.apply(Setting::InactivityTimeout(
+                MAX_INACTIVITY_TIMEOUT + Duration::from_secs(1)
+            )),
+            Err(ProfileError::InactivityTimeoutOutOfRange)
+        );
+        assert_eq!(
+            profile.apply(Setting::PreAlarmTimeout(Duration::from_millis(999))),
+            Err(ProfileError::PreAlarmTimeoutOutOfRange)
+        );
+        assert_eq!(
+            profile.apply(Setting::EmergencyTimeout(
+                MAX_EMERGENCY_TIMEOUT + Duration::from_secs(1)
+            )),
+            Err(ProfileError::EmergencyTimeoutOutOfRange)
+        );
+        assert_eq!(profile, AlarmProfile::default());
+    }
+
+    #[test]
+    fn rejects_zero_threshold() {
+        let mut profile = AlarmProfile::default();
+        assert_eq!(
+            profile.apply(Setting::MotionThreshold(0)),
+            Err(ProfileError::MotionThresholdZero)
+        );
+    }
+
+    #[test]
+    fn sample_period_bounded_by_odr() {
+        let mut profile = AlarmProfile::default();
+        assert_eq!(
+            profile.apply(Setting::SamplePeriod(Duration::from_millis(10))),
+            Ok(())
+        );
+        assert_eq!(
+            profile.apply(Setting::AccelOdr(AccelOdr::Hz50)),
+            Err(ProfileError::SamplePeriodOutOfRange)
+        );
+        assert_eq!(profile.accel_odr, AccelOdr::Hz100);
+        assert_eq!(
+            profile.apply(Setting::SamplePeriod(
+                MAX_SAMPLE_PERIOD + Duration::from_millis(1)
+            )),
+            Err(ProfileError::SamplePeriodOutOfRange)
+        );
+    }
+
+    #[test]
+    fn applies_valid_settings() {
+        let mut profile = AlarmProfile::default();
+        profile
+            .apply(Setting::InactivityTimeout(Duration::from_secs(120)))
+            .unwrap();
+        profile
+            .apply(Setting::AccelSensitivity(AccelSensitivity::G1))
+            .unwrap();
+        assert_eq!(profile.inactivity_timeout, Duration::from_secs(120));
+        assert_eq!(profile.accel_sensitivity, AccelSensitivity::G1);
+    }
+
+    #[test]
+    fn fall_thresholds_straddle_one_g() {
+        let mut profile = AlarmProfile::default();
+        assert!(!profile.detects_falls());
+        assert_eq!(
+            profile.apply(Setting::FreeFallThreshold(1000)),
+            Err(ProfileError::FallThresholdOutOfRange)
+        );
+        assert_eq!(
+            profile.apply(Setting::ImpactThreshold(1000)),
+            Err(ProfileError::FallThresholdOutOfRange)
+        );
+        assert_eq!(
+            profile.apply(Setting::FallStillness(
+                MAX_FALL_STILLNESS + Duration::from_secs(1)
+            )),
+            Err(ProfileError::FallStillnessOutOfRange)
+        );
+        profile.apply(Setting::ImpactThreshold(2500)).unwrap();
+        assert!(profile.detects_falls());
+        profile.apply(Setting::ImpactThreshold(0)).unwrap();
+        assert!(!profile.detects_falls());
+    }
+
+    #[test]
+    fn rotation_threshold_within_gyro_range() {
+        let mut profile = AlarmProfile::default();
+        assert!(!profile.detects_rotation());
+        assert_eq!(
+            profile.apply(Setting::RotationThreshold(MAX_ROTATION_THRESHOLD + 1)),
+            Err(ProfileError::RotationThresholdOutOfRange)
+        );
+        profile.apply(Setting::RotationThreshold(30)).unwrap();
+        assert!(profile.detects_rotation());
+        profile.apply(Setting::RotationThreshold(0)).unwrap();
+        assert!(!profile.detects_rotation());
+    }
+
+    #[test]
+    fn tip_over_settings_within_range() {
+        let mut profile = AlarmProfile::default();
+        assert!(!profile.detects_tip_over());
+        assert_eq!(
+            profile.apply(Setting::TipOverAngle(MAX_TIP_OVER_ANGLE + 1)),
+            Err(ProfileError::TipOverOutOfRange)
+        );
+        assert_eq!(
+            profile.apply(Setting::TipOverHold(Duration::from_millis(500))),
+            Err(ProfileError::TipOverOutOfRange)
+        );
+        profile.apply(Setting::TipOverAngle(60)).unwrap();
+        assert!(profile.detects_tip_over());
+    }
+
+    #[test]
+    fn vibration_settings_within_range() {
+        let mut profile = AlarmProfile::default();
+        assert!(!profile.masks_vibration());
+        assert_eq!(
+            profile.apply(Setting::VibrationBand(MAX_VIBRATION_BAND_HZ + 1)),
+            Err(ProfileError::VibrationOutOfRange)
+        );
+        assert_eq!(
+            profile.apply(Setting::VibrationPeak(0)),
+            Err(ProfileError::VibrationOutOfRange)
+        );
+        assert_eq!(
+            profile.apply(Setting::VibrationPeak(101)),
+            Err(ProfileError::VibrationOutOfRange)
+        );
+        profile.apply(Setting::VibrationBand(2)).unwrap();
+        assert!(profile.masks_vibration());
+    }
+
+    #[test]
+    fn validates_schedule_windows() {
+        let mut profile = AlarmProfile::default();
+        let night = Window {
+            days: Days::WEEKDAYS,
+            start: 22 * 60,
+            end: 6 * 60,
+        };
+        profile
+            .apply(Setting::Window {
+                slot: 1,
+                window: Some(night),
+            })
+            .unwrap();
+        assert_eq!(profile.schedule.windows[1], Some(night));
+        assert_eq!(
+            profile.apply(Setting::Window {
+                slot: 0,
+                window: Some(Window {
+                    end: 22 * 60,
+                    ..night
+                }),
+            }),
+            Err(ProfileError::InvalidWindow)
+        );
+        assert_eq!(
+            profile.apply(Setting::Window {
+                slot: MAX_WINDOWS,
+                window: None,
+            }),
+            Err(ProfileError::InvalidWindow)
+        );
+        profile
+            .apply(Setting::Window {
+                slot: 1,
+                window: None,
+            })
+            .unwrap();
+        assert!(profile.schedule.is_empty());
+    }
+
+    #[test]
+    fn odr_and_range_round_trip() {
+        for odr in AccelOdr::ALL {
+            assert_eq!(AccelOdr::from_hz(odr.hz()), Some(odr));
+        }
+        for sensitivity in AccelSensitivity::ALL {
+            assert_eq!(
+                AccelSensitivity::from_range_g(sensitivity.range_g()),
+                Some(sensitivity)
+            );
+        }
+        for source in MotionSource::ALL {
+            assert_eq!(MotionSource::from_name(source.name()), Some(source));
+        }
+        assert_eq!(AccelOdr::from_hz(42), None);
+        assert_eq!(AccelSensitivity::from_range_g(3), None);
+    }
+}
@@ -0,0 +1,178 @@
+//! Plays recorded accelerometer samples through motion, fall and tip-over
+//! detection, activity classification, vibration masking and the state
+//! machine, as the firmware would have run them, so
+//! a profile can be tuned on a host instead of by shaking the board.
+//!
+//! Detection always runs on the samples, as with
+//! [`MotionSource::Polling`](crate::profile::MotionSource); the wake-up
+//! interrupt cannot be replayed.
+
+use crate::{
+    activity::{Activity, ActivityMonitor},
+    app_state::{AppResetMessage, AppState},
+    fall::FallDetector,
+    motion::{MotionDetector, RawSample},
+    profile::AlarmProfile,
+    spectrum::{Vibration, VibrationMask},
+    tilt::TipOverDetector,
+    time::Instant,
+};
+
+/// The firmware's view of a recording, one sample every `sample_period` of
+/// the profile, starting armed at time zero.
+#[derive(Debug, Clone)]
+pub struct Replay {
+    profile: AlarmProfile,
+    state: AppState,
+    detector: MotionDetector,
+    fall: FallDetector,
+    tip_over: TipOverDetector,
+    activity: ActivityMonitor,
+    vibration: VibrationMask,
+    /// Whether the motion detector fired on the last sample, gated or not.
+    raw_motion: bool,
+    now: Instant,
+}
+
+impl Replay {
+    pub fn new(profile: &AlarmProfile) -> Self {
+        let now = Instant::from_millis(0);
+        Replay {
+            profile: *profile,
+            state: AppState::new(now, profile),
+            detector: MotionDetector::new(profile),
+            fall: FallDetector::new(profile),
+            tip_over: TipOverDetector::new(profile),
+            activity: ActivityMonitor::new(profile),
+            vibration: VibrationMask::new(profile),
+            raw_motion: false,
+            now,
+        }
+    }
+    /// When the last sample was taken.
+    pub fn now(&self) -> Instant {
+        self.now
+    }
+    pub fn state(&self) -> AppState {
+        self.state
+    }
+    /// The activity of the last complete window.
+    pub fn activity(&self) -> Activity {
+        self.activity.activity()
+    }
+    /// The machine vibration being masked, if any.
+    pub fn vibration(&self) -> Option<Vibration> {
+        self.vibration.vibration()
+    }
+    /// Whether the motion detector fired on the last sample, before the
+    /// activity and the vibration mask had their say. Sweeps use it to tune
+    /// the threshold apart from the gates.
+    pub fn raw_motion(&self) -> bool {
+        self.raw_motion
+    }
+    /// Takes the next sample. The state first escalates as the deadlines
+    /// passed since the last sample say, then handles what was detected in
+    /// `raw`, which is returned whether the state took it or not. The
+    /// baseline for tip-over detection is learned from the first samples.
+    /// Motion is only detected while the activity is human and not masked
+    /// as machine vibration.
+    pub fn step(&mut self, raw: RawSample) -> Option<AppResetMessage> {
+        self.now = self.now + self.profile.sample_period;
+        self.state.update(self.now, &self.profile);
+        let fall = self.fall.update(raw).map(AppResetMessage::from);
+        let tip_over = self.tip_over.update(raw).map(AppResetMessage::from);
+        self.activity.update(raw);
+        self.vibration.update(raw);
+        let motion = self.detector.update(raw);
+        self.raw_motion = motion.is_some();
+        let motion = motion
+            .filter(|_| self.activity.is_human() && self.vibration.permits())
+            .map(AppResetMessage::from);
+        // Only the weightiest one counts: the motion of a fall must not
+        // cancel the alarm it raised.
+        let message = fall.or(tip_over).or(motion)?;
+        let _ = self.state.handle_reset(message, self.now, &self.profile);
+        Some(message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::time::Duration;
+
+    use super::*;
+    use crate::{
+        app_state::StateKind,
+        profile::AccelSensitivity,
+        sensor::{MotionSensor, TraceSensor},
+    };
+
+    const STILL: RawSample = [0, 0, 1_336];
+
+    fn profile() -> AlarmProfile {
+        AlarmProfile {
+            inactivity_timeout: Duration::from_secs(2),
+            pre_alarm_timeout: Duration::from_secs(3),
+            sample_period: Duration::from_millis(20),
+            accel_sensitivity: AccelSensitivity::G12,
+            ..AlarmProfile::default()
+        }
+    }
+
+    #[test]
+    fn stillness_escalates_on_the_sample_after_each_deadline() {
+        let mut replay = Replay::new(&profile());
+        let mut changes = [(0, StateKind::Active); 2];
+        let mut changed = 0;
+        for _ in 0..500 {
+            let before = replay.state().kind();
+            assert_eq!(replay.step(STILL), None);
+            if replay.state().kind() != before {
+                changes[changed] = (replay.now().as_millis(), replay.state().kind());
+                changed += 1;
+            }
+        }
+        assert_eq!(
+            changes,
+            [(2_000, StateKind::PreAlarm), (5_000, StateKind::Alarm)]
+        );
+    }
+
+    #[test]
+    fn recorded_motion_cancels_the_pre_alarm() {
+        let mut replay = Replay::new(&AlarmProfile {
+            inactivity_timeout: Duration::from_secs(1),
+            ..profile()
+        });
+        let mut trace = TraceSensor::new(include_str!("../testdata/pick_up.csv"));
+        let mut first = None;
+        while let Ok(raw) = trace.accel() {
+            let pre_alarm = replay.state().kind() == StateKind::PreAlarm;
+            if let Some(message) = replay.step(raw) {
+                assert!(matches!(message, AppResetMessage::FromAccelerometer { .. }));
+                first.get_or_insert((pre_alarm, replay.state().kind()));
+            }
+        }
+        // The board lies still for longer than the inactivity timeout before
+        // it is picked up.
+        assert_eq!(first, Some((true, StateKind::Active)));
+    }
+
+    #[test]
+    fn vibration_is_not_motion() {
+        let profile = AlarmProfile {
+            motion_threshold: 5,
+            ..profile()
+        };
+        let mut trace = TraceSensor::new(include_str!("../testdata/fan.csv"));
+        let mut replay = Replay::new(&profile);
+        let mut detected = false;
+        while let Ok(raw) = trace.accel() {
+            assert_eq!(replay.step(raw), None);
+            detected |= replay.raw_motion();
+        }
+        // The fan is strong enough to be motion, but not the wearer's.
+        assert!(detected);
+        assert_eq!(replay.activity(), Activity::Vehicle);
+    }
+}
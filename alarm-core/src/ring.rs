@@ -0,0 +1,269 @@
+use core::fmt;
+
+use crate::{crc::crc32, flash::FlashRegion};
+
+const HEADER_SIZE: usize = 8;
+const CRC_SIZE: usize = 4;
+/// Bytes of every record taken up by the header and CRC rather than payload.
+pub const RECORD_OVERHEAD: usize = HEADER_SIZE + CRC_SIZE;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum StorageError<E> {
+    Flash(E),
+    /// Wear levelling needs at least two pages, each holding a record.
+    RegionTooSmall,
+}
+
+impl<E> fmt::Display for StorageError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            StorageError::Flash(_) => "flash access failed",
+            StorageError::RegionTooSmall => "flash region too small",
+        })
+    }
+}
+
+/// Fixed-size records of `N` bytes appended round-robin over the pages of a
+/// flash region.
+///
+/// Records are written one after the other, moving on to the next page
+/// (erasing it first) when the current one is full and wrapping around at the
+/// end of the region, so every page wears at the same rate. Each record
+/// carries a sequence number and a CRC, so after a reset the newest intact
+/// record can be found again and a write cut short by power loss only loses
+/// itself.
+///
+/// Record layout, little endian:
+///
+/// ```text
+/// 0  magic u16 | 2  version u16 | 4  sequence u32
+/// 8  payload (N - 12 bytes)     | N - 4  CRC-32 of everything before it
+/// ```
+pub struct FlashRing<F, const N: usize> {
+    flash: F,
+    magic: u16,
+    sequence: u32,
+    next_slot: usize,
+}
+
+impl<F: FlashRegion, const N: usize> FlashRing<F, N> {
+    /// `magic` tells apart the rings kept by different users of the same kind
+    /// of flash.
+    pub fn new(flash: F, magic: u16) -> Self {
+        FlashRing {
+            flash,
+            magic,
+            sequence: 0,
+            next_slot: 0,
+        }
+    }
+    pub fn release(self) -> F {
+        self.flash
+    }
+    fn slots_per_page(&self) -> usize {
+        self.flash.page_size() / N
+    }
+    fn slot_count(&self) -> usize {
+        self.slots_per_page() * self.flash.page_count()
+    }
+    fn check_geometry(&self) -> Result<(), StorageError<F::Error>> {
+        if self.slots_per_page() == 0 || self.flash.page_count() < 2 {
+            return Err(StorageError::RegionTooSmall);
+        }
+        Ok(())
+    }
+    fn read_slot(&mut self, slot: usize) -> Result<[u8; N], StorageError<F::Error>> {
+        let mut record = [0; N];
+        self.flash
+            .read(slot * N, &mut record)
+            .map_err(StorageError::Flash)?;
+        Ok(record)
+    }
+    /// Returns the version and sequence number of an intact record.
+    fn check_record(&self, record: &[u8; N]) -> Option<(u16, u32)> {
+        let (body, crc) = record.split_at(N - CRC_SIZE);
+        if u16::from_le_bytes([record[0], record[1]]) != self.magic
+            || u32::from_le_bytes(crc.try_into().ok()?) != crc32(body)
+        {
+            return None;
+        }
+        let version = u16::from_le_bytes([record[2], record[3]]);
+        let sequence = u32::from_le_bytes(record[4..8].try_into().ok()?);
+        Some((version, sequence))
+    }
+    /// Finds where the next record goes. Must run once before
+    /// [`append`](Self::append) after the ring is created.
+    pub fn scan(&mut self) -> Result<(), StorageError<F::Error>> {
+        self.check_geometry()?;
+        let mut newest: Option<(u32, usize)> = None;
+        for slot in 0..self.slot_count() {
+            let record = self.read_slot(slot)?;
+            if let Some((_, sequence)) = self.check_record(&record) {
+                if newest.is_none_or(|(newest, _)| sequence > newest) {
+                    newest = Some((sequence, slot));
+                }
+            }
+        }
+        (self.sequence, self.next_slot) = match newest {
+            Some((sequence, slot)) => (sequence, (slot + 1) % self.slot_count()),
+            None => (0, 0),
+        };
+        Ok(())
+    }
+    /// Calls `visit` with the version, sequence number and payload of every
+    /// intact record, oldest first.
+    pub fn for_each(
+        &mut self,
+        mut visit: impl FnMut(u16, u32, &[u8]),
+    ) -> Result<(), StorageError<F::Error>> {
+        self.check_geometry()?;
+        let slot_count = self.slot_count();
+        for i in 0..slot_count {
+            let record = self.read_slot((self.next_slot + i) % slot_count)?;
+            if let Some((version, sequence)) = self.check_record(&record) {
+                visit(version, sequence, &record[HEADER_SIZE..N - CRC_SIZE]);
+            }
+        }
+        Ok(())
+    }
+    /// Writes a record after the newest one. `payload` is zero-padded or cut
+    /// to `N - RECORD_OVERHEAD` bytes. Returns the record's sequence number.
+    pub fn append(&mut self, version: u16, payload: &[u8]) -> Result<u32, StorageError<F::Error>> {
+        self.check_geometry()?;
+        let slots_per_page = self.slots_per_page();
+        let mut slot = self.next_slot;
+        let blank = self.read_slot(slot)?.iter().all(|&b| b == 0xFF);
+        if !blank && !slot.is_multiple_of(slots_per_page) {
+            // Left dirty by an interrupted write. Erasing this page would also
+            // erase the newest good record, so move on to the next one.
+            slot = (slot / slots_per_page + 1) * slots_per_page % self.slot_count();
+        }
+        if slot.is_multiple_of(slots_per_page) {
+            self.flash
+                .erase_page(slot / slots_per_page)
+                .map_err(StorageError::Flash)?;
+        }
+        let sequence = self.sequence.wrapping_add(1);
+        let mut record = [0; N];
+        record[0..2].copy_from_slice(&self.magic.to_le_bytes());
+        record[2..4].copy_from_slice(&version.to_le_bytes());
+        record[4..8].copy_from_slice(&sequence.to_le_bytes());
+        let len = payload.len().min(N - RECORD_OVERHEAD);
+        record[HEADER_SIZE..HEADER_SIZE + len].copy_from_slice(&payload[..len]);
+        let crc = crc32(&record[..N - CRC_SIZE]);
+        record[N - CRC_SIZE..].copy_from_slice(&crc.to_le_bytes());
+        self.flash
+            .write(slot * N, &record)
+            .map_err(StorageError::Flash)?;
+        self.sequence = sequence;
+        self.next_slot = (slot + 1) % self.slot_count();
+        Ok(sequence)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use std::vec::Vec;
+
+    use super::*;
+    use crate::flash::mock::{MockFlash, MockFlashError};
+
+    const PAGE_SIZE: usize = 64;
+    const N: usize = 16;
+    const SLOTS_PER_PAGE: usize = PAGE_SIZE / N;
+
+    fn ring(pages: usize) -> FlashRing<MockFlash, N> {
+        let mut ring = FlashRing::new(MockFlash::new(PAGE_SIZE, pages), 0xBEEF);
+        ring.scan().unwrap();
+        ring
+    }
+
+    fn payloads(ring: &mut FlashRing<MockFlash, N>) -> Vec<u8> {
+        let mut seen = Vec::new();
+        ring.for_each(|_, _, payload| seen.push(payload[0]))
+            .unwrap();
+        seen
+    }
+
+    fn reopen(ring: FlashRing<MockFlash, N>) -> FlashRing<MockFlash, N> {
+        let mut ring = FlashRing::new(ring.release(), 0xBEEF);
+        ring.scan().unwrap();
+        ring
+    }
+
+    #[test]
+    fn visits_oldest_first_after_wraparound() {
+        let mut ring = ring(2);
+        for i in 1..=(2 * SLOTS_PER_PAGE as u8 + 1) {
+            ring.append(1, &[i]).unwrap();
+        }
+        // The first page was erased to make room for the ninth record.
+        let mut ring = reopen(ring);
+        assert_eq!(payloads(&mut ring), [5, 6, 7, 8, 9]);
+        assert_eq!(ring.append(1, &[10]), Ok(10));
+    }
+
+    #[test]
+    fn spreads_erases_over_pages() {
+        let mut ring = ring(2);
+        for i in 0..(8 * SLOTS_PER_PAGE) {
+            ring.append(1, &[i as u8]).unwrap();
+        }
+        assert_eq!(ring.release().erase_counts, [4, 4]);
+    }
+
+    #[test]
+    fn ignores_other_magic() {
+        let mut ring = ring(2);
+        ring.append(1, &[1]).unwrap();
+        let mut other = FlashRing::<_, N>::new(ring.release(), 0xCAFE);
+        other.scan().unwrap();
+        assert_eq!(other.for_each(|_, _, _| panic!()), Ok(()));
+    }
+
+    #[test]
+    fn power_loss_mid_write_keeps_previous_and_recovers() {
+        let mut ring = ring(2);
+        ring.append(1, &[1]).unwrap();
+        let mut flash = ring.release();
+        flash.bytes_until_power_loss = Some(N / 2);
+        let mut ring = FlashRing::<_, N>::new(flash, 0xBEEF);
+        ring.scan().unwrap();
+        assert_eq!(
+            ring.append(1, &[2]),
+            Err(StorageError::Flash(MockFlashError::PowerLoss))
+        );
+
+        let mut flash = ring.release();
+        flash.bytes_until_power_loss = None;
+        let mut ring = FlashRing::<_, N>::new(flash, 0xBEEF);
+        ring.scan().unwrap();
+        assert_eq!(payloads(&mut ring), [1]);
+        // The half-written slot is skipped rather than erased along with the
+        // good record in front of it.
+        ring.append(1, &[3]).unwrap();
+        let mut ring = reopen(ring);
+        assert_eq!(payloads(&mut ring), [1, 3]);
+        assert_eq!(ring.release().erase_counts, [1, 1]);
+    }
+
+    #[test]
+    fn corrupt_record_is_skipped() {
+        let mut ring = ring(2);
+        ring.append(1, &[1]).unwrap();
+        ring.append(1, &[2]).unwrap();
+        let mut flash = ring.release();
+        flash.data[N + HEADER_SIZE] ^= 0x01;
+        let mut ring = FlashRing::<_, N>::new(flash, 0xBEEF);
+        ring.scan().unwrap();
+        assert_eq!(payloads(&mut ring), [1]);
+    }
+
+    #[test]
+    fn rejects_single_page_region() {
+        let mut ring = FlashRing::<_, N>::new(MockFlash::new(PAGE_SIZE, 1), 0xBEEF);
+        assert_eq!(ring.scan(), Err(StorageError::RegionTooSmall));
+        assert_eq!(ring.append(1, &[1]), Err(StorageError::RegionTooSmall));
+    }
+}
@@ -0,0 +1,166 @@
+//! Motion detection on the L3GD20 gyroscope.
+//!
+//! Twisting or turning the device in place barely changes the linear
+//! acceleration, so the [`MotionDetector`](crate::motion::MotionDetector) can
+//! miss it while the rotation rate shows it plainly. The gyroscope reads a few
+//! degrees per second even at rest, and that zero-rate level drifts with
+//! temperature, so a slow high-pass filter takes it out before the rate is
+//! averaged over the same window as the acceleration.
+
+use crate::{
+    app_state::AppResetMessage,
+    motion::{self, HighPass, SlidingWindow},
+    profile::AlarmProfile,
+};
+
+/// The zero-rate estimate moves 1/2^`BIAS_SHIFT` of the way towards each
+/// sample, a time constant of 128 samples. That is 2.5 s at the default
+/// sample period, so a turn lasting a second mostly gets through.
+const BIAS_SHIFT: u32 = 7;
+
+/// One gyroscope sample in raw counts, as read from the L3GD20.
+pub type RawRate = [i16; 3];
+
+/// Converts a raw sample to milli-degrees per second per axis, at the
+/// ±250 dps range the firmware sets, 8.75 mdps per count.
+pub fn to_milli_dps(raw: RawRate) -> [i32; 3] {
+    raw.map(|counts| i32::from(counts) * 35 / 4)
+}
+
+/// Rotation above the profile's threshold.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct RotationEvent {
+    /// Largest filtered rate in the window, in degrees per second.
+    pub peak_dps: u16,
+    /// Mean filtered rate over the window, in degrees per second.
+    pub mean_dps: u16,
+}
+
+impl From<RotationEvent> for AppResetMessage {
+    fn from(event: RotationEvent) -> AppResetMessage {
+        AppResetMessage::FromGyroscope {
+            rate: event.peak_dps,
+        }
+    }
+}
+
+/// Turns raw gyroscope samples into [`RotationEvent`]s.
+///
+/// Feed it every sample, taken every `sample_period` of the profile. An event
+/// is reported when the mean rate over the window reaches
+/// `rotation_threshold`, and the window then starts over, as with the
+/// [`MotionDetector`](crate::motion::MotionDetector). Nothing is reported
+/// while `rotation_threshold` is zero.
+#[derive(Debug, Clone)]
+pub struct RotationDetector {
+    threshold_dps: u16,
+    bias: HighPass,
+    window: SlidingWindow,
+}
+
+impl RotationDetector {
+    pub fn new(profile: &AlarmProfile) -> Self {
+        let mut detector = RotationDetector {
+            threshold_dps: profile.rotation_threshold,
+            bias: HighPass::new(BIAS_SHIFT),
+            window: SlidingWindow::new(),
+        };
+        detector.configure(profile);
+        detector
+    }
+    /// Picks up a changed profile. The window starts over, but the zero-rate
+    /// estimate is kept.
+    pub fn configure(&mut self, profile: &AlarmProfile) {
+        self.threshold_dps = profile.rotation_threshold;
+        self.window.resize(motion::window_len(profile));
+    }
+    pub fn update(&mut self, raw: RawRate) -> Option<RotationEvent> {
+        // Keep tracking the zero-rate level while off, so it is settled when
+        // detection is turned back on.
+        let filtered = self.bias.filter(to_milli_dps(raw));
+        if self.threshold_dps == 0 {
+            return None;
+        }
+        let rate = motion::magnitude(filtered.map(|mdps| mdps / 1000));
+        let (peak_dps, mean_dps) = self.window.push(rate, self.threshold_dps)?;
+        Some(RotationEvent { peak_dps, mean_dps })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::time::Duration;
+
+    use super::*;
+
+    /// A zero-rate level of a few dps on every axis.
+    const AT_REST: RawRate = [300, -250, 120];
+
+    fn profile() -> AlarmProfile {
+        AlarmProfile {
+            sample_period: Duration::from_millis(20),
+            rotation_threshold: 30,
+            ..AlarmProfile::default()
+        }
+    }
+
+    /// Counts for `dps` on top of the zero-rate level, about Z.
+    fn turning(dps: i32) -> RawRate {
+        let counts = dps * 4000 / 35;
+        [AT_REST[0], AT_REST[1], AT_REST[2] + counts as i16]
+    }
+
+    #[test]
+    fn converts_counts_to_milli_dps() {
+        assert_eq!(to_milli_dps([1_000, -4, 0]), [8_750, -35, 0]);
+        assert_eq!(to_milli_dps([i16::MAX, 0, 0])[0], 286_711);
+    }
+
+    #[test]
+    fn zero_rate_level_is_not_rotation() {
+        let mut detector = RotationDetector::new(&profile());
+        for i in 0..500 {
+            let noise = if i % 2 == 0 { 60 } else { -60 };
+            assert_eq!(detector.update(AT_REST.map(|axis| axis + noise)), None);
+        }
+        // Drifting by 10 dps over 20 s, as when the board warms up.
+        for i in 0..1_000 {
+            let drift = (i * 1_143 / 1_000) as i16;
+            assert_eq!(detector.update(AT_REST.map(|axis| axis + drift)), None);
+        }
+    }
+
+    #[test]
+    fn detects_a_twist() {
+        let mut detector = RotationDetector::new(&profile());
+        for _ in 0..100 {
+            assert_eq!(detector.update(AT_REST), None);
+        }
+        // A quarter turn in a second.
+        let events: [Option<RotationEvent>; 50] =
+            core::array::from_fn(|_| detector.update(turning(90)));
+        let event = events.iter().flatten().next().copied().unwrap();
+        assert!(event.peak_dps >= 85, "{:?}", event);
+        assert!(event.mean_dps >= 30, "{:?}", event);
+        assert!(event.peak_dps >= event.mean_dps);
+    }
+
+    #[test]
+    fn slow_turn_stays_below_threshold() {
+        let mut detector = RotationDetector::new(&profile());
+        for _ in 0..100 {
+            detector.update(AT_REST);
+        }
+        assert!((0..50).all(|_| detector.update(turning(20)).is_none()));
+    }
+
+    #[test]
+    fn zero_threshold_turns_detection_off() {
+        let mut detector = RotationDetector::new(&AlarmProfile {
+            rotation_threshold: 0,
+            ..profile()
+        });
+        detector.update(AT_REST);
+        assert!((0..50).all(|_| detector.update(turning(200)).is_none()));
+    }
+}
@@ -0,0 +1,349 @@
+//! Weekly monitoring windows, e.g. night shifts.
+//!
+//! Outside every window the alarm is disarmed; an empty schedule monitors
+//! around the clock. The firmware keeps a [`Scheduler`] that turns the
+//! schedule into arm/disarm messages and programs the RTC alarm for
+//! [`Schedule::next_change`] so it can sleep until then.
+
+use core::fmt;
+
+use crate::{
+    app_state::AppResetMessage,
+    calendar::{DateTime, Weekday},
+};
+
+pub const MAX_WINDOWS: usize = 4;
+const MINUTES_PER_DAY: u16 = 24 * 60;
+const MINUTES_PER_WEEK: u16 = 7 * MINUTES_PER_DAY;
+
+/// The weekdays a window starts on.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Days(u8);
+
+impl Days {
+    pub const EVERY_DAY: Days = Days(0x7F);
+    pub const WEEKDAYS: Days = Days(0x1F);
+    pub const WEEKENDS: Days = Days(0x60);
+
+    pub fn from_bits(bits: u8) -> Days {
+        Days(bits & Days::EVERY_DAY.0)
+    }
+    pub fn bits(self) -> u8 {
+        self.0
+    }
+    pub fn contains(self, day: Weekday) -> bool {
+        self.0 & (1 << day.index()) != 0
+    }
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+    /// Parses `daily`, `weekdays`, `weekends` or a comma-separated list such
+    /// as `mon,wed,fri`.
+    pub fn parse(text: &str) -> Option<Days> {
+        match text {
+            "daily" => return Some(Days::EVERY_DAY),
+            "weekdays" => return Some(Days::WEEKDAYS),
+            "weekends" => return Some(Days::WEEKENDS),
+            _ => {}
+        }
+        text.split(',').try_fold(Days(0), |days, name| {
+            Weekday::from_abbreviation(name).map(|day| Days(days.0 | 1 << day.index()))
+        })
+    }
+}
+
+impl fmt::Display for Days {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Days::EVERY_DAY => return f.write_str("daily"),
+            Days::WEEKDAYS => return f.write_str("weekdays"),
+            Days::WEEKENDS => return f.write_str("weekends"),
+            _ => {}
+        }
+        let mut separator = "";
+        for day in Weekday::ALL.into_iter().filter(|&day| self.contains(day)) {
+            write!(f, "{}{}", separator, day.abbreviation())?;
+            separator = ",";
+        }
+        Ok(())
+    }
+}
+
+/// Monitoring from `start` to `end`, both in minutes since midnight, on each
+/// of `days`. A window that ends before it starts runs past midnight into the
+/// next day.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Window {
+    pub days: Days,
+    pub start: u16,
+    pub end: u16,
+}
+
+impl Window {
+    pub fn is_valid(&self) -> bool {
+        !self.days.is_empty()
+            && self.start < MINUTES_PER_DAY
+            && self.end < MINUTES_PER_DAY
+            && self.start != self.end
+    }
+    fn length(&self) -> u16 {
+        (self.end + MINUTES_PER_DAY - self.start) % MINUTES_PER_DAY
+    }
+    /// Where the window opens on each of its days, in minutes since Monday
+    /// midnight.
+    fn openings(&self) -> impl Iterator<Item = u16> + '_ {
+        Weekday::ALL
+            .into_iter()
+            .filter(|&day| self.days.contains(day))
+            .map(|day| u16::from(day.index()) * MINUTES_PER_DAY + self.start)
+    }
+    fn contains(&self, minute_of_week: u16) -> bool {
+        self.openings().any(|opening| {
+            (minute_of_week + MINUTES_PER_WEEK - opening) % MINUTES_PER_WEEK < self.length()
+        })
+    }
+}
+
+impl fmt::Display for Window {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {:02}:{:02} {:02}:{:02}",
+            self.days,
+            self.start / 60,
+            self.start % 60,
+            self.end / 60,
+            self.end % 60
+        )
+    }
+}
+
+/// Parses `HH:MM` into minutes since midnight.
+pub fn parse_time_of_day(text: &str) -> Option<u16> {
+    let (hour, minute) = text.split_once(':')?;
+    let (hour, minute) = (hour.parse::<u16>().ok()?, minute.parse::<u16>().ok()?);
+    (hour < 24 && minute < 60).then_some(hour * 60 + minute)
+}
+
+/// A weekly point in time the RTC alarm can match on.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct WeekTime {
+    pub weekday: Weekday,
+    pub hour: u8,
+    pub minute: u8,
+}
+
+impl WeekTime {
+    fn from_minute_of_week(minute: u16) -> WeekTime {
+        WeekTime {
+            weekday: Weekday::ALL[usize::from(minute / MINUTES_PER_DAY)],
+            hour: (minute % MINUTES_PER_DAY / 60) as u8,
+            minute: (minute % 60) as u8,
+        }
+    }
+}
+
+fn minute_of_week(at: &DateTime) -> u16 {
+    u16::from(at.weekday().index()) * MINUTES_PER_DAY + at.minute_of_day()
+}
+
+/// Up to [`MAX_WINDOWS`] monitoring windows, stored in numbered slots.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub struct Schedule {
+    pub windows: [Option<Window>; MAX_WINDOWS],
+}
+
+impl Schedule {
+    pub fn is_empty(&self) -> bool {
+        self.windows.iter().all(Option::is_none)
+    }
+    fn active(&self) -> impl Iterator<Item = &Window> {
+        self.windows.iter().flatten()
+    }
+    fn monitoring_at(&self, minute_of_week: u16) -> bool {
+        self.is_empty() || self.active().any(|window| window.contains(minute_of_week))
+    }
+    /// Whether the alarm should be armed at `at`.
+    pub fn monitoring(&self, at: &DateTime) -> bool {
+        self.monitoring_at(minute_of_week(at))
+    }
+    /// The next minute after `at` at which [`monitoring`](Self::monitoring)
+    /// changes, or `None` if it never does.
+    pub fn next_change(&self, at: &DateTime) -> Option<WeekTime> {
+        let now = minute_of_week(at);
+        let monitoring = self.monitoring_at(now);
+        // Monitoring can only change where a window opens or closes.
+        self.active()
+            .flat_map(|window| {
+                window.openings().flat_map(move |opening| {
+                    [opening, (opening + window.length()) % MINUTES_PER_WEEK]
+                })
+            })
+            .filter(|&boundary| self.monitoring_at(boundary) != monitoring)
+            .min_by_key(
+                |&boundary| match (boundary + MINUTES_PER_WEEK - now) % MINUTES_PER_WEEK {
+                    0 => MINUTES_PER_WEEK,
+                    ahead => ahead,
+                },
+            )
+            .map(WeekTime::from_minute_of_week)
+    }
+}
+
+/// Turns a [`Schedule`] into arm and disarm messages.
+///
+/// A message is only produced when the schedule changes its mind, so a
+/// manual `arm` or `disarm` holds until the next window opens or closes.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Scheduler {
+    monitoring: Option<bool>,
+}
+
+impl Scheduler {
+    pub const fn new() -> Self {
+        Scheduler { monitoring: None }
+    }
+    /// Re-evaluates `schedule` at `at`. Call it at boot, whenever the RTC
+    /// alarm fires and whenever the schedule or the time is changed.
+    pub fn poll(&mut self, schedule: &Schedule, at: &DateTime) -> Option<AppResetMessage> {
+        if schedule.is_empty() {
+            // Clearing the schedule re-arms a device it had disarmed, as
+            // an empty schedule monitors all the time.
+            return match self.monitoring.take() {
+                Some(false) => Some(AppResetMessage::FromSchedule { arm: true }),
+                _ => None,
+            };
+        }
+        let monitoring = schedule.monitoring(at);
+        if self.monitoring.replace(monitoring) == Some(monitoring) {
+            return None;
+        }
+        Some(AppResetMessage::FromSchedule { arm: monitoring })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use std::string::ToString;
+
+    use super::*;
+
+    /// 2026-10-19 is a Monday.
+    fn at(day: u8, hour: u8, minute: u8) -> DateTime {
+        DateTime::new(2026, 10, 19 + day, hour, minute, 0).unwrap()
+    }
+
+    fn night_shift() -> Schedule {
+        Schedule {
+            windows: [
+                Some(Window {
+                    days: Days::WEEKDAYS,
+                    start: 22 * 60,
+                    end: 6 * 60,
+                }),
+                None,
+                None,
+                None,
+            ],
+        }
+    }
+
+    #[test]
+    fn empty_schedule_always_monitors() {
+        let schedule = Schedule::default();
+        assert!(schedule.monitoring(&at(0, 3, 0)));
+        assert_eq!(schedule.next_change(&at(0, 3, 0)), None);
+    }
+
+    #[test]
+    fn window_runs_past_midnight() {
+        let schedule = night_shift();
+        assert!(!schedule.monitoring(&at(0, 21, 59)));
+        assert!(schedule.monitoring(&at(0, 22, 0)));
+        assert!(schedule.monitoring(&at(1, 5, 59)));
+        assert!(!schedule.monitoring(&at(1, 6, 0)));
+        // Friday night runs into Saturday morning, but Saturday night is off.
+        assert!(schedule.monitoring(&at(5, 3, 0)));
+        assert!(!schedule.monitoring(&at(5, 23, 0)));
+        assert!(!schedule.monitoring(&at(6, 23, 0)));
+    }
+
+    #[test]
+    fn finds_next_change() {
+        let schedule = night_shift();
+        let week_time = |weekday, hour, minute| WeekTime {
+            weekday,
+            hour,
+            minute,
+        };
+        assert_eq!(
+            schedule.next_change(&at(0, 12, 0)),
+            Some(week_time(Weekday::Monday, 22, 0))
+        );
+        assert_eq!(
+            schedule.next_change(&at(0, 22, 0)),
+            Some(week_time(Weekday::Tuesday, 6, 0))
+        );
+        // Over the weekend the next shift is on Monday.
+        assert_eq!(
+            schedule.next_change(&at(5, 6, 0)),
+            Some(week_time(Weekday::Monday, 22, 0))
+        );
+    }
+
+    #[test]
+    fn overlapping_windows_merge() {
+        let mut schedule = night_shift();
+        schedule.windows[2] = Some(Window {
+            days: Days::EVERY_DAY,
+            start: 5 * 60,
+            end: 7 * 60,
+        });
+        assert!(schedule.monitoring(&at(1, 6, 30)));
+        assert_eq!(
+            schedule.next_change(&at(1, 1, 0)),
+            Some(WeekTime {
+                weekday: Weekday::Tuesday,
+                hour: 7,
+                minute: 0,
+            })
+        );
+    }
+
+    #[test]
+    fn scheduler_reports_changes_only() {
+        let schedule = night_shift();
+        let mut scheduler = Scheduler::new();
+        assert_eq!(
+            scheduler.poll(&schedule, &at(0, 12, 0)),
+            Some(AppResetMessage::FromSchedule { arm: false })
+        );
+        assert_eq!(scheduler.poll(&schedule, &at(0, 13, 0)), None);
+        assert_eq!(
+            scheduler.poll(&schedule, &at(0, 22, 0)),
+            Some(AppResetMessage::FromSchedule { arm: true })
+        );
+        assert_eq!(scheduler.poll(&Schedule::default(), &at(0, 23, 0)), None);
+        assert_eq!(
+            scheduler.poll(&schedule, &at(1, 12, 0)),
+            Some(AppResetMessage::FromSchedule { arm: false })
+        );
+        assert_eq!(
+            scheduler.poll(&Schedule::default(), &at(1, 12, 5)),
+            Some(AppResetMessage::FromSchedule { arm: true })
+        );
+        assert_eq!(scheduler.poll(&Schedule::default(), &at(1, 12, 10)), None);
+    }
+
+    #[test]
+    fn days_round_trip() {
+        for text in ["daily", "weekdays", "weekends", "mon,wed,sun"] {
+            assert_eq!(Days::parse(text).unwrap().to_string(), text);
+        }
+        assert_eq!(Days::parse("sat,sun"), Some(Days::WEEKENDS));
+        assert_eq!(Days::parse("mon,funday"), None);
+        assert_eq!(parse_time_of_day("22:30"), Some(22 * 60 + 30));
+        assert_eq!(parse_time_of_day("24:00"), None);
+    }
+}
@@ -0,0 +1,259 @@
+//! The accelerometer as the motion tasks see it.
+//!
+//! [`MotionSensor`] hides where the samples come from, so motion and fall
+//! detection run the same against the LSM303DLHC, a [`ScriptedSensor`] in
+//! tests, or a recorded trace played back by [`TraceSensor`].
+
+use core::{fmt, str::Lines};
+
+use crate::{
+    motion::{RawSample, WakeUpConfig},
+    profile::{AccelOdr, AccelSensitivity, AlarmProfile},
+};
+
+/// An accelerometer delivering calibrated samples.
+pub trait MotionSensor {
+    type Error;
+
+    /// Applies the output data rate and range of `profile`.
+    fn configure(&mut self, profile: &AlarmProfile) -> Result<(), Self::Error>;
+    /// The latest sample in calibrated, left-justified counts.
+    fn accel(&mut self) -> Result<RawSample, Self::Error>;
+    /// Reports motion above the threshold of `config` without being read.
+    fn enable_wake_up(&mut self, config: WakeUpConfig) -> Result<(), Self::Error>;
+    fn disable_wake_up(&mut self) -> Result<(), Self::Error>;
+    /// Gets a sensor that keeps failing talking again. It has lost its
+    /// settings afterwards, so configure it again.
+    fn recover(&mut self) {}
+}
+
+/// [`MotionSensor`] for sensors whose accesses are awaited.
+///
+/// Every [`MotionSensor`] is one too, completing at once.
+// Both firmwares run their executors on one core, so the futures need not
+// be `Send`.
+#[allow(async_fn_in_trait)]
+pub trait AsyncMotionSensor {
+    type Error;
+
+    async fn configure(&mut self, profile: &AlarmProfile) -> Result<(), Self::Error>;
+    async fn accel(&mut self) -> Result<RawSample, Self::Error>;
+    async fn enable_wake_up(&mut self, config: WakeUpConfig) -> Result<(), Self::Error>;
+    async fn disable_wake_up(&mut self) -> Result<(), Self::Error>;
+    async fn recover(&mut self) {}
+}
+
+impl<S: MotionSensor> AsyncMotionSensor for S {
+    type Error = S::Error;
+
+    async fn configure(&mut self, profile: &AlarmProfile) -> Result<(), S::Error> {
+        MotionSensor::configure(self, profile)
+    }
+    async fn accel(&mut self) -> Result<RawSample, S::Error> {
+        MotionSensor::accel(self)
+    }
+    async fn enable_wake_up(&mut self, config: WakeUpConfig) -> Result<(), S::Error> {
+        MotionSensor::enable_wake_up(self, config)
+    }
+    async fn disable_wake_up(&mut self) -> Result<(), S::Error> {
+        MotionSensor::disable_wake_up(self)
+    }
+    async fn recover(&mut self) {
+        MotionSensor::recover(self)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ScriptError {
+    /// The script has a failure at this point.
+    Failed,
+    /// Every step of the script has been played.
+    Ended,
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ScriptError::Failed => "scripted failure",
+            ScriptError::Ended => "script ended",
+        })
+    }
+}
+
+/// Plays back a fixed list of readings, `None` standing for a failed read,
+/// and remembers how it was set up so tests can check.
+#[derive(Debug, Clone)]
+pub struct ScriptedSensor<'a> {
+    script: &'a [Option<RawSample>],
+    next: usize,
+    /// Rate and range of the last `configure`.
+    pub configured: Option<(AccelOdr, AccelSensitivity)>,
+    /// The wake-up interrupt while it is enabled.
+    pub wake_up: Option<WakeUpConfig>,
+    pub recoveries: u32,
+}
+
+impl<'a> ScriptedSensor<'a> {
+    pub fn new(script: &'a [Option<RawSample>]) -> Self {
+        ScriptedSensor {
+            script,
+            next: 0,
+            configured: None,
+            wake_up: None,
+            recoveries: 0,
+        }
+    }
+}
+
+impl MotionSensor for ScriptedSensor<'_> {
+    type Error = ScriptError;
+
+    fn configure(&mut self, profile: &AlarmProfile) -> Result<(), ScriptError> {
+        self.configured = Some((profile.accel_odr, profile.accel_sensitivity));
+        Ok(())
+    }
+    fn accel(&mut self) -> Result<RawSample, ScriptError> {
+        let step = self.script.get(self.next).ok_or(ScriptError::Ended)?;
+        self.next += 1;
+        step.ok_or(ScriptError::Failed)
+    }
+    fn enable_wake_up(&mut self, config: WakeUpConfig) -> Result<(), ScriptError> {
+        self.wake_up = Some(config);
+        Ok(())
+    }
+    fn disable_wake_up(&mut self) -> Result<(), ScriptError> {
+        self.wake_up = None;
+        Ok(())
+    }
+    fn recover(&mut self) {
+        self.recoveries += 1;
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum TraceError {
+    /// The line, counted from 1, is not three comma-separated counts.
+    Malformed(usize),
+    /// Every sample of the trace has been played.
+    Ended,
+}
+
+impl fmt::Display for TraceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TraceError::Malformed(line) => write!(f, "line {} is not x,y,z", line),
+            TraceError::Ended => f.write_str("trace ended"),
+        }
+    }
+}
+
+/// Replays a trace recorded as CSV, one `x,y,z` sample of raw counts per
+/// line under an optional header, like the ones in `alarm-core/testdata`.
+///
+/// The samples are taken as already calibrated, and the trace's own rate and
+/// range as the ones configured.
+#[derive(Debug, Clone)]
+pub struct TraceSensor<'a> {
+    lines: Lines<'a>,
+    line: usize,
+}
+
+impl<'a> TraceSensor<'a> {
+    pub fn new(csv: &'a str) -> Self {
+        TraceSensor {
+            lines: csv.lines(),
+            line: 0,
+        }
+    }
+}
+
+fn parse_sample(line: &str) -> Option<RawSample> {
+    let mut axes = line.split(',').map(|axis| axis.trim().parse().ok());
+    let sample = [axes.next()??, axes.next()??, axes.next()??];
+    axes.next().is_none().then_some(sample)
+}
+
+impl MotionSensor for TraceSensor<'_> {
+    type Error = TraceError;
+
+    fn configure(&mut self, _: &AlarmProfile) -> Result<(), TraceError> {
+        Ok(())
+    }
+    fn accel(&mut self) -> Result<RawSample, TraceError> {
+        loop {
+            let line = self.lines.next().ok_or(TraceError::Ended)?;
+            self.line += 1;
+            if let Some(sample) = parse_sample(line) {
+                return Ok(sample);
+            }
+            // The header, or a blank line at the end.
+            if !(self.line == 1 || line.trim().is_empty()) {
+                return Err(TraceError::Malformed(self.line));
+            }
+        }
+    }
+    fn enable_wake_up(&mut self, _: WakeUpConfig) -> Result<(), TraceError> {
+        Ok(())
+    }
+    fn disable_wake_up(&mut self) -> Result<(), TraceError> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use std::{
+        future::Future,
+        pin::pin,
+        task::{Context, Poll, Waker},
+    };
+
+    // Not the glob: through the blanket impl both traits would apply.
+    use super::{MotionSensor, ScriptError, ScriptedSensor, TraceError, TraceSensor};
+    use crate::{motion::WakeUpConfig, profile::AlarmProfile};
+
+    #[test]
+    fn script_plays_samples_and_failures() {
+        let script = [Some([1, 2, 3]), None, Some([4, 5, 6])];
+        let mut sensor = ScriptedSensor::new(&script);
+        assert_eq!(sensor.accel(), Ok([1, 2, 3]));
+        assert_eq!(sensor.accel(), Err(ScriptError::Failed));
+        assert_eq!(sensor.accel(), Ok([4, 5, 6]));
+        assert_eq!(sensor.accel(), Err(ScriptError::Ended));
+
+        let profile = AlarmProfile::default();
+        sensor.configure(&profile).unwrap();
+        assert_eq!(
+            sensor.configured,
+            Some((profile.accel_odr, profile.accel_sensitivity))
+        );
+        sensor.enable_wake_up(WakeUpConfig::new(&profile)).unwrap();
+        assert_eq!(sensor.wake_up, Some(WakeUpConfig::new(&profile)));
+        sensor.disable_wake_up().unwrap();
+        assert_eq!(sensor.wake_up, None);
+    }
+
+    #[test]
+    fn trace_skips_header_and_flags_bad_lines() {
+        let mut sensor = TraceSensor::new(include_str!("../testdata/pick_up.csv"));
+        assert_eq!(sensor.accel(), Ok([2, -3, 1336]));
+        assert_eq!(sensor.accel(), Ok([-6, 0, 1333]));
+
+        let mut sensor = TraceSensor::new("1, 2, 3\n4,5\n\n");
+        assert_eq!(sensor.accel(), Ok([1, 2, 3]));
+        assert_eq!(sensor.accel(), Err(TraceError::Malformed(2)));
+        assert_eq!(sensor.accel(), Err(TraceError::Ended));
+    }
+
+    #[test]
+    fn blocking_sensors_complete_at_once_when_awaited() {
+        let script = [Some([7, 8, 9])];
+        let mut sensor = ScriptedSensor::new(&script);
+        let mut read = pin!(super::AsyncMotionSensor::accel(&mut sensor));
+        assert_eq!(
+            read.as_mut().poll(&mut Context::from_waker(Waker::noop())),
+            Poll::Ready(Ok([7, 8, 9]))
+        );
+    }
+}
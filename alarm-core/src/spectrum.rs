@@ -0,0 +1,424 @@
+//! Finds steady machine vibration in the accelerometer samples and masks it
+//! out of the motion decision.
+//!
+//! The samples are cut into windows of [`WINDOW_LEN`], gravity is taken out
+//! of each axis as the window's mean, and the power spectra of the three axes
+//! are added up. A window whose strongest band, `vibration_band_hz` either
+//! side of its peak, holds `vibration_peak_pct` of the power is narrow-band.
+//! Once the peak has stayed in place for [`VIBRATION_SETTLE`] the band is
+//! masked: motion only counts while what is left of the window outside it
+//! still reaches `motion_threshold_mg`. The mask holds until the band loses
+//! most of its power, when the machine stops or changes speed.
+//!
+//! Frequencies follow from `sample_period`: a window lasts [`WINDOW_LEN`]
+//! periods and its bins are one over that apart, up to the Nyquist limit of
+//! half the sampling rate. At the default 20 ms that is 0.78 Hz up to 25 Hz;
+//! at the LSM303's full 100 Hz, with a period of 10 ms, 1.6 Hz up to 50 Hz.
+//! Faster vibration folds back below the limit. It is still masked if it is
+//! steady, but at the wrong frequency. The samples have to come at a fixed
+//! rate, so the firmware takes them on deadlines rather than sleeping a
+//! period after each.
+
+use core::time::Duration;
+
+use crate::{
+    motion::{self, RawSample},
+    profile::{AccelSensitivity, AlarmProfile},
+};
+
+/// Samples per window. A power of two for the FFT.
+pub const WINDOW_LEN: usize = 64;
+/// How long a narrow-band peak has to hold its frequency to be masked.
+pub const VIBRATION_SETTLE: Duration = Duration::from_secs(5);
+/// Bins up to DC and Nyquist, neither of which is searched for a peak.
+const BINS: usize = WINDOW_LEN / 2;
+/// How far the peak may wander between windows and still be the same one.
+const PEAK_DRIFT_BINS: usize = 1;
+/// The mask is lifted once its band holds less than 1/`RELEASE_RATIO` of the
+/// power it had when it was set.
+const RELEASE_RATIO: u64 = 4;
+const TWIDDLE_BITS: u32 = 14;
+/// `sin(2πk/WINDOW_LEN)` for the first quarter turn, scaled by
+/// 2^`TWIDDLE_BITS`.
+const QUARTER_SINE: [i64; WINDOW_LEN / 4 + 1] = [
+    0, 1606, 3196, 4756, 6270, 7723, 9102, 10394, 11585, 12665, 13623, 14449, 15137, 15679, 16069,
+    16305, 16384,
+];
+
+/// A masked vibration.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Vibration {
+    /// Frequency of the peak, in thousandths of a hertz.
+    pub milli_hz: u32,
+    /// RMS acceleration in the masked band, in milli-g.
+    pub amplitude_mg: u32,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Lock {
+    bin: usize,
+    power: u64,
+}
+
+/// Masks steady narrow-band vibration out of the motion decision.
+///
+/// Feed it every sample, taken every `sample_period` of the profile, and ask
+/// [`permits`](Self::permits) before counting a
+/// [`MotionEvent`](crate::motion::MotionEvent).
+#[derive(Debug, Clone)]
+pub struct VibrationMask {
+    sensitivity: AccelSensitivity,
+    period_ms: u32,
+    /// Half the width of the band in bins, or `None` when masking is off.
+    band_bins: Option<usize>,
+    peak_pct: u64,
+    threshold: u32,
+    settle: usize,
+    samples: [[i16; 3]; WINDOW_LEN],
+    len: usize,
+    /// The narrow-band peak of the last window and for how many windows in a
+    /// row it has held.
+    candidate: Option<usize>,
+    steady: usize,
+    lock: Option<Lock>,
+    /// RMS of the last window outside the masked band, in milli-g.
+    residual_mg: u32,
+}
+
+impl VibrationMask {
+    pub fn new(profile: &AlarmProfile) -> Self {
+        let mut mask = VibrationMask {
+            sensitivity: profile.accel_sensitivity,
+            period_ms: 1,
+            band_bins: None,
+            peak_pct: 0,
+            threshold: 0,
+            settle: 1,
+            samples: [[0; 3]; WINDOW_LEN],
+            len: 0,
+            candidate: None,
+            steady: 0,
+            lock: None,
+            residual_mg: 0,
+        };
+        mask.configure(profile);
+        mask
+    }
+    /// Picks up a changed profile. Whatever was masked has to settle again.
+    pub fn configure(&mut self, profile: &AlarmProfile) {
+        let period = profile.sample_period.as_millis().max(1);
+        self.sensitivity = profile.accel_sensitivity;
+        self.period_ms = u32::try_from(period).unwrap_or(u32::MAX);
+        let window_ms = u64::from(self.period_ms) * WINDOW_LEN as u64;
+        // At least one bin either side, or the peak alone would be masked.
+        self.band_bins = profile.masks_vibration().then(|| {
+            (u64::from(profile.vibration_band_hz) * window_ms)
+                .div_ceil(1000)
+                .max(1) as usize
+        });
+        self.peak_pct = u64::from(profile.vibration_peak_pct);
+        self.threshold = u32::from(profile.motion_threshold);
+        self.settle = (VIBRATION_SETTLE.as_millis() as u64)
+            .div_ceil(window_ms)
+            .max(1) as usize;
+        self.len = 0;
+        self.candidate = None;
+        self.steady = 0;
+        self.lock = None;
+        self.residual_mg = 0;
+    }
+    /// Takes a sample. Returns the vibration when a window completes and
+    /// starts masking it.
+    pub fn update(&mut self, raw: RawSample) -> Option<Vibration> {
+        self.band_bins?;
+        let mg = motion::to_milli_g(raw, self.sensitivity);
+        self.samples[self.len] = mg.map(|axis| axis.clamp(i16::MIN.into(), i16::MAX.into()) as i16);
+        self.len += 1;
+        if self.len < WINDOW_LEN {
+            return None;
+        }
+        self.len = 0;
+        let power = self.power_spectrum();
+        let was_masking = self.lock.is_some();
+        self.analyse(&power);
+        self.vibration().filter(|_| !was_masking)
+    }
+    /// The vibration being masked, if any.
+    pub fn vibration(&self) -> Option<Vibration> {
+        let lock = self.lock?;
+        let window_ms = u64::from(self.period_ms) * WINDOW_LEN as u64;
+        Some(Vibration {
+            milli_hz: (lock.bin as u64 * 1_000_000 / window_ms) as u32,
+            amplitude_mg: rms_mg(lock.power),
+        })
+    }
+    /// Whether motion detected now may count: nothing is masked, or the last
+    /// window still reaches the motion threshold without the masked band.
+    pub fn permits(&self) -> bool {
+        self.lock.is_none() || self.residual_mg >= self.threshold
+    }
+    /// Sum of the power spectra of the axes, Hann windowed so that a
+    /// vibration between two bins does not smear across the spectrum.
+    fn power_spectrum(&self) -> [u64; BINS] {
+        let mut power = [0; BINS];
+        for axis in 0..3 {
+            let sum: i32 = self.samples.iter().map(|s| i32::from(s[axis])).sum();
+            let mean = sum / WINDOW_LEN as i32;
+            let mut re = self.samples.map(|s| i32::from(s[axis]) - mean);
+            let mut im = [0; WINDOW_LEN];
+            fft(&mut re, &mut im);
+            // Windowing is a convolution in frequency: four times the Hann
+            // window's bin k is 2X[k] - X[k-1] - X[k+1].
+            for (k, bin) in power.iter_mut().enumerate().skip(1) {
+                let hann = |x: &[i32; WINDOW_LEN]| {
+                    i64::from(2 * x[k]) - i64::from(x[k - 1]) - i64::from(x[k + 1])
+                };
+                let (r, i) = (hann(&re), hann(&im));
+                *bin += (r * r + i * i) as u64;
+            }
+        }
+        power
+    }
+    fn analyse(&mut self, power: &[u64; BINS]) {
+        let Some(width) = self.band_bins else {
+            return;
+        };
+        let band = |bin: usize| -> u64 {
+            power[bin.saturating_sub(width).max(1)..=(bin + width).min(BINS - 1)]
+                .iter()
+                .sum()
+        };
+        let total: u64 = power.iter().sum();
+        let peak = (1..BINS).max_by_key(|&bin| power[bin]).unwrap_or(1);
+        let narrow = total > 0 && band(peak) * 100 >= total * self.peak_pct;
+        let near = |a: usize, b: usize| a.abs_diff(b) <= PEAK_DRIFT_BINS;
+
+        self.steady = match self.candidate {
+            Some(candidate) if narrow && near(candidate, peak) => self.steady + 1,
+            _ if narrow => 1,
+            _ => 0,
+        };
+        self.candidate = narrow.then_some(peak);
+        self.lock = match self.lock {
+            // A machine drifts a little with load.
+            Some(lock) if narrow && near(lock.bin, peak) => Some(Lock {
+                bin: peak,
+                power: band(peak),
+            }),
+            Some(lock) if band(lock.bin) * RELEASE_RATIO >= lock.power => Some(lock),
+            Some(_) => None,
+            None if self.steady >= self.settle => Some(Lock {
+                bin: peak,
+                power: band(peak),
+            }),
+            None => None,
+        };
+        let masked = self.lock.map_or(0, |lock| band(lock.bin));
+        self.residual_mg = rms_mg(total - masked);
+    }
+}
+
+/// RMS acceleration in milli-g of a share of [`VibrationMask::power_spectrum`].
+///
+/// By Parseval the mean square is `2/N²` of the power in the bins below
+/// Nyquist; the spectrum is four times the Hann window's, whose mean square
+/// is 3/8, which leaves a third.
+fn rms_mg(power: u64) -> u32 {
+    motion::isqrt(power / (3 * WINDOW_LEN * WINDOW_LEN) as u64) as u32
+}
+
+/// `(cos, sin)` of `2πk/WINDOW_LEN` for `k` in the first half turn.
+fn twiddle(k: usize) -> (i64, i64) {
+    const QUARTER: usize = WINDOW_LEN / 4;
+    if k <= QUARTER {
+        (QUARTER_SINE[QUARTER - k], QUARTER_SINE[k])
+    } else {
+        (-QUARTER_SINE[k - QUARTER], QUARTER_SINE[2 * QUARTER - k])
+    }
+}
+
+/// In-place radix-2 FFT in fixed point.
+fn fft(re: &mut [i32; WINDOW_LEN], im: &mut [i32; WINDOW_LEN]) {
+    let bits = WINDOW_LEN.trailing_zeros();
+    for i in 0..WINDOW_LEN {
+        let j = i.reverse_bits() >> (usize::BITS - bits);
+        if j > i {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+    let mut len = 2;
+    while len <= WINDOW_LEN {
+        let half = len / 2;
+        for start in (0..WINDOW_LEN).step_by(len) {
+            for j in 0..half {
+                let (cos, sin) = twiddle(j * WINDOW_LEN / len);
+                let (a, b) = (start + j, start + j + half);
+                let (br, bi) = (i64::from(re[b]), i64::from(im[b]));
+                // Times e^(-iθ).
+                let tr = ((cos * br + sin * bi) >> TWIDDLE_BITS) as i32;
+                let ti = ((cos * bi - sin * br) >> TWIDDLE_BITS) as i32;
+                re[b] = re[a] - tr;
+                im[b] = im[a] - ti;
+                re[a] += tr;
+                im[a] += ti;
+            }
+        }
+        len *= 2;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+    use std::vec::Vec;
+
+    use super::*;
+
+    const HZ: f64 = 100.0;
+
+    /// 100 Hz at ±16 g, with masking on.
+    fn profile() -> AlarmProfile {
+        AlarmProfile {
+            sample_period: Duration::from_millis(10),
+            accel_sensitivity: AccelSensitivity::G12,
+            vibration_band_hz: 2,
+            ..AlarmProfile::default()
+        }
+    }
+
+    /// Raw counts at ±16 g of gravity along Z plus the given milli-g per axis.
+    fn raw([x, y, z]: [f64; 3]) -> RawSample {
+        [x, y, 1000.0 + z].map(|mg| (mg * 16.0 / 12.0).round() as i16)
+    }
+
+    fn sine(hz: f64, amplitude: f64, i: usize) -> f64 {
+        amplitude * (2.0 * core::f64::consts::PI * hz * i as f64 / HZ).sin()
+    }
+
+    /// Ten seconds of samples.
+    fn run(mask: &mut VibrationMask, sample: impl Fn(usize) -> [f64; 3]) -> Vec<Vibration> {
+        (0..1000)
+            .filter_map(|i| mask.update(raw(sample(i))))
+            .collect()
+    }
+
+    #[test]
+    fn fft_finds_a_sinusoid() {
+        let mut re = core::array::from_fn(|i| sine(12.5, 1000.0, i) as i32);
+        let mut im = [0; WINDOW_LEN];
+        fft(&mut re, &mut im);
+        // 12.5 Hz at 100 Hz is bin 8; a real sine puts N/2 of its amplitude
+        // there.
+        let power = |k: usize| i64::from(re[k]).pow(2) + i64::from(im[k]).pow(2);
+        let peak = (1..BINS).max_by_key(|&k| power(k)).unwrap();
+        assert_eq!(peak, 8);
+        assert!((power(8) as f64).sqrt() > 31_000.0);
+        assert!((1..BINS).filter(|&k| k != 8).all(|k| power(k) < 100));
+    }
+
+    #[test]
+    fn frequency_follows_the_sample_period() {
+        // 50 samples a second.
+        let profile = AlarmProfile {
+            sample_period: Duration::from_millis(20),
+            ..profile()
+        };
+        let wave = |hz: f64, i: usize| sine(hz * HZ / 50.0, 200.0, i);
+        let mut mask = VibrationMask::new(&profile);
+        run(&mut mask, |i| [wave(10.0, i), 0.0, 0.0]);
+        let vibration = mask.vibration().unwrap();
+        assert!(
+            vibration.milli_hz.abs_diff(10_000) <= 800,
+            "{:?}",
+            vibration
+        );
+        // Above the 25 Hz limit, 30 Hz folds back to 20 Hz.
+        mask.configure(&profile);
+        run(&mut mask, |i| [wave(30.0, i), 0.0, 0.0]);
+        let vibration = mask.vibration().unwrap();
+        assert!(
+            vibration.milli_hz.abs_diff(20_000) <= 800,
+            "{:?}",
+            vibration
+        );
+    }
+
+    #[test]
+    fn masks_steady_vibration() {
+        let mut mask = VibrationMask::new(&profile());
+        let found = run(&mut mask, |i| {
+            [sine(23.0, 200.0, i), 0.0, sine(23.0, 100.0, i)]
+        });
+        assert_eq!(found.len(), 1);
+        let vibration = mask.vibration().unwrap();
+        assert!(
+            vibration.milli_hz.abs_diff(23_000) <= 1_600,
+            "{:?}",
+            vibration
+        );
+        // 200 mg and 100 mg peaks on two axes are 158 mg RMS.
+        assert!(
+            (140..=175).contains(&vibration.amplitude_mg),
+            "{:?}",
+            vibration
+        );
+        assert!(!mask.permits());
+    }
+
+    #[test]
+    fn motion_on_top_of_vibration_counts() {
+        let mut mask = VibrationMask::new(&profile());
+        let machine = |i| sine(23.0, 200.0, i);
+        run(&mut mask, |i| [machine(i), 0.0, 0.0]);
+        assert!(!mask.permits());
+        // Someone lifting an arm while the machine runs.
+        run(&mut mask, |i| {
+            [machine(i), sine(1.5, 300.0, i), sine(0.7, 400.0, i)]
+        });
+        assert!(mask.vibration().is_some());
+        assert!(mask.permits());
+    }
+
+    #[test]
+    fn mask_lifts_when_the_machine_stops() {
+        let mut mask = VibrationMask::new(&profile());
+        run(&mut mask, |i| [sine(40.0, 150.0, i), 0.0, 0.0]);
+        assert!(mask.vibration().is_some());
+        run(&mut mask, |_| [0.0; 3]);
+        assert_eq!(mask.vibration(), None);
+        assert!(mask.permits());
+    }
+
+    #[test]
+    fn wandering_or_broadband_signals_are_not_masked() {
+        let mut mask = VibrationMask::new(&profile());
+        // A sweep from 5 Hz to 45 Hz, as a machine spinning up.
+        let sweep = |i: usize| {
+            let t = i as f64 / HZ;
+            let phase = 2.0 * core::f64::consts::PI * (5.0 * t + 2.0 * t * t);
+            [200.0 * phase.sin(), 0.0, 0.0]
+        };
+        assert!(run(&mut mask, sweep).is_empty());
+        // Noise, from the splitmix64 hash of the sample number.
+        let noise = |i: usize| {
+            let mut n = (i as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+            n = (n ^ (n >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+            n = (n ^ (n >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+            n ^= n >> 31;
+            [(n % 400) as f64 - 200.0, 0.0, 0.0]
+        };
+        assert!(run(&mut mask, noise).is_empty());
+        assert!(mask.permits());
+    }
+
+    #[test]
+    fn off_when_the_band_is_zero() {
+        let mut mask = VibrationMask::new(&AlarmProfile {
+            vibration_band_hz: 0,
+            ..profile()
+        });
+        assert!(run(&mut mask, |i| [sine(23.0, 200.0, i), 0.0, 0.0]).is_empty());
+        assert!(mask.permits());
+    }
+}
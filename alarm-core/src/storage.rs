@@ -0,0 +1,322 @@
+use core::time::Duration;
+
+use crate::{
+    flash::FlashRegion,
+    profile::{AccelOdr, AccelSensitivity, AlarmProfile, MotionSource},
+    ring::{FlashRing, StorageError, RECORD_OVERHEAD},
+    schedule::{Days, Schedule, Window, MAX_WINDOWS},
+};
+
+/// Layout version of the stored payload. Bump it whenever the payload changes
+/// and give [`decode_payload`] an arm for the new version.
+pub const SCHEMA_VERSION: u16 = 1;
+const MAGIC: u16 = 0xA1C5;
+/// Every record takes the same space, so slots can be located without
+/// parsing what came before them. The payload leaves room for settings added
+/// later, so they take reserved bytes rather than narrowing existing fields.
+pub const RECORD_SIZE: usize = 128;
+
+/// Keeps the alarm profile in a reserved flash region.
+///
+/// Each save appends a record to a [`FlashRing`], so a page is erased only
+/// once per `page_size / RECORD_SIZE` saves. Loading picks the newest intact
+/// record, so a save interrupted by power loss only loses itself.
+pub struct ConfigStore<F> {
+    ring: FlashRing<F, RECORD_SIZE>,
+}
+
+impl<F: FlashRegion> ConfigStore<F> {
+    pub fn new(flash: F) -> Self {
+        ConfigStore {
+            ring: FlashRing::new(flash, MAGIC),
+        }
+    }
+    pub fn release(self) -> F {
+        self.ring.release()
+    }
+    /// Returns the newest stored profile, or `None` if the region is blank or
+    /// holds nothing this firmware can read.
+    pub fn load(&mut self) -> Result<Option<AlarmProfile>, StorageError<F::Error>> {
+        // Records we cannot decode still take part in the sequence so the
+        // next save lands after them.
+        self.ring.scan()?;
+        let mut newest = None;
+        self.ring.for_each(|version, _, payload| {
+            if let Some(profile) =
+                decode_payload(version, payload).filter(|profile| profile.validate().is_ok())
+            {
+                newest = Some(profile);
+            }
+        })?;
+        Ok(newest)
+    }
+    /// The stored profile, or the factory defaults when there is none.
+    pub fn load_or_default(&mut self) -> AlarmProfile {
+        self.load().ok().flatten().unwrap_or_default()
+    }
+    pub fn save(&mut self, profile: &AlarmProfile) -> Result<(), StorageError<F::Error>> {
+        let mut payload = [0; RECORD_SIZE - RECORD_OVERHEAD];
+        encode_payload(profile, &mut payload);
+        self.ring.append(SCHEMA_VERSION, &payload)?;
+        Ok(())
+    }
+}
+
+/// Schedule windows, each `days u8 | start u16 | end u16`, start here.
+const WINDOWS_AT: usize = 42;
+const WINDOW_SIZE: usize = 5;
+/// End of the fields in use; the rest of the payload is reserved and written
+/// as zero.
+const LAYOUT_END: usize = WINDOWS_AT + MAX_WINDOWS * WINDOW_SIZE;
+const _: () = assert!(LAYOUT_END <= RECORD_SIZE - RECORD_OVERHEAD);
+
+/// Payload layout, little endian, durations in milliseconds:
+///
+/// ```text
+/// 0  inactivity u32     | 4  pre-alarm u32    | 8  emergency u32
+/// 12 sample period u32  | 16 fall stillness u32 | 20 tip-over hold u32
+/// 24 motion mg u16      | 26 ODR Hz u16       | 28 free fall mg u16
+/// 30 impact mg u16      | 32 rotation dps u16 | 34 tip-over deg u16
+/// 36 vibration Hz u16   | 38 vibration % u16  | 40 range g u8
+/// 41 motion source u8   | 42 schedule windows
+/// ```
+fn encode_payload(profile: &AlarmProfile, payload: &mut [u8]) {
+    // Saturated values fail validation on load rather than wrap into valid
+    // ones.
+    let mut millis = |i: usize, d: Duration| {
+        let millis = u32::try_from(d.as_millis()).unwrap_or(u32::MAX);
+        payload[i..i + 4].copy_from_slice(&millis.to_le_bytes());
+    };
+    millis(0, profile.inactivity_timeout);
+    millis(4, profile.pre_alarm_timeout);
+    millis(8, profile.emergency_timeout);
+    millis(12, profile.sample_period);
+    millis(16, profile.fall_stillness);
+    millis(20, profile.tip_over_hold);
+    let mut u16_at = |i: usize, value: u16| payload[i..i + 2].copy_from_slice(&value.to_le_bytes());
+    u16_at(24, profile.motion_threshold);
+    u16_at(26, profile.accel_odr.hz());
+    u16_at(28, profile.free_fall_threshold);
+    u16_at(30, profile.impact_threshold);
+    u16_at(32, profile.rotation_threshold);
+    u16_at(34, profile.tip_over_angle);
+    u16_at(36, profile.vibration_band_hz);
+    u16_at(38, profile.vibration_peak_pct);
+    payload[40] = profile.accel_sensitivity.range_g();
+    payload[41] = profile.motion_source as u8;
+    for (slot, window) in profile.schedule.windows.iter().enumerate() {
+        let i = WINDOWS_AT + slot * WINDOW_SIZE;
+        // An empty slot is stored as a window without days.
+        let window = window.unwrap_or(Window {
+            days: Days::from_bits(0),
+            start: 0,
+            end: 0,
+        });
+        payload[i] = window.days.bits();
+        payload[i + 1..i + 3].copy_from_slice(&window.start.to_le_bytes());
+        payload[i + 3..i + 5].copy_from_slice(&window.end.to_le_bytes());
+    }
+    payload[LAYOUT_END..].fill(0);
+}
+
+/// Reads a payload written with schema version 1, the only one so far.
+/// Any other version gives `None`, so the record is skipped and the store
+/// falls back to an earlier record or the defaults.
+fn decode_payload(version: u16, payload: &[u8]) -> Option<AlarmProfile> {
+    let u16_at = |i: usize| u16::from_le_bytes([payload[i], payload[i + 1]]);
+    let millis_at = |i: usize| {
+        Duration::from_millis(u64::from(u32::from_le_bytes(
+            payload[i..i + 4].try_into().unwrap(),
+        )))
+    };
+    match version {
+        1 => {
+            let mut schedule = Schedule::default();
+            for (slot, window) in schedule.windows.iter_mut().enumerate() {
+                let i = WINDOWS_AT + slot * WINDOW_SIZE;
+                let days = Days::from_bits(payload[i]);
+                *window = (!days.is_empty()).then(|| Window {
+                    days,
+                    start: u16_at(i + 1),
+                    end: u16_at(i + 3),
+                });
+            }
+            Some(AlarmProfile {
+                inactivity_timeout: millis_at(0),
+                pre_alarm_timeout: millis_at(4),
+                emergency_timeout: millis_at(8),
+                sample_period: millis_at(12),
+                fall_stillness: millis_at(16),
+                tip_over_hold: millis_at(20),
+                motion_threshold: u16_at(24),
+                accel_odr: AccelOdr::from_hz(u16_at(26))?,
+                free_fall_threshold: u16_at(28),
+                impact_threshold: u16_at(30),
+                rotation_threshold: u16_at(32),
+                tip_over_angle: u16_at(34),
+                vibration_band_hz: u16_at(36),
+                vibration_peak_pct: u16_at(38),
+                accel_sensitivity: AccelSensitivity::from_range_g(payload[40])?,
+                motion_source: *MotionSource::ALL.get(usize::from(payload[41]))?,
+                schedule,
+            })
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flash::mock::{MockFlash, MockFlashError};
+
+    const PAGE_SIZE: usize = 256;
+    const SLOTS_PER_PAGE: usize = PAGE_SIZE / RECORD_SIZE;
+
+    fn store() -> ConfigStore<MockFlash> {
+        ConfigStore::new(MockFlash::new(PAGE_SIZE, 2))
+    }
+
+    fn profile(threshold: u16) -> AlarmProfile {
+        AlarmProfile {
+            motion_threshold: threshold,
+            ..AlarmProfile::default()
+        }
+    }
+
+    #[test]
+    fn blank_region_loads_defaults() {
+        let mut store = store();
+        assert_eq!(store.load(), Ok(None));
+        assert_eq!(store.load_or_default(), AlarmProfile::default());
+    }
+
+    #[test]
+    fn round_trips_through_reboot() {
+        let mut store = store();
+        store.load().unwrap();
+        let saved = AlarmProfile {
+            inactivity_timeout: Duration::from_secs(90),
+            accel_odr: AccelOdr::Hz50,
+            accel_sensitivity: AccelSensitivity::G4,
+            impact_threshold: 3000,
+            fall_stillness: Duration::from_secs(5),
+            rotation_threshold: 45,
+            tip_over_angle: 70,
+            tip_over_hold: Duration::from_millis(7_500),
+            vibration_band_hz: 3,
+            vibration_peak_pct: 45,
+            ..AlarmProfile::default()
+        };
+        store.save(&saved).unwrap();
+
+        let mut rebooted = ConfigStore::new(store.release());
+        assert_eq!(rebooted.load(), Ok(Some(saved)));
+    }
+
+    #[test]
+    fn newest_record_wins_across_wraparound() {
+        let mut store = store();
+        store.load().unwrap();
+        for threshold in 1..=(3 * SLOTS_PER_PAGE as u16 + 1) {
+            store.save(&profile(threshold)).unwrap();
+            let mut rebooted = ConfigStore::new(store.release());
+            assert_eq!(rebooted.load(), Ok(Some(profile(threshold))));
+            store = rebooted;
+        }
+    }
+
+    #[test]
+    fn spreads_erases_over_pages() {
+        let mut store = store();
+        store.load().unwrap();
+        for threshold in 1..=(8 * SLOTS_PER_PAGE as u16) {
+            store.save(&profile(threshold)).unwrap();
+        }
+        assert_eq!(store.release().erase_counts, [4, 4]);
+    }
+
+    #[test]
+    fn corrupt_record_falls_back_to_previous() {
+        let mut store = store();
+        store.load().unwrap();
+        store.save(&profile(10)).unwrap();
+        store.save(&profile(20)).unwrap();
+        let mut flash = store.release();
+        flash.data[RECORD_SIZE + RECORD_OVERHEAD] ^= 0x01;
+
+        let mut rebooted = ConfigStore::new(flash);
+        assert_eq!(rebooted.load(), Ok(Some(profile(10))));
+    }
+
+    #[test]
+    fn fully_corrupt_region_loads_defaults() {
+        let mut flash = MockFlash::new(PAGE_SIZE, 2);
+        flash.data.fill(0x00);
+        let mut store = ConfigStore::new(flash);
+        assert_eq!(store.load_or_default(), AlarmProfile::default());
+    }
+
+    #[test]
+    fn power_loss_mid_save_keeps_previous_and_recovers() {
+        let mut store = store();
+        store.load().unwrap();
+        store.save(&profile(10)).unwrap();
+        let mut flash = store.release();
+        flash.bytes_until_power_loss = Some(RECORD_SIZE / 2);
+        let mut store = ConfigStore::new(flash);
+        store.load().unwrap();
+        assert_eq!(
+            store.save(&profile(20)),
+            Err(StorageError::Flash(MockFlashError::PowerLoss))
+        );
+
+        let mut flash = store.release();
+        flash.bytes_until_power_loss = None;
+        let mut rebooted = ConfigStore::new(flash);
+        assert_eq!(rebooted.load(), Ok(Some(profile(10))));
+        // The half-written slot is skipped rather than erased along with the
+        // good record in front of it.
+        rebooted.save(&profile(30)).unwrap();
+        let flash = rebooted.release();
+        assert_eq!(flash.erase_counts, [1, 1]);
+        let mut rebooted = ConfigStore::new(flash);
+        assert_eq!(rebooted.load(), Ok(Some(profile(30))));
+    }
+
+    #[test]
+    fn unknown_schema_is_ignored_but_sequenced() {
+        let mut store = store();
+        store.load().unwrap();
+        store.save(&profile(10)).unwrap();
+        let mut payload = [0; RECORD_SIZE - RECORD_OVERHEAD];
+        encode_payload(&profile(20), &mut payload);
+        store.ring.append(SCHEMA_VERSION + 1, &payload).unwrap();
+        store.ring.append(0, &payload).unwrap();
+
+        let mut rebooted = ConfigStore::new(store.release());
+        assert_eq!(rebooted.load(), Ok(Some(profile(10))));
+        rebooted.save(&profile(30)).unwrap();
+        let mut rebooted = ConfigStore::new(rebooted.release());
+        assert_eq!(rebooted.load(), Ok(Some(profile(30))));
+    }
+
+    #[test]
+    fn invalid_stored_values_are_rejected() {
+        let mut store = store();
+        store.load().unwrap();
+        store.save(&profile(0)).unwrap();
+        let mut rebooted = ConfigStore::new(store.release());
+        assert_eq!(rebooted.load(), Ok(None));
+    }
+
+    #[test]
+    fn rejects_single_page_region() {
+        let mut store = ConfigStore::new(MockFlash::new(PAGE_SIZE, 1));
+        assert_eq!(store.load(), Err(StorageError::RegionTooSmall));
+        assert_eq!(
+            store.save(&AlarmProfile::default()),
+            Err(StorageError::RegionTooSmall)
+        );
+    }
+}
@@ -0,0 +1,342 @@
+//! Which way the board is tilted, from the gravity the accelerometer measures
+//! while it is held still.
+//!
+//! Angles use the board's compass directions: the sensor's −X axis points at
+//! the north LED and +Y at the east LED, and at rest the accelerometer reads
+//! +1 g straight up out of the board's face.
+//!
+//! [`TipOverDetector`] compares the direction of gravity with the one it
+//! learns when the alarm is armed, to tell when the device or its wearer has
+//! toppled over.
+
+use core::time::Duration;
+
+use crate::{
+    app_state::AppResetMessage,
+    compass::{atan2_degrees, CompassPoint},
+    fall::{ONE_G_MG, STILLNESS_TOLERANCE_MG},
+    motion::{self, RawSample},
+    profile::{AccelSensitivity, AlarmProfile},
+};
+
+/// Tilt in degrees up to which the board counts as level.
+pub const LEVEL_TOLERANCE: u16 = 2;
+/// Degrees of tilt per extra pair of LEDs that the bubble level lights.
+pub const LEVEL_LED_STEP: u16 = 15;
+/// How long the device is watched after arming to learn its baseline.
+pub const BASELINE_TIME: Duration = Duration::from_secs(1);
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Tilt {
+    /// Degrees the north edge is lowered, negative while it is raised.
+    pub pitch: i16,
+    /// Degrees the east edge is lowered, negative while it is raised.
+    pub roll: i16,
+    /// Degrees between the board's face and straight up: 0 lying flat, 90 on
+    /// an edge, 180 face down.
+    pub inclination: u16,
+    /// Where the lowest edge lies, in degrees clockwise from north, or `None`
+    /// lying exactly flat either way up.
+    pub downhill: Option<u16>,
+}
+
+impl Tilt {
+    /// The tilt for an acceleration in milli-g, or `None` without a reading.
+    pub fn from_accel(accel_mg: [i32; 3]) -> Option<Tilt> {
+        let [x, y, z] = accel_mg;
+        if accel_mg == [0; 3] {
+            return None;
+        }
+        let length = |u: i32, v: i32| i64::from(motion::magnitude([u, v, 0]));
+        // From -90° to 90°, as `along` is against a length.
+        let signed = |along: i32, across: i64| {
+            let angle = atan2_degrees(i64::from(along), across) as i16;
+            if angle > 180 {
+                angle - 360
+            } else {
+                angle
+            }
+        };
+        let horizontal = length(x, y);
+        // Gravity pulls towards the lowest edge, against what is measured.
+        Some(Tilt {
+            pitch: signed(x, length(y, z)),
+            roll: signed(-y, length(x, z)),
+            inclination: atan2_degrees(horizontal, i64::from(z)),
+            downhill: (horizontal != 0).then(|| atan2_degrees(-i64::from(y), i64::from(x))),
+        })
+    }
+    pub fn is_level(&self) -> bool {
+        self.inclination <= LEVEL_TOLERANCE
+    }
+    /// The LEDs a bubble level lights, in the order of [`CompassPoint::ALL`].
+    ///
+    /// All of them while level. Otherwise the one towards the lowest edge and
+    /// one more either side for every [`LEVEL_LED_STEP`] degrees, up to seven
+    /// so that the highest edge stays dark. None while lying face down.
+    pub fn level_leds(&self) -> [bool; 8] {
+        if self.is_level() {
+            return [true; 8];
+        }
+        let Some(downhill) = self.downhill else {
+            return [false; 8];
+        };
+        let point = CompassPoint::from_bearing(downhill);
+        let centre = CompassPoint::ALL.iter().position(|&p| p == point);
+        let centre = centre.unwrap_or(0);
+        let reach = usize::from((self.inclination / LEVEL_LED_STEP).min(3));
+        core::array::from_fn(|index| {
+            let apart = (index + 8 - centre) % 8;
+            apart.min(8 - apart) <= reach
+        })
+    }
+}
+
+/// The angle between two vectors in whole degrees, from 0 to 180, or `None`
+/// if either is zero.
+pub fn angle_between(a: [i32; 3], b: [i32; 3]) -> Option<u16> {
+    let (a, b) = (a.map(i64::from), b.map(i64::from));
+    if a == [0; 3] || b == [0; 3] {
+        return None;
+    }
+    let cross = [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ];
+    let sin = motion::isqrt(cross.iter().map(|c| c.unsigned_abs().pow(2)).sum());
+    let cos = a[0] * b[0] + a[1] * b[1] + a[2] * b[2];
+    Some(atan2_degrees(sin as i64, cos))
+}
+
+/// The device stayed tipped over.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct TipOverEvent {
+    /// Degrees between the baseline and where gravity points now.
+    pub angle: u16,
+}
+
+impl From<TipOverEvent> for AppResetMessage {
+    fn from(event: TipOverEvent) -> AppResetMessage {
+        AppResetMessage::FromTipOver { angle: event.angle }
+    }
+}
+
+/// Turns raw samples into [`TipOverEvent`]s.
+///
+/// Feed it every sample, taken every `sample_period` of the profile. For the
+/// first [`BASELINE_TIME`] after [`rearm`](Self::rearm) the samples taken
+/// while still are averaged into the baseline. After that an event is
+/// reported once the angle from the baseline has stayed above
+/// `tip_over_angle` for `tip_over_hold`, and not again until the device is
+/// back within the angle. Nothing is reported while `tip_over_angle` is zero.
+#[derive(Debug, Clone)]
+pub struct TipOverDetector {
+    sensitivity: AccelSensitivity,
+    angle: u16,
+    hold: usize,
+    baseline_len: usize,
+    /// Sum of the still samples learned so far, in milli-g, and their count.
+    sum: [i32; 3],
+    learned: usize,
+    /// Samples in a row beyond the angle, or `None` once reported.
+    over: Option<usize>,
+}
+
+impl TipOverDetector {
+    pub fn new(profile: &AlarmProfile) -> Self {
+        let mut detector = TipOverDetector {
+            sensitivity: profile.accel_sensitivity,
+            angle: profile.tip_over_angle,
+            hold: 1,
+            baseline_len: 1,
+            sum: [0; 3],
+            learned: 0,
+            over: Some(0),
+        };
+        detector.configure(profile);
+        detector
+    }
+    /// Picks up a changed profile. The baseline is kept, since it is already
+    /// in milli-g.
+    pub fn configure(&mut self, profile: &AlarmProfile) {
+        let samples = |duration: Duration| {
+            let period = profile.sample_period.as_millis().max(1);
+            (duration.as_millis().div_ceil(period) as usize).max(1)
+        };
+        self.sensitivity = profile.accel_sensitivity;
+        self.angle = profile.tip_over_angle;
+        self.hold = samples(profile.tip_over_hold);
+        self.baseline_len = samples(BASELINE_TIME);
+        self.over = Some(0);
+    }
+    /// Forgets the baseline, to learn it again from the next samples. Call it
+    /// whenever the alarm is armed.
+    pub fn rearm(&mut self) {
+        self.sum = [0; 3];
+        self.learned = 0;
+        self.over = Some(0);
+    }
+    /// Where gravity pointed when armed, in milli-g, once learned.
+    pub fn baseline(&self) -> Option<[i32; 3]> {
+        (self.learned >= self.baseline_len).then(|| self.sum.map(|axis| axis / self.learned as i32))
+    }
+    pub fn update(&mut self, raw: RawSample) -> Option<TipOverEvent> {
+        if self.angle == 0 {
+            return None;
+        }
+        let accel = motion::to_milli_g(raw, self.sensitivity);
+        let Some(baseline) = self.baseline() else {
+            if motion::magnitude(accel).abs_diff(ONE_G_MG) <= STILLNESS_TOLERANCE_MG {
+                for (sum, axis) in self.sum.iter_mut().zip(accel) {
+                    *sum += axis;
+                }
+                self.learned += 1;
+            }
+            return None;
+        };
+        let angle = angle_between(baseline, accel)?;
+        if angle <= self.angle {
+            self.over = Some(0);
+            return None;
+        }
+        let over = self.over? + 1;
+        if over < self.hold {
+            self.over = Some(over);
+            return None;
+        }
+        self.over = None;
+        Some(TipOverEvent { angle })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flat_board_is_level() {
+        let tilt = Tilt::from_accel([3, -4, 1000]).unwrap();
+        assert_eq!((tilt.pitch, tilt.roll, tilt.inclination), (0, 0, 0));
+        assert!(tilt.is_level());
+        assert_eq!(tilt.level_leds(), [true; 8]);
+        assert_eq!(Tilt::from_accel([0; 3]), None);
+    }
+
+    #[test]
+    fn angles_follow_the_lowest_edge() {
+        // North edge 20° down: gravity measured leans towards south (+X).
+        let north = Tilt::from_accel([342, 0, 940]).unwrap();
+        assert_eq!(
+            north,
+            Tilt {
+                pitch: 20,
+                roll: 0,
+                inclination: 20,
+                downhill: Some(0),
+            }
+        );
+        // East edge 40° down.
+        let east = Tilt::from_accel([0, -643, 766]).unwrap();
+        assert_eq!((east.pitch, east.roll, east.downhill), (0, 40, Some(90)));
+        // South-west edge down, and raised north.
+        let south_west = Tilt::from_accel([-300, 300, 906]).unwrap();
+        assert_eq!(south_west.downhill, Some(225));
+        assert!(south_west.pitch < 0 && south_west.roll < 0);
+    }
+
+    #[test]
+    fn bubble_widens_with_tilt() {
+        let leds = |accel| Tilt::from_accel(accel).unwrap().level_leds();
+        // Clockwise from north: east 10° down, then north 20° down.
+        let (o, x) = (false, true);
+        assert_eq!(leds([0, -174, 985]), [o, o, x, o, o, o, o, o]);
+        assert_eq!(leds([342, 0, 940]), [x, x, o, o, o, o, o, x]);
+        // Standing on its west edge only the east LED is dark.
+        assert_eq!(leds([0, 1000, 0]), [x, x, o, x, x, x, x, x]);
+        assert_eq!(leds([0, 0, -1000]), [o; 8]);
+    }
+
+    #[test]
+    fn angles_between_vectors() {
+        assert_eq!(angle_between([0, 0, 1000], [0, 0, 980]), Some(0));
+        assert_eq!(angle_between([0, 0, 1000], [1000, 0, 0]), Some(90));
+        assert_eq!(angle_between([0, 0, 1000], [0, 707, 707]), Some(45));
+        assert_eq!(angle_between([0, 0, 1000], [0, 0, -1000]), Some(180));
+        assert_eq!(angle_between([0; 3], [0, 0, 1000]), None);
+    }
+
+    fn tip_over_profile() -> AlarmProfile {
+        AlarmProfile {
+            sample_period: Duration::from_millis(100),
+            accel_sensitivity: AccelSensitivity::G1,
+            tip_over_angle: 60,
+            tip_over_hold: Duration::from_secs(2),
+            ..AlarmProfile::default()
+        }
+    }
+
+    /// Raw counts at ±2 g for an acceleration in milli-g.
+    fn raw(mg: [i32; 3]) -> RawSample {
+        mg.map(|axis| (axis * 16) as i16)
+    }
+
+    #[test]
+    fn learns_the_baseline_while_still() {
+        let mut detector = TipOverDetector::new(&tip_over_profile());
+        // Worn on the side: the baseline need not be flat.
+        for _ in 0..9 {
+            assert_eq!(detector.update(raw([0, 1000, 0])), None);
+        }
+        // Shaking is left out.
+        assert_eq!(detector.update(raw([0, 1500, 0])), None);
+        assert_eq!(detector.baseline(), None);
+        detector.update(raw([0, 1000, 0]));
+        assert_eq!(detector.baseline(), Some([0, 1000, 0]));
+        // Lying flat is now 90° off.
+        let events = (0..20).filter_map(|_| detector.update(raw([0, 0, 1000])));
+        assert!(events.eq([TipOverEvent { angle: 90 }]));
+    }
+
+    #[test]
+    fn reports_once_per_tip_over_after_the_hold() {
+        let mut detector = TipOverDetector::new(&tip_over_profile());
+        for _ in 0..10 {
+            detector.update(raw([0, 0, 1000]));
+        }
+        let tip = |detector: &mut TipOverDetector, samples| {
+            (0..samples)
+                .position(|_| detector.update(raw([1000, 0, 0])).is_some())
+                .map(|i| i + 1)
+        };
+        // Tilted 45°, then over for less than the hold.
+        for _ in 0..30 {
+            assert_eq!(detector.update(raw([707, 0, 707])), None);
+        }
+        assert_eq!(tip(&mut detector, 19), None);
+        assert_eq!(detector.update(raw([0, 0, 1000])), None);
+        assert_eq!(tip(&mut detector, 40), Some(20));
+        assert_eq!(tip(&mut detector, 40), None);
+        // Righted and toppled again.
+        detector.update(raw([0, 0, 1000]));
+        assert_eq!(tip(&mut detector, 40), Some(20));
+
+        detector.rearm();
+        assert_eq!(detector.baseline(), None);
+        for _ in 0..10 {
+            detector.update(raw([1000, 0, 0]));
+        }
+        assert_eq!(tip(&mut detector, 40), None);
+    }
+
+    #[test]
+    fn off_without_an_angle() {
+        let mut detector = TipOverDetector::new(&AlarmProfile {
+            tip_over_angle: 0,
+            ..tip_over_profile()
+        });
+        for _ in 0..100 {
+            assert_eq!(detector.update(raw([0, 0, -1000])), None);
+        }
+    }
+}
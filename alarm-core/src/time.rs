@@ -0,0 +1,59 @@
+use core::{ops::Add, time::Duration};
+
+/// A point on the firmware's monotonic clock, in milliseconds since boot.
+///
+/// Each runtime builds these from its own clock (FreeRTOS ticks or
+/// `Mono::now()`), so the state machine never needs to know which one it is
+/// running on.
+#[derive(Debug, Default, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub struct Instant(u64);
+
+impl Instant {
+    pub const fn from_millis(millis: u64) -> Instant {
+        Instant(millis)
+    }
+    pub const fn as_millis(self) -> u64 {
+        self.0
+    }
+    /// Time elapsed since `earlier`, or zero if `earlier` is in the future.
+    pub fn saturating_duration_since(self, earlier: Instant) -> Duration {
+        Duration::from_millis(self.0.saturating_sub(earlier.0))
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, rhs: Duration) -> Instant {
+        let millis = u64::try_from(rhs.as_millis()).unwrap_or(u64::MAX);
+        Instant(self.0.saturating_add(millis))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn adds_durations_in_millis() {
+        let t = Instant::from_millis(1_000) + Duration::from_millis(1_500);
+        assert_eq!(t.as_millis(), 2_500);
+    }
+
+    #[test]
+    fn add_saturates_at_end_of_time() {
+        let t = Instant::from_millis(u64::MAX - 1) + Duration::from_secs(1);
+        assert_eq!(t.as_millis(), u64::MAX);
+    }
+
+    #[test]
+    fn duration_since_saturates_at_zero() {
+        let early = Instant::from_millis(100);
+        let late = Instant::from_millis(350);
+        assert_eq!(
+            late.saturating_duration_since(early),
+            Duration::from_millis(250)
+        );
+        assert_eq!(early.saturating_duration_since(late), Duration::ZERO);
+    }
+}
@@ -2,180 +2,731 @@
 #![no_std]
 
 // Halt on panic
+use alarm_core::{
+    AccelCalibrator, AppResetMessage, AppState, AsyncMotionSensor, CompassPoint, HealthAction,
+    Instant, Orientation, SensorHealth, Tilt,
+};
 use panic_semihosting as _;
+use peripherals::{LedDirection, Leds};
 use rtic_monotonics::systick::prelude::*;
-mod app_state;
+use rtic_sync::channel::Sender;
+mod i2c;
+mod lsm303;
 mod peripherals;
+mod sysclk;
 
-systick_monotonic!(Mono, 36_000);
+systick_monotonic!(Mono, sysclk::TICK_RATE_HZ);
 
-#[rtic::app(device = stm32f3xx_hal::pac, peripherals = true, dispatchers=[EXTI1, EXTI4, EXTI3])]
+/// Current time on the SysTick monotonic, as the alarm state machine sees it.
+fn now() -> Instant {
+    Instant::from_millis(Mono::now().duration_since_epoch().to_millis())
+}
+
+/// Sleeps for `ms`, waking early if the state's deadline comes first so the
+/// animation never holds back an escalation.
+async fn delay_within_deadline(state: &AppState, ms: u64) {
+    let ms = state
+        .time_until_escalation(now())
+        .map_or(ms, |left| (left.as_millis() as u64).min(ms));
+    Mono::delay(ms.millis()).await;
+}
+
+/// Counts `result` towards the accelerometer's health, recovering the sensor
+/// and reporting a fault as [`SensorHealth`] says. Returns whether the sensor
+/// needs setting up again.
+async fn check_sensor<S: AsyncMotionSensor, T, const N: usize>(
+    sensor: &mut S,
+    health: &mut SensorHealth,
+    result: &Result<T, S::Error>,
+    sender: &mut Sender<'static, AppResetMessage, N>,
+) -> bool {
+    if result.is_ok() {
+        if let Some(message) = health.success() {
+            let _ = sender.send(message).await;
+        }
+        return false;
+    }
+    match health.failure() {
+        HealthAction::Retry => false,
+        action => {
+            sensor.recover().await;
+            if action == HealthAction::Fault {
+                let _ = sender
+                    .send(AppResetMessage::FromSensor { healthy: false })
+                    .await;
+            }
+            true
+        }
+    }
+}
+
+/// Lights one LED per step of the accelerometer calibration, clockwise from
+/// north, up to and including the orientation asked for next.
+fn show_orientation(leds: &mut Leds, calibrator: &AccelCalibrator) {
+    let step = Orientation::ALL
+        .iter()
+        .position(|&orientation| calibrator.next() == Some(orientation))
+        .unwrap_or(Orientation::ALL.len() - 1);
+    leds.current_direction = LedDirection::N;
+    for _ in 0..=step {
+        leds.set_high_current_direction();
+        leds.to_next_direction();
+    }
+}
+
+/// Lights the LEDs towards the lowest edge, more of them the further the
+/// board is tilted, or all of them while it is level.
+fn show_level(leds: &mut Leds, tilt: &Tilt) {
+    for (point, lit) in CompassPoint::ALL.into_iter().zip(tilt.level_leds()) {
+        if lit {
+            leds.current_direction = point.into();
+            leds.set_high_current_direction();
+        }
+    }
+}
+
+#[rtic::app(device = stm32f3xx_hal::pac, peripherals = true, dispatchers=[EXTI1, EXTI3, UART4_EXTI34])]
 mod app {
     use core::borrow::BorrowMut;
 
-    use app_state::{AppResetMessage, AppState, ACTIVE_COUNTER_INITIAL_VALUE};
+    use alarm_board::{flash::InternalFlash, rtc::Rtc};
+    use alarm_core::{
+        compass, motion, ActivityMonitor, ActivityReport, AlarmProfile, AppResetMessage, AppState,
+        Calendar, CalibrationStore, Command, CommandContext, CompassPoint, ConfigStore,
+        FallDetector, Gesture, GestureRecognizer, Journal, LineBuffer, MagCalibrator,
+        MotionDetector, MotionSource, RotationDetector, Scheduler, TipOverDetector, VibrationMask,
+        WakeUpConfig, WakeUpGate, MAX_QUEUE_SIZE,
+    };
+    use core::fmt::Write;
     use cortex_m_semihosting::hprintln;
-    use peripherals::{Accelerometer, Leds};
+    use i2c::{I2cError, I2C_CAPACITY};
+    use peripherals::{AccelInt, Accelerometer, Console, ConsoleRx, Gyroscope, Leds};
+    use rtic::mutex_prelude::*;
     use rtic_sync::{channel::*, make_channel};
-    use stm32f3xx_hal::prelude::_embedded_hal_digital_OutputPin;
+    use stm32f3xx_hal::{
+        gpio::{Gpioa, Input, Pin, U},
+        prelude::{
+            _embedded_hal_digital_InputPin, _embedded_hal_digital_OutputPin,
+            _embedded_hal_serial_Read,
+        },
+    };
 
     use super::*;
 
     #[shared]
     struct Shared {
         app_state: AppState,
+        profile: AlarmProfile,
+        journal: Journal<InternalFlash>,
+        activity: ActivityReport,
+        rtc: Rtc,
     }
 
     // Local resources go here
     #[local]
     struct Local {
-        prev_x: i16,
-        prev_y: i16,
-        prev_z: i16,
-        accelerometer: Accelerometer,
-        leds: Leds,
+        console: Console,
+        console_rx: ConsoleRx,
+        config_store: ConfigStore<InternalFlash>,
+        console_sender: Sender<'static, u8, CONSOLE_CAPACITY>,
+        user_btn: Pin<Gpioa, U<0>, Input>,
+        button_sender: Sender<'static, bool, BUTTON_CAPACITY>,
+        accel_int: AccelInt,
+        wake_up_sender: Sender<'static, (), WAKE_UP_CAPACITY>,
+        i2c_sender: Sender<'static, Result<(), I2cError>, I2C_CAPACITY>,
+        i2c_error_sender: Sender<'static, Result<(), I2cError>, I2C_CAPACITY>,
+        schedule_sender: Sender<'static, (), SCHEDULE_CAPACITY>,
     }
 
-    const CAPACITY: usize = 5;
+    const CAPACITY: usize = MAX_QUEUE_SIZE;
+    const BUTTON_CAPACITY: usize = 8;
+    const CONSOLE_CAPACITY: usize = 32;
+    const SCHEDULE_CAPACITY: usize = 2;
+    const WAKE_UP_CAPACITY: usize = 1;
+    const CONSOLE_LINE_LENGTH: usize = 48;
+    /// The magnetometer runs at 15 Hz.
+    const COMPASS_PERIOD_MS: u64 = 100;
     #[init]
     fn init(cx: init::Context) -> (Shared, Local) {
-        let (mut leds, user_btn, accelerometer) = peripherals::setup(cx);
-        let app_state = AppState::new();
+        let mut config_store = ConfigStore::new(InternalFlash::config());
+        let profile = config_store.load_or_default();
+        let (i2c_sender, i2c_receiver) = make_channel!(Result<(), I2cError>, I2C_CAPACITY);
+        let (leds, user_btn, mut accelerometer, gyroscope, accel_int, console, console_rx) =
+            peripherals::setup(cx, i2c_receiver);
+        let mut calibration_store = CalibrationStore::new(InternalFlash::calibration());
+        accelerometer.set_calibration(calibration_store.load_or_default().accel);
+        let app_state = AppState::new(now(), &profile);
+        let mut journal = Journal::new(InternalFlash::journal());
+        // The alarm keeps running without a journal rather than not at all.
+        let _ = journal
+            .open()
+            .and_then(|()| journal.record_boot(now(), app_state.kind()));
         let (s, r) = make_channel!(AppResetMessage, CAPACITY);
+        let (console_sender, console_receiver) = make_channel!(u8, CONSOLE_CAPACITY);
+        let (button_sender, button_receiver) = make_channel!(bool, BUTTON_CAPACITY);
+        let (schedule_sender, schedule_receiver) = make_channel!((), SCHEDULE_CAPACITY);
+        let (wake_up_sender, wake_up_receiver) = make_channel!((), WAKE_UP_CAPACITY);
+        let rtc = Rtc::init();
 
-        accelerometer_task::spawn(s).unwrap();
-        output_task::spawn().unwrap();
-        transition_task::spawn(r).unwrap();
-
+        button_task::spawn(button_receiver, s.clone()).unwrap();
+        // Holding the user button through reset starts the compass instead.
+        if user_btn.is_high().unwrap_or(false) {
+            compass_task::spawn(r, accelerometer, leds, calibration_store).unwrap();
+        } else {
+            accelerometer_task::spawn(s.clone(), wake_up_receiver, accelerometer, gyroscope)
+                .unwrap();
+            schedule_task::spawn(schedule_receiver, s.clone()).unwrap();
+            output_task::spawn(leds).unwrap();
+            transition_task::spawn(r).unwrap();
+            console_task::spawn(console_receiver, s.clone(), schedule_sender.clone()).unwrap();
+        }
         (
-            Shared { app_state },
+            Shared {
+                app_state,
+                profile,
+                journal,
+                activity: ActivityReport::new(),
+                rtc,
+            },
             Local {
                 // Initialization of local resources go here
-                prev_x: 0,
-                prev_y: 0,
-                prev_z: 0,
-                accelerometer,
-                leds,
+                console,
+                console_rx,
+                config_store,
+                console_sender,
+                user_btn,
+                button_sender,
+                accel_int,
+                wake_up_sender,
+                i2c_error_sender: i2c_sender.clone(),
+                i2c_sender,
+                schedule_sender,
             },
         )
     }
 
-    // Optional idle, can be removed if not needed.
+    /// Sleeps until the next interrupt; the RTC alarm wakes the core too.
     #[idle]
     fn idle(_: idle::Context) -> ! {
         loop {
-            continue;
+            cortex_m::asm::wfi();
         }
     }
 
-    #[task(binds = EXTI0, shared = [app_state])]
-    fn exti0(mut cx: exti0::Context) {
-        cx.shared.app_state.lock(|s| match s {
-            AppState::Alarm => s.reset(),
-            _ => {}
-        });
+    #[task(binds = EXTI0, local = [user_btn, button_sender])]
+    fn exti0(cx: exti0::Context) {
+        let pressed = cx.local.user_btn.is_high().unwrap_or(false);
+        let _ = cx.local.button_sender.try_send(pressed);
+        cx.local.user_btn.clear_interrupt();
+    }
+
+    #[task(priority = 2)]
+    async fn button_task(
+        _: button_task::Context,
+        mut receiver: Receiver<'static, bool, BUTTON_CAPACITY>,
+        mut sender: Sender<'static, AppResetMessage, CAPACITY>,
+    ) {
+        let mut recognizer = GestureRecognizer::new();
+        loop {
+            let gesture = match recognizer.deadline() {
+                Some(deadline) => {
+                    let left = deadline.saturating_duration_since(now()).as_millis() as u64;
+                    match Mono::timeout_after(left.millis(), receiver.recv()).await {
+                        Ok(Ok(pressed)) => recognizer.edge(pressed, now()),
+                        Ok(Err(_)) => return,
+                        Err(_) => recognizer.poll(now()),
+                    }
+                }
+                None => match receiver.recv().await {
+                    Ok(pressed) => recognizer.edge(pressed, now()),
+                    Err(_) => return,
+                },
+            };
+            if let Some(gesture) = gesture {
+                let _ = sender.send(AppResetMessage::FromButton { gesture }).await;
+            }
+        }
     }
 
-    #[task(priority=2,local=[prev_x, prev_y, prev_z, accelerometer])]
+    #[task(binds = RTCALARM, local = [schedule_sender])]
+    fn rtc_alarm(cx: rtc_alarm::Context) {
+        Rtc::clear_alarm();
+        let _ = cx.local.schedule_sender.try_send(());
+    }
+
+    /// Arms and disarms the alarm as the schedule in the profile says,
+    /// sleeping until the RTC alarm or the console asks for the schedule to
+    /// be re-evaluated.
+    #[task(priority=1,shared=[profile, rtc])]
+    async fn schedule_task(
+        c: schedule_task::Context,
+        mut receiver: Receiver<'static, (), SCHEDULE_CAPACITY>,
+        mut sender: Sender<'static, AppResetMessage, CAPACITY>,
+    ) {
+        let mut shared = (c.shared.profile, c.shared.rtc);
+        let mut scheduler = Scheduler::new();
+        loop {
+            let message = shared.lock(|profile, rtc| {
+                let now = rtc.now();
+                rtc.set_alarm(profile.schedule.next_change(&now));
+                scheduler.poll(&profile.schedule, &now)
+            });
+            if let Some(message) = message {
+                let _ = sender.send(message).await;
+            }
+            if receiver.recv().await.is_err() {
+                return;
+            }
+        }
+    }
+
+    /// The accelerometer's wake-up interrupt on INT1, which the accelerometer
+    /// task checks against the activity before it counts as motion.
+    #[task(binds = EXTI4, local = [accel_int, wake_up_sender])]
+    fn exti4(cx: exti4::Context) {
+        let _ = cx.local.wake_up_sender.try_send(());
+        cx.local.accel_int.clear_interrupt();
+    }
+
+    /// Progress of the accelerometer's I2C transfer.
+    #[task(binds = I2C1_EV_EXTI23, local = [i2c_sender])]
+    fn i2c1_ev(cx: i2c1_ev::Context) {
+        if let Some(result) = i2c::on_event() {
+            let _ = cx.local.i2c_sender.try_send(result);
+        }
+    }
+
+    #[task(binds = I2C1_ER, local = [i2c_error_sender])]
+    fn i2c1_er(cx: i2c1_er::Context) {
+        if let Some(result) = i2c::on_error() {
+            let _ = cx.local.i2c_error_sender.try_send(result);
+        }
+    }
+
+    #[task(binds = USART1_EXTI25, local = [console_rx, console_sender])]
+    fn usart1(cx: usart1::Context) {
+        if let Ok(byte) = cx.local.console_rx.read() {
+            let _ = cx.local.console_sender.try_send(byte);
+        }
+    }
+
+    #[task(priority=2, shared=[app_state, profile, activity, journal])]
     async fn accelerometer_task(
         c: accelerometer_task::Context,
         mut sender: Sender<'static, AppResetMessage, CAPACITY>,
+        wake_up_receiver: Receiver<'static, (), WAKE_UP_CAPACITY>,
+        mut accelerometer: Accelerometer,
+        mut gyroscope: Gyroscope,
     ) {
-        let prev_x = c.local.prev_x;
-        let prev_y = c.local.prev_y;
-        let prev_z = c.local.prev_z;
-        let accelerometer = c.local.accelerometer;
-        if let Ok(axis) = accelerometer.accel() {
-            *prev_x = axis.x;
-            *prev_y = axis.y;
-            *prev_z = axis.z;
+        let mut shared_profile = c.shared.profile;
+        let mut shared_state = c.shared.app_state;
+        let profile = shared_profile.lock(|p| *p);
+        if accelerometer.bring_up(&profile).await.is_faulty() {
+            // Applied before sampling starts, so it starts out faulty too.
+            let fault = AppResetMessage::FromSensor { healthy: false };
+            (&mut shared_state, c.shared.journal).lock(|s, journal| {
+                let now = now();
+                let from = s.kind();
+                let _ = s.handle_reset(fault, now, &profile);
+                let _ = journal.record_reset(now, fault, from, s.kind());
+            });
+        }
+        monitor_motion(
+            accelerometer,
+            gyroscope,
+            sender,
+            wake_up_receiver,
+            shared_state,
+            shared_profile,
+            c.shared.activity,
+        )
+        .await;
+    }
+
+    /// The body of `accelerometer_task`, apart from bringing up the LSM303, so
+    /// that it works with any [`AsyncMotionSensor`].
+    ///
+    /// In interrupt mode the samples are read only as long as something needs
+    /// them. Each wake-up from `wake_up_receiver` is reported as motion once the
+    /// samples that follow show the wearer moving.
+    async fn monitor_motion<S: AsyncMotionSensor>(
+        mut accelerometer: S,
+        mut gyroscope: Gyroscope,
+        mut sender: Sender<'static, AppResetMessage, CAPACITY>,
+        mut wake_up_receiver: Receiver<'static, (), WAKE_UP_CAPACITY>,
+        mut shared_state: impl rtic::Mutex<T = AppState>,
+        mut shared_profile: impl rtic::Mutex<T = AlarmProfile>,
+        mut shared_activity: impl rtic::Mutex<T = ActivityReport>,
+    ) {
+        let mut configured = shared_profile.lock(|p| *p);
+        let mut detector = MotionDetector::new(&configured);
+        let mut fall = FallDetector::new(&configured);
+        let mut tip_over = TipOverDetector::new(&configured);
+        let mut activity = ActivityMonitor::new(&configured);
+        let mut vibration = VibrationMask::new(&configured);
+        let mut gate = WakeUpGate::new(&configured);
+        let mut rotation = RotationDetector::new(&configured);
+        let mut wake_up = false;
+        let mut spinning = false;
+        // An accelerometer that did not answer at boot left the alarm in
+        // `SensorFault`, which its first sample that works clears.
+        let mut health = if shared_state.lock(|s| *s == AppState::SensorFault) {
+            SensorHealth::faulty()
+        } else {
+            SensorHealth::new()
+        };
+        // Set when the sensor lost its settings, e.g. after a bus recovery.
+        let mut stale = health.is_faulty();
+        let mut was_disarmed = false;
+        // Samples are taken on deadlines, so reading and processing one does
+        // not stretch the period the vibration mask's frequencies rely on.
+        let mut deadline = Mono::now();
+        loop {
+            let profile = shared_profile.lock(|p| *p);
+            let disarmed = shared_state.lock(|s| *s == AppState::Disarmed);
+            // Tipping over is judged against how the device lay when armed.
+            if was_disarmed && !disarmed {
+                tip_over.rearm();
+            }
+            was_disarmed = disarmed;
+            // Masking needs the samples of several seconds without a break.
+            let interrupt =
+                profile.motion_source == MotionSource::Interrupt && !profile.masks_vibration();
+            let gyro = profile.detects_rotation() && !disarmed;
+            if stale || (profile, interrupt && !disarmed, gyro) != (configured, wake_up, spinning) {
+                let mut result = Ok(());
+                if stale
+                    || (profile.accel_odr, profile.accel_sensitivity)
+                        != (configured.accel_odr, configured.accel_sensitivity)
+                {
+                    result = accelerometer.configure(&profile).await;
+                }
+                wake_up = interrupt && !disarmed;
+                result = result.and(if wake_up {
+                    accelerometer
+                        .enable_wake_up(WakeUpConfig::new(&profile))
+                        .await
+                } else {
+                    accelerometer.disable_wake_up().await
+                });
+                // Retried with the next sample until it takes.
+                stale = result.is_err();
+                if gyro != spinning {
+                    spinning = gyro;
+                    let _ = if spinning {
+                        gyroscope.enable()
+                    } else {
+                        gyroscope.disable()
+                    };
+                }
+                detector.configure(&profile);
+                fall.configure(&profile);
+                tip_over.configure(&profile);
+                activity.configure(&profile);
+                vibration.configure(&profile);
+                gate.configure(&profile);
+                rotation.configure(&profile);
+                configured = profile;
+            }
+            let idle =
+                interrupt && !profile.detects_falls() && !profile.detects_tip_over() && !spinning;
+            if idle && !gate.is_open() {
+                // Wait for EXTI4, meanwhile keeping up with the profile and
+                // making sure the sensor still answers.
+                let poll = (motion::WAKE_UP_POLL_PERIOD.as_millis() as u64).millis();
+                let woke = matches!(
+                    Mono::timeout_after(poll, wake_up_receiver.recv()).await,
+                    Ok(Ok(()))
+                );
+                if !disarmed {
+                    let sample = accelerometer.accel().await;
+                    stale |=
+                        check_sensor(&mut accelerometer, &mut health, &sample, &mut sender).await;
+                }
+                if woke && wake_up {
+                    // Nothing was sampled while waiting.
+                    activity.resume();
+                    gate.open();
+                }
+                deadline = Mono::now();
+                continue;
+            }
+            if wake_up && wake_up_receiver.try_recv().is_ok() {
+                gate.open();
+            }
+            if !disarmed {
+                let sample = accelerometer.accel().await;
+                stale |= check_sensor(&mut accelerometer, &mut health, &sample, &mut sender).await;
+                if let Ok(sample) = sample {
+                    if let Some(event) = fall.update(sample) {
+                        let _ = sender.send(event.into()).await;
+                    }
+                    if let Some(event) = tip_over.update(sample) {
+                        let _ = sender.send(event.into()).await;
+                    }
+                    if activity.update(sample).is_some() {
+                        shared_activity.lock(|report| *report = activity.report());
+                    }
+                    vibration.update(sample);
+                    if let Some(message) = gate.update(&activity) {
+                        let _ = sender.try_send(message);
+                    }
+                    // In interrupt mode the wake-up gate reports the motion.
+                    if let Some(event) = detector
+                        .update(sample)
+                        .filter(|_| !interrupt && activity.is_human() && vibration.permits())
+                    {
+                        // More motion follows if the channel is full, so it
+                        // is dropped rather than holding up sampling.
+                        let _ = sender.try_send(event.into());
+                    }
+                }
+            }
+            // The gyroscope has no interrupt wired up, so it is always polled.
+            if spinning {
+                if let Some(event) = gyroscope.rate().ok().and_then(|rate| rotation.update(rate)) {
+                    let _ = sender.try_send(event.into());
+                }
+            }
+            deadline += (profile.sample_period.as_millis() as u64).millis();
+            Mono::delay_until(deadline).await;
         }
+    }
+
+    /// Lights the LED pointing to magnetic north. A long press starts the
+    /// calibration: turn the board every which way while the LEDs spin, then
+    /// long press again to store the result.
+    ///
+    /// A double press starts the accelerometer calibration instead. The lit
+    /// LEDs count the step; put the board down in that orientation and short
+    /// press once it is still. A double press cancels it.
+    ///
+    /// A short press switches to the bubble level and back. It lights the
+    /// LEDs towards the lowest edge, more of them the further the board is
+    /// tilted.
+    #[task(priority=1, shared=[profile])]
+    async fn compass_task(
+        mut c: compass_task::Context,
+        mut receiver: Receiver<'static, AppResetMessage, CAPACITY>,
+        mut accelerometer: Accelerometer,
+        mut leds: Leds,
+        mut calibration_store: CalibrationStore<InternalFlash>,
+    ) {
+        let profile = c.shared.profile.lock(|p| *p);
+        let mut calibration = calibration_store.load_or_default();
+        let mut calibrator = None;
+        let mut accel_calibrator: Option<AccelCalibrator> = None;
+        // Showing the bubble level instead of the compass.
+        let mut level = false;
+        let _ = accelerometer.init(&profile).await;
+        let _ = accelerometer.enable_magnetometer().await;
         loop {
-            if let Ok(axis) = accelerometer.accel() {
-                let difference =
-                    (axis.x - *prev_x).abs() + (axis.y - *prev_y).abs() + (axis.z - *prev_z).abs();
-                if difference > 1000 {
-                    let _ = sender.send(AppResetMessage::FromAccelerometer).await;
-                }
-                *prev_x = axis.x;
-                *prev_y = axis.y;
-                *prev_z = axis.z;
+            let gesture = match receiver.try_recv() {
+                Ok(AppResetMessage::FromButton { gesture }) => Some(gesture),
+                _ => None,
+            };
+            match (gesture, accel_calibrator.as_mut()) {
+                (Some(Gesture::Long), None) => {
+                    calibrator = match calibrator.take() {
+                        None => Some(MagCalibrator::new()),
+                        Some(calibrator) => {
+                            // A calibration that did not cover every axis is dropped.
+                            if let Some(mag) = calibrator.finish() {
+                                calibration.mag = mag;
+                                let _ = calibration_store.save(&calibration);
+                            }
+                            None
+                        }
+                    };
+                }
+                (Some(Gesture::Double), None) if calibrator.is_none() => {
+                    accel_calibrator = Some(AccelCalibrator::new());
+                }
+                (Some(Gesture::Short), None) if calibrator.is_none() => level = !level,
+                // Cancels, keeping the old calibration.
+                (Some(Gesture::Double), Some(_)) => accel_calibrator = None,
+                (Some(Gesture::Short), Some(calibrating)) => {
+                    // A confirm in the wrong orientation is ignored and the
+                    // same step lights up again.
+                    if calibrating.confirm() {
+                        if let Some(accel) = calibrating.finish() {
+                            calibration.accel = accel;
+                            accelerometer.set_calibration(accel);
+                            let _ = calibration_store.save(&calibration);
+                            accel_calibrator = None;
+                        }
+                    }
+                }
+                _ => {}
+            }
+            leds.set_low_all_direction();
+            if let Some(calibrating) = accel_calibrator.as_mut() {
+                if let Ok(raw) = accelerometer.raw_accel().await {
+                    calibrating.update(motion::to_milli_g(raw, profile.accel_sensitivity));
+                }
+                show_orientation(&mut leds, calibrating);
+                Mono::delay(COMPASS_PERIOD_MS.millis()).await;
+                continue;
+            }
+            if level {
+                let sample = accelerometer.accel().await.ok();
+                let tilt = sample.and_then(|raw| {
+                    Tilt::from_accel(motion::to_milli_g(raw, profile.accel_sensitivity))
+                });
+                if let Some(tilt) = tilt {
+                    show_level(&mut leds, &tilt);
+                }
+                Mono::delay(COMPASS_PERIOD_MS.millis()).await;
+                continue;
             }
-            Mono::delay(1000.millis()).await;
+            match (
+                accelerometer.accel().await,
+                accelerometer.mag().await,
+                calibrator.as_mut(),
+            ) {
+                (_, Ok(mag), Some(calibrator)) => {
+                    calibrator.update(mag);
+                    leds.to_next_direction();
+                    leds.set_high_current_direction();
+                }
+                (Ok(accel), Ok(mag), None) => {
+                    if let Some(bearing) = compass::north_bearing(
+                        motion::to_milli_g(accel, profile.accel_sensitivity),
+                        calibration.mag.apply(mag),
+                    ) {
+                        leds.current_direction = CompassPoint::from_bearing(bearing).into();
+                        leds.set_high_current_direction();
+                    }
+                }
+                _ => {}
+            }
+            Mono::delay(COMPASS_PERIOD_MS.millis()).await;
         }
     }
 
-    #[task(priority=2,shared=[app_state])]
+    #[task(priority=2,shared=[app_state, profile, journal])]
     async fn transition_task(
         c: transition_task::Context,
         mut receiver: Receiver<'static, AppResetMessage, CAPACITY>,
     ) {
-        let mut shared_app_state = c.shared.app_state;
+        let mut shared = (c.shared.app_state, c.shared.profile, c.shared.journal);
         while let Ok(transition) = receiver.recv().await {
-            shared_app_state.lock(|s| {
-                match transition {
-                    AppResetMessage::FromButton => match *s {
-                        AppState::Alarm => *s = AppState::Active(ACTIVE_COUNTER_INITIAL_VALUE),
-                        _ => {}
-                    },
-                    AppResetMessage::FromAccelerometer => match *s {
-                        AppState::PreAlarm(_) => {
-                            *s = AppState::Active(ACTIVE_COUNTER_INITIAL_VALUE)
-                        }
-                        _ => {}
-                    },
-                };
+            shared.lock(|s, profile, journal| {
+                let now = now();
+                let from = s.kind();
+                let _ = s.handle_reset(transition, now, profile);
+                let _ = journal.record_reset(now, transition, from, s.kind());
             })
         }
     }
 
-    #[task(priority=1,local=[leds], shared=[app_state])]
-    async fn output_task(c: output_task::Context) {
-        let mut shared_app_state = c.shared.app_state;
-        let leds = c.local.leds;
+    #[task(priority=1,local=[console, config_store], shared=[profile, journal, activity, rtc])]
+    async fn console_task(
+        c: console_task::Context,
+        mut receiver: Receiver<'static, u8, CONSOLE_CAPACITY>,
+        mut sender: Sender<'static, AppResetMessage, CAPACITY>,
+        mut schedule_sender: Sender<'static, (), SCHEDULE_CAPACITY>,
+    ) {
+        let console = c.local.console;
+        let config_store = c.local.config_store;
+        let mut shared = (
+            c.shared.profile,
+            c.shared.journal,
+            c.shared.activity,
+            c.shared.rtc,
+        );
+        let mut line = LineBuffer::<CONSOLE_LINE_LENGTH>::new();
+        while let Ok(byte) = receiver.recv().await {
+            let _ = match line.push(byte).map(|text| text.and_then(Command::parse)) {
+                Some(Ok(command)) => {
+                    if let Some(message) = command.reset_message() {
+                        let _ = sender.send(message).await;
+                    }
+                    let result = shared.lock(|profile, journal, activity, rtc| {
+                        let context = CommandContext {
+                            profile,
+                            store: config_store,
+                            journal,
+                            activity,
+                            calendar: rtc,
+                        };
+                        command.execute(context, console)
+                    });
+                    if command.affects_schedule() {
+                        let _ = schedule_sender.try_send(());
+                    }
+                    result
+                }
+                Some(Err(e)) => writeln!(console, "error: {}", e),
+                None => continue,
+            };
+        }
+    }
+
+    #[task(priority=1, shared=[app_state, profile, journal])]
+    async fn output_task(c: output_task::Context, mut leds: Leds) {
+        let mut shared = (c.shared.app_state, c.shared.profile, c.shared.journal);
         loop {
-            let s = shared_app_state.lock(|s| s.clone());
+            let s = shared.lock(|s, profile, journal| {
+                let now = now();
+                let mut from = s.kind();
+                while s.escalate(now, profile).is_ok() {
+                    let _ = journal.record_timeout(now, from, s.kind());
+                    from = s.kind();
+                }
+                *s
+            });
             match s {
-                AppState::Active(counter) => {
-                    if counter != 0 {
-                        let _ = leds.north.set_high();
-                        Mono::delay(25.millis()).await;
-                        let _ = leds.north.set_low();
-                        Mono::delay(75.millis()).await;
-                        let _ = shared_app_state.lock(|s| s.decrement_counter());
-                    } else {
-                        let _ = shared_app_state.lock(|s| s.transition());
-                    }
+                AppState::Active { .. } => {
+                    let _ = leds.north.set_high();
+                    Mono::delay(250.millis()).await;
+                    let _ = leds.north.set_low();
+                    delay_within_deadline(&s, 750).await;
                 }
-                AppState::PreAlarm(counter) => {
+                AppState::PreAlarm { .. } => {
                     let first_direction = leds.current_direction;
                     leds.set_high_current_direction();
-                    Mono::delay(10.millis()).await;
+                    Mono::delay(100.millis()).await;
                     leds.set_low_current_direction();
                     leds.to_next_direction();
                     while leds.current_direction != first_direction {
                         leds.set_high_current_direction();
-                        Mono::delay(10.millis()).await;
+                        Mono::delay(100.millis()).await;
                         leds.set_low_current_direction();
                         leds.to_next_direction();
                     }
-                    Mono::delay(200.millis()).await;
-                    let _ = shared_app_state.lock(|s| {
-                        s.decrement_counter();
-                        s.decrement_counter();
-                    });
-                    if counter == 0 {
-                        let _ = shared_app_state.lock(|s| {
-                            s.transition();
-                        });
-                    }
+                    delay_within_deadline(&s, 2000).await;
+                }
+                AppState::Alarm { .. } => {
+                    leds.set_high_all_direction();
+                    Mono::delay(1000.millis()).await;
+                    leds.set_low_all_direction();
+                    delay_within_deadline(&s, 1000).await;
                 }
-                AppState::Alarm => {
+                AppState::Emergency => {
                     leds.set_high_all_direction();
                     Mono::delay(100.millis()).await;
                     leds.set_low_all_direction();
                     Mono::delay(100.millis()).await;
                 }
+                AppState::Disarmed => {
+                    let _ = leds.south.set_high();
+                    Mono::delay(50.millis()).await;
+                    let _ = leds.south.set_low();
+                    Mono::delay(950.millis()).await;
+                }
+                AppState::SensorFault => {
+                    // East and west flash twice, which no other state does.
+                    for _ in 0..2 {
+                        let _ = leds.east.set_high();
+                        let _ = leds.west.set_high();
+                        Mono::delay(100.millis()).await;
+                        let _ = leds.east.set_low();
+                        let _ = leds.west.set_low();
+                        Mono::delay(100.millis()).await;
+                    }
+                    Mono::delay(600.millis()).await;
+                }
             };
         }
     }
@@ -0,0 +1,9 @@
+//! Clock rates the firmware runs at.
+
+/// Core clock in hertz: the 8 MHz HSI halved and multiplied by nine in the PLL.
+pub const SYSCLK_HZ: u32 = 36_000_000;
+
+/// SysTick monotonic ticks per second; one tick is one millisecond, as on the
+/// FreeRTOS firmware. The reload value is `SYSCLK_HZ / TICK_RATE_HZ`, so it
+/// is only right while the core actually runs at `SYSCLK_HZ`.
+pub const TICK_RATE_HZ: u32 = 1000;
@@ -1,4 +1,29 @@
+// The clock rates the firmware runs at; `FreeRTOSConfig.h` must agree.
+include!("src/sysclk.rs");
+
+/// Panics unless `FreeRTOSConfig.h` defines `name` as exactly `expected`.
+fn check_config(header: &str, name: &str, expected: u32) {
+    let value: String = header
+        .lines()
+        .skip_while(|line| !line.starts_with(&format!("#define {name}")))
+        .flat_map(|line| line.split("//").next().unwrap_or("").chars())
+        .skip_while(|c| !c.is_ascii_digit())
+        .take_while(|c| c.is_ascii_digit())
+        .collect();
+    assert_eq!(
+        value.parse::<u32>().ok(),
+        Some(expected),
+        "{name} in src/FreeRTOSConfig.h must be {expected} to match src/sysclk.rs"
+    );
+}
+
 fn main() {
+    println!("cargo:rerun-if-changed=src/sysclk.rs");
+    println!("cargo:rerun-if-changed=src/FreeRTOSConfig.h");
+    let header = std::fs::read_to_string("src/FreeRTOSConfig.h").expect("FreeRTOSConfig.h");
+    check_config(&header, "configCPU_CLOCK_HZ", SYSCLK_HZ);
+    check_config(&header, "configTICK_RATE_HZ", TICK_RATE_HZ);
+
     let mut b = freertos_cargo_build::Builder::new();
 
     // Path to FreeRTOS kernel or set ENV "FREERTOS_SRC" instead
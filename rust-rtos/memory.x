@@ -1,7 +1,13 @@
 MEMORY
 {
   /* NOTE K = KiBi = 1024 bytes */
-  FLASH (rx) : ORIGIN = 0x08000000, LENGTH = 256K
+  FLASH (rx) : ORIGIN = 0x08000000, LENGTH = 240K
+  /* Two 2K pages hold the sensor calibration, see alarm-board/src/flash.rs */
+  CALIBRATION (r) : ORIGIN = 0x0803C000, LENGTH = 4K
+  /* Four 2K pages hold the black-box event journal, see alarm-board/src/flash.rs */
+  JOURNAL (r) : ORIGIN = 0x0803D000, LENGTH = 8K
+  /* Last two 2K pages hold the persisted alarm configuration, see alarm-board/src/flash.rs */
+  CONFIG (r) : ORIGIN = 0x0803F000, LENGTH = 4K
   CCMRAM (rwx) : ORIGIN = 0x10000000, LENGTH = 8K
   RAM (rwx) : ORIGIN = 0x20000000, LENGTH = 40K
 }
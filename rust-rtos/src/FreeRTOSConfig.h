@@ -95,9 +95,9 @@ extern void vAssertCalled(unsigned long ulLine, const char *const pcFileName);
 #define configUSE_PREEMPTION 1
 #define configUSE_IDLE_HOOK 0
 #define configUSE_TICK_HOOK 0
-#define configCPU_CLOCK_HZ (4200000UL) // also systick runs at this frequency
+#define configCPU_CLOCK_HZ (36000000UL) // SYSCLK_HZ in sysclk.rs, checked by build.rs
 #define configTICK_RATE_HZ                                                     \
-  ((TickType_t)1000) // 1000=1ms per tick, 100=10ms per tick
+  ((TickType_t)1000) // TICK_RATE_HZ in sysclk.rs, checked by build.rs
 #define configMAX_PRIORITIES (5)
 #define configMINIMAL_STACK_SIZE ((unsigned short)80)
 #define configTOTAL_HEAP_SIZE ((size_t)(15 * 1024)) // was 15
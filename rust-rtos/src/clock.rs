@@ -0,0 +1,26 @@
+use alarm_core::Instant;
+use core::cell::Cell;
+use cortex_m::interrupt::Mutex as CortexMMutex;
+use freertos_rust::FreeRtosUtils;
+
+use crate::sysclk::TICK_RATE_HZ;
+
+/// Last tick count seen and how many times the 32-bit tick counter has wrapped
+/// since boot.
+static G_CLOCK: CortexMMutex<Cell<(u32, u32)>> = CortexMMutex::new(Cell::new((0, 0)));
+
+/// Current time on the FreeRTOS tick clock, extended to 64 bits so it keeps
+/// counting past the tick counter rollover.
+pub fn now() -> Instant {
+    let ticks = cortex_m::interrupt::free(|cs| {
+        let clock = G_CLOCK.borrow(cs);
+        let (last, mut wraps) = clock.get();
+        let ticks = FreeRtosUtils::get_tick_count();
+        if ticks < last {
+            wraps += 1;
+        }
+        clock.set((ticks, wraps));
+        (u64::from(wraps) << 32) | u64::from(ticks)
+    });
+    Instant::from_millis(ticks * 1000 / u64::from(TICK_RATE_HZ))
+}
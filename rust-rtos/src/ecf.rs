@@ -6,16 +6,40 @@ use cortex_m::{
 };
 use cortex_m_rt::{exception, ExceptionFrame};
 use freertos_rust::*;
-use stm32f3xx_hal::{gpio::*, interrupt};
+use stm32f3xx_hal::{
+    gpio::*,
+    interrupt,
+    prelude::{_embedded_hal_digital_InputPin, _embedded_hal_serial_Read},
+};
+
+use alarm_board::rtc::Rtc;
 
-use crate::app_state::AppResetMessage;
+use crate::{
+    i2c,
+    peripherals::{AccelInt, ConsoleRx},
+};
 
 #[global_allocator]
 static GLOBAL: FreeRtosAllocator = FreeRtosAllocator;
 static G_BTN: CortexMMutex<RefCell<Option<Pin<Gpioa, U<0>, Input>>>> =
     CortexMMutex::new(RefCell::new(None));
-static G_STATE_QUEUE: CortexMMutex<RefCell<Option<Arc<Queue<AppResetMessage>>>>> =
+/// Button level after each edge, for the gesture recognizer in the button task.
+static G_BUTTON_QUEUE: CortexMMutex<RefCell<Option<Arc<Queue<bool>>>>> =
+    CortexMMutex::new(RefCell::new(None));
+static G_CONSOLE_RX: CortexMMutex<RefCell<Option<ConsoleRx>>> =
     CortexMMutex::new(RefCell::new(None));
+static G_CONSOLE_QUEUE: CortexMMutex<RefCell<Option<Arc<Queue<u8>>>>> =
+    CortexMMutex::new(RefCell::new(None));
+/// Woken by the RTC alarm so the schedule task re-evaluates the schedule.
+static G_SCHEDULE_QUEUE: CortexMMutex<RefCell<Option<Arc<Queue<()>>>>> =
+    CortexMMutex::new(RefCell::new(None));
+static G_ACCEL_INT: CortexMMutex<RefCell<Option<AccelInt>>> = CortexMMutex::new(RefCell::new(None));
+/// Woken by the accelerometer's wake-up interrupt so the accelerometer task
+/// checks whether the wearer is moving.
+static G_WAKE_UP_QUEUE: CortexMMutex<RefCell<Option<Arc<Queue<()>>>>> =
+    CortexMMutex::new(RefCell::new(None));
+/// The task waiting for the I2C transfer in progress.
+static G_I2C_WAITER: CortexMMutex<RefCell<Option<Task>>> = CortexMMutex::new(RefCell::new(None));
 
 pub fn setup_interrupt(interrupt_number: impl InterruptNumber) {
     unsafe {
@@ -25,28 +49,112 @@ pub fn setup_interrupt(interrupt_number: impl InterruptNumber) {
 
 pub fn setup_interrupt_resource(
     user_btn: Pin<Gpioa, U<0>, Input>,
-    task_resetter_semaphore_arc: Arc<Queue<AppResetMessage>>,
+    button_queue_arc: Arc<Queue<bool>>,
 ) {
     cortex_m::interrupt::free(|cs| {
         *G_BTN.borrow(cs).borrow_mut() = Some(user_btn);
-        *G_STATE_QUEUE.borrow(cs).borrow_mut() = Some(task_resetter_semaphore_arc);
+        *G_BUTTON_QUEUE.borrow(cs).borrow_mut() = Some(button_queue_arc);
+    });
+}
+
+pub fn setup_console_resource(console_rx: ConsoleRx, console_queue_arc: Arc<Queue<u8>>) {
+    cortex_m::interrupt::free(|cs| {
+        *G_CONSOLE_RX.borrow(cs).borrow_mut() = Some(console_rx);
+        *G_CONSOLE_QUEUE.borrow(cs).borrow_mut() = Some(console_queue_arc);
+    });
+}
+
+pub fn setup_schedule_resource(schedule_queue_arc: Arc<Queue<()>>) {
+    cortex_m::interrupt::free(|cs| {
+        *G_SCHEDULE_QUEUE.borrow(cs).borrow_mut() = Some(schedule_queue_arc);
+    });
+}
+
+pub fn setup_motion_resource(accel_int: AccelInt, wake_up_queue_arc: Arc<Queue<()>>) {
+    cortex_m::interrupt::free(|cs| {
+        *G_ACCEL_INT.borrow(cs).borrow_mut() = Some(accel_int);
+        *G_WAKE_UP_QUEUE.borrow(cs).borrow_mut() = Some(wake_up_queue_arc);
     });
 }
 
 #[interrupt]
 #[allow(non_snake_case)]
-fn EXTI0() {
+fn USART1_EXTI25() {
     cortex_m::interrupt::free(|cs| {
-        if let Some(ref mut state_semaphore) = *G_STATE_QUEUE.borrow(cs).borrow_mut() {
-            let _ = state_semaphore
-                .send_from_isr(&mut InterruptContext::new(), AppResetMessage::FromButton);
+        if let Some(ref mut console_rx) = *G_CONSOLE_RX.borrow(cs).borrow_mut() {
+            if let Ok(byte) = console_rx.read() {
+                if let Some(ref mut console_queue) = *G_CONSOLE_QUEUE.borrow(cs).borrow_mut() {
+                    let _ = console_queue.send_from_isr(&mut InterruptContext::new(), byte);
+                }
+            }
         }
+    });
+}
+
+#[interrupt]
+#[allow(non_snake_case)]
+fn EXTI0() {
+    cortex_m::interrupt::free(|cs| {
         if let Some(ref mut btn) = *G_BTN.borrow(cs).borrow_mut() {
+            let pressed = btn.is_high().unwrap_or(false);
+            if let Some(ref mut button_queue) = *G_BUTTON_QUEUE.borrow(cs).borrow_mut() {
+                let _ = button_queue.send_from_isr(&mut InterruptContext::new(), pressed);
+            }
             btn.clear_interrupt();
         }
     });
 }
 
+#[interrupt]
+#[allow(non_snake_case)]
+fn EXTI4() {
+    cortex_m::interrupt::free(|cs| {
+        if let Some(ref mut accel_int) = *G_ACCEL_INT.borrow(cs).borrow_mut() {
+            if let Some(ref mut wake_up_queue) = *G_WAKE_UP_QUEUE.borrow(cs).borrow_mut() {
+                let _ = wake_up_queue.send_from_isr(&mut InterruptContext::new(), ());
+            }
+            accel_int.clear_interrupt();
+        }
+    });
+}
+
+pub fn set_i2c_waiter(task: Option<Task>) {
+    cortex_m::interrupt::free(|cs| {
+        *G_I2C_WAITER.borrow(cs).borrow_mut() = task;
+    });
+}
+
+fn wake_i2c_waiter(result: Option<Result<(), i2c::I2cError>>) {
+    cortex_m::interrupt::free(|cs| {
+        if let (Some(result), Some(waiter)) = (result, &*G_I2C_WAITER.borrow(cs).borrow()) {
+            i2c::notify_from_isr(waiter, result);
+        }
+    });
+}
+
+#[interrupt]
+#[allow(non_snake_case)]
+fn I2C1_EV_EXTI23() {
+    wake_i2c_waiter(i2c::on_event());
+}
+
+#[interrupt]
+#[allow(non_snake_case)]
+fn I2C1_ER() {
+    wake_i2c_waiter(i2c::on_error());
+}
+
+#[interrupt]
+#[allow(non_snake_case)]
+fn RTCALARM() {
+    Rtc::clear_alarm();
+    cortex_m::interrupt::free(|cs| {
+        if let Some(ref mut schedule_queue) = *G_SCHEDULE_QUEUE.borrow(cs).borrow_mut() {
+            let _ = schedule_queue.send_from_isr(&mut InterruptContext::new(), ());
+        }
+    });
+}
+
 #[exception]
 unsafe fn DefaultHandler(_irqn: i16) {}
 
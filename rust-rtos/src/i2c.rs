@@ -0,0 +1,345 @@
+use core::{
+    fmt,
+    sync::atomic::{AtomicU32, Ordering},
+};
+use cortex_m::asm;
+use freertos_rust::{CurrentTask, Duration, InterruptContext, Task, TaskNotification};
+use stm32f3xx_hal::{
+    gpio::{Alternate, Gpiob, OpenDrain, Pin, U},
+    hal::blocking::i2c::{Write, WriteRead},
+    pac,
+};
+
+use crate::{ecf, sysclk::SYSCLK_HZ};
+
+/// 400 kHz fast mode from the 8 MHz HSI, per the reference manual's timing
+/// table.
+const TIMINGR_400KHZ: u32 = 0x0031_0309;
+/// Longest a transfer may take before the bus is assumed stuck. The longest
+/// one, a six-byte burst read, takes under 0.3 ms.
+const TIMEOUT_MS: u32 = 10;
+
+const CR1_PE: u32 = 1 << 0;
+const CR1_NACKIE: u32 = 1 << 4;
+const CR1_STOPIE: u32 = 1 << 5;
+const CR1_TCIE: u32 = 1 << 6;
+const CR1_ERRIE: u32 = 1 << 7;
+const CR1_TXDMAEN: u32 = 1 << 14;
+const CR1_RXDMAEN: u32 = 1 << 15;
+const CR2_SADD_MASK: u32 = 0x3FF;
+const CR2_RD_WRN: u32 = 1 << 10;
+const CR2_START: u32 = 1 << 13;
+const CR2_STOP: u32 = 1 << 14;
+const CR2_NBYTES_SHIFT: u32 = 16;
+const CR2_AUTOEND: u32 = 1 << 25;
+const ISR_NACKF: u32 = 1 << 4;
+const ISR_STOPF: u32 = 1 << 5;
+const ISR_TC: u32 = 1 << 6;
+const ISR_BERR: u32 = 1 << 8;
+const ISR_ARLO: u32 = 1 << 9;
+const ISR_OVR: u32 = 1 << 10;
+
+const DMA_EN: u32 = 1 << 0;
+/// Memory to peripheral.
+const DMA_DIR: u32 = 1 << 4;
+const DMA_MINC: u32 = 1 << 7;
+
+/// The bus lines, PB6 and PB7.
+const SCL: u32 = 6;
+const SDA: u32 = 7;
+const MODER_OUTPUT: u32 = 0b01;
+const MODER_ALTERNATE: u32 = 0b10;
+/// Half an SCL period at 100 kHz, in core clock cycles.
+const HALF_PERIOD_CYCLES: u32 = SYSCLK_HZ / 200_000;
+
+/// Why the transfer in progress is failing, as an `I2cError::code`, or zero.
+static FAILURE: AtomicU32 = AtomicU32::new(0);
+
+pub type I2cPins = (
+    Pin<Gpiob, U<6>, Alternate<OpenDrain, 4>>,
+    Pin<Gpiob, U<7>, Alternate<OpenDrain, 4>>,
+);
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum I2cError {
+    /// The device did not acknowledge its address or a byte.
+    Nack,
+    /// A misplaced start or stop condition, or an overrun.
+    Bus,
+    /// Another master took the bus.
+    Arbitration,
+    /// The transfer did not finish within `TIMEOUT_MS`.
+    Timeout,
+}
+
+impl fmt::Display for I2cError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            I2cError::Nack => "no acknowledge",
+            I2cError::Bus => "bus error",
+            I2cError::Arbitration => "arbitration lost",
+            I2cError::Timeout => "timeout",
+        })
+    }
+}
+
+impl I2cError {
+    /// As sent in a task notification, where zero means none arrived.
+    fn code(result: Result<(), I2cError>) -> u32 {
+        match result {
+            Ok(()) => 1,
+            Err(I2cError::Nack) => 2,
+            Err(I2cError::Bus) => 3,
+            Err(I2cError::Arbitration) => 4,
+            Err(I2cError::Timeout) => 5,
+        }
+    }
+    fn from_code(code: u32) -> Result<(), I2cError> {
+        match code {
+            1 => Ok(()),
+            2 => Err(I2cError::Nack),
+            3 => Err(I2cError::Bus),
+            4 => Err(I2cError::Arbitration),
+            _ => Err(I2cError::Timeout),
+        }
+    }
+}
+
+/// I2C1 with DMA1 moving the bytes (channel 6 transmits, channel 7
+/// receives), driven through its registers since the HAL's `I2c` busy-waits
+/// on every byte.
+///
+/// A transfer is started from the calling task, which then sleeps until
+/// `I2C1_EV_EXTI23` or `I2C1_ER` sends it a task notification with the
+/// outcome, so lower priority tasks run meanwhile. A register address with a
+/// read is sent as a write, a repeated start and a read, the sensors
+/// auto-incrementing through the registers in one DMA burst.
+pub struct DmaI2c {
+    _pins: I2cPins,
+}
+
+impl DmaI2c {
+    pub fn new(_i2c: pac::I2C1, _dma: pac::DMA1, pins: I2cPins) -> Self {
+        let (rcc, i2c, dma) = regs();
+        rcc.apb1enr.modify(|_, w| w.i2c1en().set_bit());
+        rcc.ahbenr.modify(|_, w| w.dma1en().set_bit());
+        i2c.timingr.write(|w| unsafe { w.bits(TIMINGR_400KHZ) });
+        dma.ch6
+            .par
+            .write(|w| unsafe { w.bits(i2c.txdr.as_ptr() as u32) });
+        dma.ch7
+            .par
+            .write(|w| unsafe { w.bits(i2c.rxdr.as_ptr() as u32) });
+        enable();
+        DmaI2c { _pins: pins }
+    }
+    fn transfer(&mut self, address: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<(), I2cError> {
+        let task = Task::current().ok();
+        if task.is_some() {
+            // Drop a notification from a transfer that already timed out.
+            CurrentTask::take_notification(true, Duration::zero());
+        }
+        ecf::set_i2c_waiter(task.clone());
+        let (_, i2c, dma) = regs();
+        dma.ch6.cr.write(|w| unsafe { w.bits(0) });
+        dma.ch7.cr.write(|w| unsafe { w.bits(0) });
+        dma.ch6
+            .mar
+            .write(|w| unsafe { w.bits(bytes.as_ptr() as u32) });
+        dma.ch6
+            .ndtr
+            .write(|w| unsafe { w.bits(bytes.len() as u32) });
+        dma.ch6
+            .cr
+            .write(|w| unsafe { w.bits(DMA_DIR | DMA_MINC | DMA_EN) });
+        if !buffer.is_empty() {
+            dma.ch7
+                .mar
+                .write(|w| unsafe { w.bits(buffer.as_mut_ptr() as u32) });
+            dma.ch7
+                .ndtr
+                .write(|w| unsafe { w.bits(buffer.len() as u32) });
+            dma.ch7.cr.write(|w| unsafe { w.bits(DMA_MINC | DMA_EN) });
+        }
+        // With a read to follow, stop after the write so `on_event` can
+        // turn the bus round with a repeated start.
+        let autoend = if buffer.is_empty() { CR2_AUTOEND } else { 0 };
+        i2c.cr2.write(|w| unsafe {
+            w.bits(
+                u32::from(address) << 1
+                    | (bytes.len() as u32) << CR2_NBYTES_SHIFT
+                    | autoend
+                    | CR2_START,
+            )
+        });
+        let result = match task {
+            Some(_) => I2cError::from_code(CurrentTask::take_notification(
+                true,
+                Duration::ms(TIMEOUT_MS),
+            )),
+            // The sensors are set up before the scheduler starts, while the
+            // I2C interrupts are still masked, so poll for the outcome.
+            None => loop {
+                if let Some(result) = on_event().or_else(on_error) {
+                    break result;
+                }
+            },
+        };
+        ecf::set_i2c_waiter(None);
+        if result == Err(I2cError::Timeout) {
+            disable();
+            enable();
+        }
+        dma.ch6.cr.write(|w| unsafe { w.bits(0) });
+        dma.ch7.cr.write(|w| unsafe { w.bits(0) });
+        result
+    }
+}
+
+impl Write for DmaI2c {
+    type Error = I2cError;
+
+    fn write(&mut self, address: u8, bytes: &[u8]) -> Result<(), I2cError> {
+        self.transfer(address, bytes, &mut [])
+    }
+}
+
+impl WriteRead for DmaI2c {
+    type Error = I2cError;
+
+    fn write_read(&mut self, address: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<(), I2cError> {
+        self.transfer(address, bytes, buffer)
+    }
+}
+
+/// Handles `I2C1_EV_EXTI23`. Returns the outcome once the transfer is over.
+pub fn on_event() -> Option<Result<(), I2cError>> {
+    let (_, i2c, dma) = regs();
+    let isr = i2c.isr.read().bits();
+    if isr & ISR_NACKF != 0 {
+        i2c.icr.write(|w| unsafe { w.bits(ISR_NACKF) });
+        FAILURE.store(I2cError::code(Err(I2cError::Nack)), Ordering::Relaxed);
+        // Without AUTOEND the stop is ours to send.
+        if i2c.cr2.read().bits() & CR2_AUTOEND == 0 {
+            i2c.cr2
+                .modify(|r, w| unsafe { w.bits(r.bits() | CR2_STOP) });
+        }
+    } else if isr & ISR_TC != 0 {
+        // The register address went out; read the rest with a repeated
+        // start, as many bytes as the receive channel still expects.
+        let address = i2c.cr2.read().bits() & CR2_SADD_MASK;
+        let len = dma.ch7.ndtr.read().bits();
+        i2c.cr2.write(|w| unsafe {
+            w.bits(address | CR2_RD_WRN | len << CR2_NBYTES_SHIFT | CR2_AUTOEND | CR2_START)
+        });
+    }
+    if isr & ISR_STOPF != 0 {
+        i2c.icr.write(|w| unsafe { w.bits(ISR_STOPF) });
+        return Some(take_failure());
+    }
+    None
+}
+
+/// Handles `I2C1_ER`. The transfer is abandoned on any error.
+pub fn on_error() -> Option<Result<(), I2cError>> {
+    let (_, i2c, _) = regs();
+    let isr = i2c.isr.read().bits() & (ISR_BERR | ISR_ARLO | ISR_OVR);
+    if isr == 0 {
+        return None;
+    }
+    i2c.icr.write(|w| unsafe { w.bits(isr) });
+    // No stop follows a bus error or lost arbitration, so start over.
+    disable();
+    enable();
+    let _ = take_failure();
+    Some(Err(if isr & ISR_ARLO != 0 {
+        I2cError::Arbitration
+    } else {
+        I2cError::Bus
+    }))
+}
+
+/// Sends the outcome of a transfer to the task waiting for it.
+pub fn notify_from_isr(waiter: &Task, result: Result<(), I2cError>) {
+    let _ = waiter.notify_from_isr(
+        &mut InterruptContext::new(),
+        TaskNotification::OverwriteValue(I2cError::code(result)),
+    );
+}
+
+/// Frees the bus from a sensor that was cut off mid-byte and holds SDA low,
+/// waiting for clocks that will never come.
+///
+/// With the peripheral off, SCL is clocked by hand up to nine times until SDA
+/// is let go, and a stop condition ends whatever the sensor thought was going
+/// on. The pins then go back to I2C1 and the peripheral starts afresh. Only
+/// call it between transfers.
+pub fn recover() {
+    let gpiob = unsafe { &*pac::GPIOB::ptr() };
+    let set = |pin: u32| gpiob.bsrr.write(|w| unsafe { w.bits(1 << pin) });
+    let reset = |pin: u32| gpiob.bsrr.write(|w| unsafe { w.bits(1 << (pin + 16)) });
+    let mode = |bits: u32| {
+        gpiob.moder.modify(|r, w| unsafe {
+            w.bits(
+                r.bits() & !(0b11 << (2 * SCL) | 0b11 << (2 * SDA))
+                    | bits << (2 * SCL)
+                    | bits << (2 * SDA),
+            )
+        })
+    };
+    disable();
+    // Both pins are already open drain, so high lets the line float.
+    set(SCL);
+    set(SDA);
+    mode(MODER_OUTPUT);
+    for _ in 0..9 {
+        if gpiob.idr.read().bits() & 1 << SDA != 0 {
+            break;
+        }
+        reset(SCL);
+        asm::delay(HALF_PERIOD_CYCLES);
+        set(SCL);
+        asm::delay(HALF_PERIOD_CYCLES);
+    }
+    // A stop is SDA rising while SCL is high.
+    reset(SCL);
+    reset(SDA);
+    asm::delay(HALF_PERIOD_CYCLES);
+    set(SCL);
+    asm::delay(HALF_PERIOD_CYCLES);
+    set(SDA);
+    asm::delay(HALF_PERIOD_CYCLES);
+    mode(MODER_ALTERNATE);
+    let _ = take_failure();
+    enable();
+}
+
+fn take_failure() -> Result<(), I2cError> {
+    match FAILURE.swap(0, Ordering::Relaxed) {
+        0 => Ok(()),
+        code => I2cError::from_code(code),
+    }
+}
+
+fn enable() {
+    let (_, i2c, _) = regs();
+    i2c.cr1.write(|w| unsafe {
+        w.bits(CR1_PE | CR1_NACKIE | CR1_STOPIE | CR1_TCIE | CR1_ERRIE | CR1_TXDMAEN | CR1_RXDMAEN)
+    });
+}
+
+/// Resets the peripheral's state machine; it needs three APB cycles, which
+/// reading the register back covers.
+fn disable() {
+    let (_, i2c, _) = regs();
+    i2c.cr1.write(|w| unsafe { w.bits(0) });
+    let _ = i2c.cr1.read();
+}
+
+fn regs() -> (
+    &'static pac::rcc::RegisterBlock,
+    &'static pac::i2c1::RegisterBlock,
+    &'static pac::dma1::RegisterBlock,
+) {
+    unsafe { (&*pac::RCC::ptr(), &*pac::I2C1::ptr(), &*pac::DMA1::ptr()) }
+}
@@ -0,0 +1,181 @@
+use alarm_core::{
+    compass::RawMagSample, motion::RawSample, AccelCalibration, AccelOdr, AccelSensitivity,
+    AlarmProfile, MotionSensor, SensorHealth, WakeUpConfig,
+};
+use stm32f3xx_hal::hal::blocking::i2c::{Write, WriteRead};
+
+use crate::i2c::{self, DmaI2c, I2cError};
+
+const ADDRESS: u8 = 0x19;
+const CTRL_REG1_A: u8 = 0x20;
+const CTRL_REG2_A: u8 = 0x21;
+const CTRL_REG3_A: u8 = 0x22;
+const CTRL_REG4_A: u8 = 0x23;
+const REFERENCE_A: u8 = 0x26;
+const OUT_X_L_A: u8 = 0x28;
+const INT1_CFG_A: u8 = 0x30;
+const INT1_THS_A: u8 = 0x32;
+const INT1_DURATION_A: u8 = 0x33;
+/// Set in the register address to read several registers in one go.
+const AUTO_INCREMENT: u8 = 0x80;
+
+const XYZ_ENABLE: u8 = 0b0000_0111;
+/// Block data update, so the two halves of a reading always belong together.
+const BDU: u8 = 1 << 7;
+/// Route the high-pass filtered data to the INT1 comparison.
+const HPIS1: u8 = 1 << 0;
+/// Raise INT1 on the AOI1 event.
+const I1_AOI1: u8 = 1 << 6;
+/// Fire when X, Y or Z goes above the threshold.
+const XHIE_YHIE_ZHIE: u8 = 0b0010_1010;
+
+const MAG_ADDRESS: u8 = 0x1E;
+const CRA_REG_M: u8 = 0x00;
+const CRB_REG_M: u8 = 0x01;
+const MR_REG_M: u8 = 0x02;
+const OUT_X_H_M: u8 = 0x03;
+/// 15 Hz output rate.
+const MAG_ODR_15HZ: u8 = 0b100 << 2;
+/// ±1.3 gauss, the gain `compass::MagCalibration` assumes.
+const MAG_GAIN_1_3: u8 = 0b001 << 5;
+const MAG_CONTINUOUS: u8 = 0b00;
+const MAG_SLEEP: u8 = 0b11;
+
+/// The LSM303DLHC accelerometer and magnetometer.
+///
+/// The `lsm303dlhc` crate hides the register access needed for the wake-up
+/// interrupt, so this talks to the registers directly and only covers what
+/// the firmware uses. The magnetometer sleeps until the compass needs it.
+///
+/// Samples come out corrected by the board's [`AccelCalibration`], so every
+/// consumer sees the same calibrated data.
+pub struct Lsm303 {
+    i2c: DmaI2c,
+    sensitivity: AccelSensitivity,
+    calibration: AccelCalibration,
+}
+
+impl Lsm303 {
+    pub fn new(i2c: DmaI2c) -> Self {
+        Lsm303 {
+            i2c,
+            // Until `init` applies the profile, which comes first.
+            sensitivity: AccelSensitivity::G1,
+            calibration: AccelCalibration::UNCALIBRATED,
+        }
+    }
+    /// Applies `profile` and puts the magnetometer to sleep, since it keeps
+    /// running across a reset of the MCU alone.
+    pub fn init(&mut self, profile: &AlarmProfile) -> Result<(), I2cError> {
+        self.configure(profile)?;
+        self.disable_magnetometer()
+    }
+    /// Runs [`init`](Self::init), once more after a bus recovery if the
+    /// sensor does not answer. One that still does not is reported faulty
+    /// rather than stopping the firmware, and is retried with every sample.
+    pub fn bring_up(&mut self, profile: &AlarmProfile) -> SensorHealth {
+        if self.init(profile).is_ok() {
+            return SensorHealth::new();
+        }
+        self.recover();
+        match self.init(profile) {
+            Ok(()) => SensorHealth::new(),
+            Err(_) => SensorHealth::faulty(),
+        }
+    }
+    pub fn set_calibration(&mut self, calibration: AccelCalibration) {
+        self.calibration = calibration;
+    }
+    /// The latest sample as the sensor reports it, for calibrating.
+    pub fn raw_accel(&mut self) -> Result<RawSample, I2cError> {
+        let mut buf = [0; 6];
+        self.i2c
+            .write_read(ADDRESS, &[OUT_X_L_A | AUTO_INCREMENT], &mut buf)?;
+        Ok([
+            i16::from_le_bytes([buf[0], buf[1]]),
+            i16::from_le_bytes([buf[2], buf[3]]),
+            i16::from_le_bytes([buf[4], buf[5]]),
+        ])
+    }
+    /// Starts the magnetometer, for the compass.
+    pub fn enable_magnetometer(&mut self) -> Result<(), I2cError> {
+        self.i2c.write(MAG_ADDRESS, &[CRA_REG_M, MAG_ODR_15HZ])?;
+        self.i2c.write(MAG_ADDRESS, &[CRB_REG_M, MAG_GAIN_1_3])?;
+        self.i2c.write(MAG_ADDRESS, &[MR_REG_M, MAG_CONTINUOUS])
+    }
+    pub fn disable_magnetometer(&mut self) -> Result<(), I2cError> {
+        self.i2c.write(MAG_ADDRESS, &[MR_REG_M, MAG_SLEEP])
+    }
+    /// The latest magnetometer sample in raw counts.
+    pub fn mag(&mut self) -> Result<RawMagSample, I2cError> {
+        let mut buf = [0; 6];
+        // The magnetometer always increments, and sends X, Z, Y big endian.
+        self.i2c.write_read(MAG_ADDRESS, &[OUT_X_H_M], &mut buf)?;
+        Ok([
+            i16::from_be_bytes([buf[0], buf[1]]),
+            i16::from_be_bytes([buf[4], buf[5]]),
+            i16::from_be_bytes([buf[2], buf[3]]),
+        ])
+    }
+    fn write(&mut self, register: u8, value: u8) -> Result<(), I2cError> {
+        self.i2c.write(ADDRESS, &[register, value])
+    }
+    fn read(&mut self, register: u8) -> Result<u8, I2cError> {
+        let mut buf = [0];
+        self.i2c.write_read(ADDRESS, &[register], &mut buf)?;
+        Ok(buf[0])
+    }
+}
+
+impl MotionSensor for Lsm303 {
+    type Error = I2cError;
+
+    /// Applies the output data rate and range of `profile`.
+    fn configure(&mut self, profile: &AlarmProfile) -> Result<(), I2cError> {
+        let odr = match profile.accel_odr {
+            AccelOdr::Hz1 => 0b0001,
+            AccelOdr::Hz10 => 0b0010,
+            AccelOdr::Hz25 => 0b0011,
+            AccelOdr::Hz50 => 0b0100,
+            AccelOdr::Hz100 => 0b0101,
+            AccelOdr::Hz200 => 0b0110,
+            AccelOdr::Hz400 => 0b0111,
+        };
+        let full_scale = match profile.accel_sensitivity {
+            AccelSensitivity::G1 => 0b00,
+            AccelSensitivity::G2 => 0b01,
+            AccelSensitivity::G4 => 0b10,
+            AccelSensitivity::G12 => 0b11,
+        };
+        self.write(CTRL_REG1_A, odr << 4 | XYZ_ENABLE)?;
+        self.write(CTRL_REG4_A, BDU | full_scale << 4)?;
+        self.sensitivity = profile.accel_sensitivity;
+        Ok(())
+    }
+    /// The latest sample in calibrated, left-justified counts.
+    fn accel(&mut self) -> Result<RawSample, I2cError> {
+        let raw = self.raw_accel()?;
+        Ok(self.calibration.apply(raw, self.sensitivity))
+    }
+    /// Raises INT1 while high-pass filtered acceleration on any axis stays
+    /// above the threshold of `config` for its duration.
+    fn enable_wake_up(&mut self, config: WakeUpConfig) -> Result<(), I2cError> {
+        self.write(CTRL_REG2_A, HPIS1)?;
+        self.write(INT1_THS_A, config.threshold)?;
+        self.write(INT1_DURATION_A, config.duration)?;
+        self.write(INT1_CFG_A, XHIE_YHIE_ZHIE)?;
+        // Reading the reference register resets the filter to the current
+        // orientation, so enabling the interrupt does not fire it.
+        self.read(REFERENCE_A)?;
+        self.write(CTRL_REG3_A, I1_AOI1)
+    }
+    fn disable_wake_up(&mut self) -> Result<(), I2cError> {
+        self.write(CTRL_REG3_A, 0)?;
+        self.write(INT1_CFG_A, 0)
+    }
+    /// Frees the bus and leaves the sensor to be configured again, in case
+    /// it lost power.
+    fn recover(&mut self) {
+        i2c::recover();
+    }
+}
@@ -6,44 +6,194 @@
 use panic_halt as _;
 
 extern crate alloc;
-mod app_state;
+mod clock;
 mod ecf;
+mod i2c;
+mod lsm303;
 mod peripherals;
+mod sysclk;
 mod tasks;
+use alarm_board::{flash, rtc};
+use alarm_core::{
+    ActivityReport, AlarmProfile, AppResetMessage, AppState, CalibrationStore, ConfigStore,
+    Journal, MAX_QUEUE_SIZE,
+};
 use alloc::sync::Arc;
-use app_state::{AppResetMessage, AppState, MAX_QUEUE_SIZE};
 use cortex_m_rt::entry;
 use freertos_rust::*;
+use stm32f3xx_hal::{
+    gpio::{Gpioa, Input, Pin, U},
+    pac::Interrupt,
+    prelude::_embedded_hal_digital_InputPin,
+};
 
 #[allow(clippy::empty_loop)]
 #[entry]
 fn main() -> ! {
-    let (leds, user_btn, accelerometer) = peripherals::setup();
-    let state = Arc::new(Mutex::new(AppState::new()).unwrap());
+    let mut config_store = ConfigStore::new(flash::InternalFlash::config());
+    let profile = config_store.load_or_default();
+    let (leds, user_btn, mut accelerometer, gyroscope, accel_int, console, console_rx) =
+        peripherals::setup();
+    // A missing accelerometer starts the alarm in `SensorFault` until it answers.
+    let health = accelerometer.bring_up(&profile);
+    let mut calibration_store = CalibrationStore::new(flash::InternalFlash::calibration());
+    accelerometer.set_calibration(calibration_store.load_or_default().accel);
+    // Holding the user button through reset starts the compass instead.
+    if user_btn.is_high().unwrap_or(false) {
+        start_compass(profile, leds, user_btn, accelerometer, calibration_store);
+    }
+    let state = if health.is_faulty() {
+        AppState::SensorFault
+    } else {
+        AppState::new(clock::now(), &profile)
+    };
+    let mut journal = Journal::new(flash::InternalFlash::journal());
+    // The alarm keeps running without a journal rather than not at all.
+    let _ = journal
+        .open()
+        .and_then(|()| journal.record_boot(clock::now(), state.kind()));
+    let journal = Arc::new(Mutex::new(journal).unwrap());
+    let rtc = Arc::new(Mutex::new(rtc::Rtc::init()).unwrap());
+    let state = Arc::new(Mutex::new(state).unwrap());
+    let profile = Arc::new(Mutex::new(profile).unwrap());
+    let activity = Arc::new(Mutex::new(ActivityReport::new()).unwrap());
     let state_queue = Arc::new(Queue::<AppResetMessage>::new(MAX_QUEUE_SIZE).unwrap());
+    let button_queue = Arc::new(Queue::<bool>::new(tasks::BUTTON_QUEUE_SIZE).unwrap());
+    let console_queue = Arc::new(Queue::<u8>::new(tasks::CONSOLE_QUEUE_SIZE).unwrap());
+    let schedule_queue = Arc::new(Queue::<()>::new(tasks::SCHEDULE_QUEUE_SIZE).unwrap());
+    let wake_up_queue = Arc::new(Queue::<()>::new(tasks::WAKE_UP_QUEUE_SIZE).unwrap());
     let task_resetter_semaphore = Arc::new(Semaphore::new_binary().unwrap());
 
     ecf::setup_interrupt(user_btn.interrupt());
-    ecf::setup_interrupt_resource(user_btn, Arc::clone(&state_queue));
+    ecf::setup_interrupt_resource(user_btn, Arc::clone(&button_queue));
+    ecf::setup_interrupt(Interrupt::I2C1_EV_EXTI23);
+    ecf::setup_interrupt(Interrupt::I2C1_ER);
+    ecf::setup_interrupt(Interrupt::USART1_EXTI25);
+    ecf::setup_console_resource(console_rx, Arc::clone(&console_queue));
+    ecf::setup_interrupt(Interrupt::RTCALARM);
+    ecf::setup_schedule_resource(Arc::clone(&schedule_queue));
+    ecf::setup_interrupt(accel_int.interrupt());
+    ecf::setup_motion_resource(accel_int, Arc::clone(&wake_up_queue));
 
     Task::new()
         .name("accelerometer")
-        .stack_size(128)
+        // The vibration mask's FFT runs on this stack.
+        .stack_size(512)
         .priority(TaskPriority(2))
         .start(tasks::accelerometer_task(
             Arc::clone(&state_queue),
+            Arc::clone(&wake_up_queue),
+            Arc::clone(&state),
+            Arc::clone(&profile),
+            Arc::clone(&activity),
             accelerometer,
+            gyroscope,
         ))
         .unwrap();
 
     Task::new()
-        .name("output")
+        .name("button")
+        .stack_size(128)
+        .priority(TaskPriority(2))
+        .start(tasks::button_task(
+            Arc::clone(&button_queue),
+            Arc::clone(&state_queue),
+        ))
+        .unwrap();
+
+    Task::new()
+        .name("schedule")
+        .stack_size(128)
+        .priority(TaskPriority(2))
+        .start(tasks::schedule_task(
+            Arc::clone(&schedule_queue),
+            Arc::clone(&state_queue),
+            Arc::clone(&profile),
+            Arc::clone(&rtc),
+        ))
+        .unwrap();
+
+    Task::new()
+        .name("transition")
         .stack_size(128)
+        .priority(TaskPriority(2))
+        .start(tasks::transition_task(
+            Arc::clone(&state_queue),
+            Arc::clone(&state),
+            Arc::clone(&profile),
+            Arc::clone(&journal),
+        ))
+        .unwrap();
+
+    Task::new()
+        .name("output")
+        .stack_size(192)
         .priority(TaskPriority(1))
         .start(tasks::output_task(
-            Arc::clone(&state_queue),
             Arc::clone(&state),
+            Arc::clone(&profile),
+            Arc::clone(&journal),
+            leds,
+        ))
+        .unwrap();
+
+    Task::new()
+        .name("console")
+        .stack_size(256)
+        .priority(TaskPriority(1))
+        .start(tasks::console_task(
+            Arc::clone(&console_queue),
+            Arc::clone(&state_queue),
+            Arc::clone(&schedule_queue),
+            tasks::ConsoleResources {
+                profile: Arc::clone(&profile),
+                config_store,
+                journal: Arc::clone(&journal),
+                activity: Arc::clone(&activity),
+                rtc: Arc::clone(&rtc),
+            },
+            console,
+        ))
+        .unwrap();
+
+    FreeRtosUtils::start_scheduler()
+}
+
+fn start_compass(
+    profile: AlarmProfile,
+    leds: peripherals::Leds,
+    user_btn: Pin<Gpioa, U<0>, Input>,
+    accelerometer: peripherals::Accelerometer,
+    calibration_store: CalibrationStore<flash::InternalFlash>,
+) -> ! {
+    let gesture_queue = Arc::new(Queue::<AppResetMessage>::new(MAX_QUEUE_SIZE).unwrap());
+    let button_queue = Arc::new(Queue::<bool>::new(tasks::BUTTON_QUEUE_SIZE).unwrap());
+
+    ecf::setup_interrupt(user_btn.interrupt());
+    ecf::setup_interrupt_resource(user_btn, Arc::clone(&button_queue));
+    ecf::setup_interrupt(Interrupt::I2C1_EV_EXTI23);
+    ecf::setup_interrupt(Interrupt::I2C1_ER);
+
+    Task::new()
+        .name("button")
+        .stack_size(128)
+        .priority(TaskPriority(2))
+        .start(tasks::button_task(
+            Arc::clone(&button_queue),
+            Arc::clone(&gesture_queue),
+        ))
+        .unwrap();
+
+    Task::new()
+        .name("compass")
+        .stack_size(256)
+        .priority(TaskPriority(1))
+        .start(tasks::compass_task(
+            Arc::clone(&gesture_queue),
+            profile,
+            accelerometer,
             leds,
+            calibration_store,
         ))
         .unwrap();
 
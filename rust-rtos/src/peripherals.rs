@@ -1,12 +1,17 @@
+use alarm_board::l3gd20::L3gd20;
+use alarm_core::CompassPoint;
+use core::fmt;
 use freertos_rust::{CurrentTask, Duration};
-use lsm303dlhc::Lsm303dlhc;
 use stm32f3xx_hal::{
     gpio::*,
-    i2c::I2c,
-    pac::{self, I2C1},
+    pac::{self, SPI1, USART1},
     prelude::*,
+    serial::{self, Rx, Serial, Tx},
+    spi::{self, Spi},
 };
 
+use crate::{i2c::DmaI2c, lsm303::Lsm303, sysclk::SYSCLK_HZ};
+
 #[derive(PartialEq, Eq, Copy, Clone)]
 pub enum LedDirection {
     N,
@@ -19,6 +24,21 @@ pub enum LedDirection {
     NW,
 }
 
+impl From<CompassPoint> for LedDirection {
+    fn from(point: CompassPoint) -> LedDirection {
+        match point {
+            CompassPoint::North => LedDirection::N,
+            CompassPoint::NorthEast => LedDirection::NE,
+            CompassPoint::East => LedDirection::E,
+            CompassPoint::SouthEast => LedDirection::SE,
+            CompassPoint::South => LedDirection::S,
+            CompassPoint::SouthWest => LedDirection::SW,
+            CompassPoint::West => LedDirection::W,
+            CompassPoint::NorthWest => LedDirection::NW,
+        }
+    }
+}
+
 pub type LedPin<const T: u8> = Pin<Gpioe, U<T>, Output<PushPull>>;
 
 pub struct Leds {
@@ -100,17 +120,48 @@ impl Leds {
     }
 }
 
-pub type Accelerometer = Lsm303dlhc<
-    I2c<
-        I2C1,
+pub type Accelerometer = Lsm303;
+
+pub type Gyroscope = L3gd20<
+    Spi<
+        SPI1,
         (
-            Pin<Gpiob, U<6>, Alternate<OpenDrain, 4>>,
-            Pin<Gpiob, U<7>, Alternate<OpenDrain, 4>>,
+            Pin<Gpioa, U<5>, Alternate<PushPull, 5>>,
+            Pin<Gpioa, U<6>, Alternate<PushPull, 5>>,
+            Pin<Gpioa, U<7>, Alternate<PushPull, 5>>,
         ),
     >,
+    Pin<Gpioe, U<3>, Output<PushPull>>,
 >;
 
-pub fn setup() -> (Leds, Pin<Gpioa, U<0>, Input>, Accelerometer) {
+/// The LSM303DLHC INT1 line, wired to PE4 on the Discovery board.
+pub type AccelInt = Pin<Gpioe, U<4>, Input>;
+
+pub type ConsoleTxPin = Pin<Gpioc, U<4>, Alternate<PushPull, 7>>;
+pub type ConsoleRxPin = Pin<Gpioc, U<5>, Alternate<PushPull, 7>>;
+pub type ConsoleRx = Rx<USART1, ConsoleRxPin>;
+
+/// Transmit half of the USART1 console (PC4 TX, PC5 RX, 115200 8N1).
+pub struct Console(pub Tx<USART1, ConsoleTxPin>);
+
+impl fmt::Write for Console {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            while self.0.write(byte).is_err() {}
+        }
+        Ok(())
+    }
+}
+
+pub fn setup() -> (
+    Leds,
+    Pin<Gpioa, U<0>, Input>,
+    Accelerometer,
+    Gyroscope,
+    AccelInt,
+    Console,
+    ConsoleRx,
+) {
     let p = pac::Peripherals::take().unwrap();
     let mut exti = p.EXTI;
     let mut rcc = p.RCC.constrain();
@@ -118,6 +169,7 @@ pub fn setup() -> (Leds, Pin<Gpioa, U<0>, Input>, Accelerometer) {
     let mut gpioe = p.GPIOE.split(&mut rcc.ahb);
     let mut gpioa = p.GPIOA.split(&mut rcc.ahb);
     let mut gpiob = p.GPIOB.split(&mut rcc.ahb);
+    let mut gpioc = p.GPIOC.split(&mut rcc.ahb);
     let leds = Leds {
         current_direction: LedDirection::N,
         northwest: gpioe
@@ -149,7 +201,9 @@ pub fn setup() -> (Leds, Pin<Gpioa, U<0>, Input>, Accelerometer) {
         .pa0
         .into_pull_down_input(&mut gpioa.moder, &mut gpioa.pupdr);
     let mut flash = p.FLASH.constrain();
-    let clocks = rcc.cfgr.freeze(&mut flash.acr);
+    let clocks = rcc.cfgr.sysclk(SYSCLK_HZ.Hz()).freeze(&mut flash.acr);
+    // FreeRTOS was built for this rate, so anything else skews every delay.
+    assert_eq!(clocks.sysclk().0, SYSCLK_HZ);
     let mut scl =
         gpiob
             .pb6
@@ -160,19 +214,61 @@ pub fn setup() -> (Leds, Pin<Gpioa, U<0>, Input>, Accelerometer) {
             .into_af_open_drain(&mut gpiob.moder, &mut gpiob.otyper, &mut gpiob.afrl);
     scl.internal_pull_up(&mut gpiob.pupdr, true);
     sda.internal_pull_up(&mut gpiob.pupdr, true);
-    let i2c = stm32f3xx_hal::i2c::I2c::new(
-        p.I2C1,
-        (scl, sda),
-        100.kHz().try_into().unwrap(),
+    let i2c = DmaI2c::new(p.I2C1, p.DMA1, (scl, sda));
+    let accelerometer = Lsm303::new(i2c);
+    let sck =
+        gpioa
+            .pa5
+            .into_af_push_pull::<5>(&mut gpioa.moder, &mut gpioa.otyper, &mut gpioa.afrl);
+    let miso =
+        gpioa
+            .pa6
+            .into_af_push_pull::<5>(&mut gpioa.moder, &mut gpioa.otyper, &mut gpioa.afrl);
+    let mosi =
+        gpioa
+            .pa7
+            .into_af_push_pull::<5>(&mut gpioa.moder, &mut gpioa.otyper, &mut gpioa.afrl);
+    let gyro_cs = gpioe
+        .pe3
+        .into_push_pull_output(&mut gpioe.moder, &mut gpioe.otyper);
+    // The L3GD20 idles with the clock high and samples on the rising edge.
+    let spi = Spi::new(
+        p.SPI1,
+        (sck, miso, mosi),
+        spi::config::Config::default()
+            .frequency(1.MHz())
+            .mode(spi::MODE_3),
         clocks,
-        &mut rcc.apb1,
+        &mut rcc.apb2,
     );
-    let mut accelerometer = lsm303dlhc::Lsm303dlhc::new(i2c).unwrap();
-    let _ = accelerometer.accel_odr(lsm303dlhc::AccelOdr::Hz100);
-    let _ = accelerometer.set_accel_sensitivity(lsm303dlhc::Sensitivity::G12);
+    let gyroscope = L3gd20::new(spi, gyro_cs);
+    let mut accel_int = gpioe
+        .pe4
+        .into_floating_input(&mut gpioe.moder, &mut gpioe.pupdr);
+    let tx = gpioc
+        .pc4
+        .into_af_push_pull::<7>(&mut gpioc.moder, &mut gpioc.otyper, &mut gpioc.afrl);
+    let rx = gpioc
+        .pc5
+        .into_af_push_pull::<7>(&mut gpioc.moder, &mut gpioc.otyper, &mut gpioc.afrl);
+    let mut serial = Serial::new(p.USART1, (tx, rx), 115_200.Bd(), clocks, &mut rcc.apb2);
+    serial.enable_interrupt(serial::Event::ReceiveDataRegisterNotEmpty);
+    let (console_tx, console_rx) = serial.split();
     syscfg.select_exti_interrupt_source(&user_btn);
-    user_btn.trigger_on_edge(&mut exti, Edge::Rising);
+    // Both edges, so the gesture recognizer can time how long it is held.
+    user_btn.trigger_on_edge(&mut exti, Edge::RisingFalling);
     user_btn.enable_interrupt(&mut exti);
+    syscfg.select_exti_interrupt_source(&accel_int);
+    accel_int.trigger_on_edge(&mut exti, Edge::Rising);
+    accel_int.enable_interrupt(&mut exti);
 
-    (leds, user_btn, accelerometer)
+    (
+        leds,
+        user_btn,
+        accelerometer,
+        gyroscope,
+        accel_int,
+        Console(console_tx),
+        console_rx,
+    )
 }
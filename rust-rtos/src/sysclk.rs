@@ -0,0 +1,11 @@
+// Clock rates shared between the firmware and `FreeRTOSConfig.h`.
+//
+// `build.rs` includes this file and refuses to build if the header's
+// `configCPU_CLOCK_HZ` or `configTICK_RATE_HZ` disagree with it, so SysTick
+// and every FreeRTOS delay run at the rate the code assumes.
+
+/// Core clock in hertz: the 8 MHz HSI halved and multiplied by nine in the PLL.
+pub const SYSCLK_HZ: u32 = 36_000_000;
+
+/// FreeRTOS ticks per second; one tick is one millisecond.
+pub const TICK_RATE_HZ: u32 = 1000;
@@ -1,98 +1,566 @@
 use alloc::sync::Arc;
-use freertos_rust::{CurrentTask, Duration, Mutex, Queue, Semaphore, Task};
+use core::fmt::Write;
+use freertos_rust::{CurrentTask, Duration, Mutex, Queue, Semaphore, Task, TaskDelay};
 use stm32f3xx_hal::prelude::_embedded_hal_digital_OutputPin;
 
+use alarm_board::{flash::InternalFlash, rtc::Rtc};
+use alarm_core::{
+    compass, motion, AccelCalibrator, ActivityMonitor, ActivityReport, AlarmProfile,
+    AppResetMessage, AppState, Calendar, CalibrationStore, Command, CommandContext, CompassPoint,
+    ConfigStore, FallDetector, Gesture, GestureRecognizer, HealthAction, Journal, LineBuffer,
+    MagCalibrator, MotionDetector, MotionSensor, MotionSource, Orientation, RotationDetector,
+    Scheduler, SensorHealth, Tilt, TipOverDetector, VibrationMask, WakeUpConfig, WakeUpGate,
+};
+
 use crate::{
-    app_state::{
-        AppResetMessage, AppState, ACTIVE_COUNTER_INITIAL_VALUE, PRE_ALARM_COUNTER_INITIAL_VALUE,
-    },
-    peripherals::{Accelerometer, Leds},
+    clock,
+    peripherals::{Accelerometer, Console, Gyroscope, LedDirection, Leds},
 };
 
-const DIFFERENCE_TOLERANCE: i16 = 1000;
+pub const BUTTON_QUEUE_SIZE: usize = 8;
+pub const SCHEDULE_QUEUE_SIZE: usize = 2;
+pub const WAKE_UP_QUEUE_SIZE: usize = 1;
+pub const CONSOLE_QUEUE_SIZE: usize = 32;
+const CONSOLE_LINE_LENGTH: usize = 48;
+/// The magnetometer runs at 15 Hz.
+const COMPASS_PERIOD_MS: u32 = 100;
+
+fn current_profile(profile_arc: &Mutex<AlarmProfile>) -> AlarmProfile {
+    profile_arc
+        .lock(Duration::infinite())
+        .map(|p| *p)
+        .unwrap_or_default()
+}
+
+/// Counts `result` towards the accelerometer's health, recovering the sensor
+/// and reporting a fault as [`SensorHealth`] says. Returns whether the sensor
+/// needs setting up again.
+fn check_sensor<S: MotionSensor, T>(
+    sensor: &mut S,
+    health: &mut SensorHealth,
+    result: &Result<T, S::Error>,
+    state_queue: &Queue<AppResetMessage>,
+) -> bool {
+    if result.is_ok() {
+        if let Some(message) = health.success() {
+            let _ = state_queue.send(message, Duration::infinite());
+        }
+        return false;
+    }
+    match health.failure() {
+        HealthAction::Retry => false,
+        action => {
+            sensor.recover();
+            if action == HealthAction::Fault {
+                let _ = state_queue.send(
+                    AppResetMessage::FromSensor { healthy: false },
+                    Duration::infinite(),
+                );
+            }
+            true
+        }
+    }
+}
 
-pub fn accelerometer_task(
+/// Feeds the samples of `accelerometer` to motion, fall and tip-over
+/// detection, to the activity classifier and to the vibration mask, and the
+/// gyroscope's to rotation detection. Motion only counts while the activity
+/// is human and not masked as machine vibration.
+///
+/// In interrupt mode the samples are read only as long as something needs
+/// them. Each wake-up from `wake_up_queue` is reported as motion once the
+/// samples that follow show the wearer moving.
+pub fn accelerometer_task<S: MotionSensor + Send + 'static>(
     state_queue: Arc<Queue<AppResetMessage>>,
+    wake_up_queue: Arc<Queue<()>>,
+    s_arc: Arc<Mutex<AppState>>,
+    profile_arc: Arc<Mutex<AlarmProfile>>,
+    activity_arc: Arc<Mutex<ActivityReport>>,
+    mut accelerometer: S,
+    mut gyroscope: Gyroscope,
+) -> impl FnOnce(Task) + Send + 'static {
+    let mut configured = current_profile(&profile_arc);
+    let mut detector = MotionDetector::new(&configured);
+    let mut fall = FallDetector::new(&configured);
+    let mut tip_over = TipOverDetector::new(&configured);
+    let mut activity = ActivityMonitor::new(&configured);
+    let mut vibration = VibrationMask::new(&configured);
+    let mut gate = WakeUpGate::new(&configured);
+    let mut rotation = RotationDetector::new(&configured);
+    let mut wake_up = false;
+    let mut spinning = false;
+    // An accelerometer that did not answer at boot started the alarm in
+    // `SensorFault`, which its first sample that works clears.
+    let mut health = if s_arc
+        .lock(Duration::infinite())
+        .is_ok_and(|s| *s == AppState::SensorFault)
+    {
+        SensorHealth::faulty()
+    } else {
+        SensorHealth::new()
+    };
+    // Set when the sensor lost its settings, e.g. after a bus recovery.
+    let mut stale = health.is_faulty();
+    let mut was_disarmed = false;
+    // Samples are taken on deadlines, so reading and processing one does not
+    // stretch the period the vibration mask's frequencies rely on.
+    let mut pacer = TaskDelay::new();
+    move |_| loop {
+        let profile = current_profile(&profile_arc);
+        let disarmed = s_arc
+            .lock(Duration::infinite())
+            .is_ok_and(|s| *s == AppState::Disarmed);
+        // Tipping over is judged against how the device lay when armed.
+        if was_disarmed && !disarmed {
+            tip_over.rearm();
+        }
+        was_disarmed = disarmed;
+        // Masking needs the samples of several seconds without a break.
+        let interrupt =
+            profile.motion_source == MotionSource::Interrupt && !profile.masks_vibration();
+        let gyro = profile.detects_rotation() && !disarmed;
+        if stale || (profile, interrupt && !disarmed, gyro) != (configured, wake_up, spinning) {
+            let mut result = Ok(());
+            if stale
+                || (profile.accel_odr, profile.accel_sensitivity)
+                    != (configured.accel_odr, configured.accel_sensitivity)
+            {
+                result = accelerometer.configure(&profile);
+            }
+            wake_up = interrupt && !disarmed;
+            result = result.and(if wake_up {
+                accelerometer.enable_wake_up(WakeUpConfig::new(&profile))
+            } else {
+                accelerometer.disable_wake_up()
+            });
+            // Retried with the next sample until it takes.
+            stale = result.is_err();
+            if gyro != spinning {
+                spinning = gyro;
+                let _ = if spinning {
+                    gyroscope.enable()
+                } else {
+                    gyroscope.disable()
+                };
+            }
+            detector.configure(&profile);
+            fall.configure(&profile);
+            tip_over.configure(&profile);
+            activity.configure(&profile);
+            vibration.configure(&profile);
+            gate.configure(&profile);
+            rotation.configure(&profile);
+            configured = profile;
+        }
+        let idle =
+            interrupt && !profile.detects_falls() && !profile.detects_tip_over() && !spinning;
+        if idle && !gate.is_open() {
+            // Wait for EXTI4, meanwhile keeping up with the profile and
+            // making sure the sensor still answers.
+            let woke = wake_up_queue
+                .receive(Duration::ms(motion::WAKE_UP_POLL_PERIOD.as_millis() as u32))
+                .is_ok();
+            if !disarmed {
+                let sample = accelerometer.accel();
+                stale |= check_sensor(&mut accelerometer, &mut health, &sample, &state_queue);
+            }
+            if woke && wake_up {
+                // Nothing was sampled while waiting.
+                activity.resume();
+                gate.open();
+            }
+            pacer = TaskDelay::new();
+            continue;
+        }
+        if wake_up && wake_up_queue.receive(Duration::zero()).is_ok() {
+            gate.open();
+        }
+        if !disarmed {
+            let sample = accelerometer.accel();
+            stale |= check_sensor(&mut accelerometer, &mut health, &sample, &state_queue);
+            if let Ok(sample) = sample {
+                if let Some(event) = fall.update(sample) {
+                    let _ = state_queue.send(event.into(), Duration::infinite());
+                }
+                if let Some(event) = tip_over.update(sample) {
+                    let _ = state_queue.send(event.into(), Duration::infinite());
+                }
+                if activity.update(sample).is_some() {
+                    if let Ok(mut report) = activity_arc.lock(Duration::infinite()) {
+                        *report = activity.report();
+                    }
+                }
+                vibration.update(sample);
+                if let Some(message) = gate.update(&activity) {
+                    let _ = state_queue.send(message, Duration::zero());
+                }
+                // In interrupt mode the wake-up gate reports the motion.
+                if let Some(event) = detector
+                    .update(sample)
+                    .filter(|_| !interrupt && activity.is_human() && vibration.permits())
+                {
+                    // More motion follows if the queue is full, so it is
+                    // dropped rather than holding up sampling.
+                    let _ = state_queue.send(event.into(), Duration::zero());
+                }
+            }
+        }
+        // The gyroscope has no interrupt wired up, so it is always polled.
+        if spinning {
+            if let Some(event) = gyroscope.rate().ok().and_then(|rate| rotation.update(rate)) {
+                let _ = state_queue.send(event.into(), Duration::zero());
+            }
+        }
+        pacer.delay_until(Duration::ms(profile.sample_period.as_millis() as u32));
+    }
+}
+
+pub fn button_task(
+    button_queue: Arc<Queue<bool>>,
+    state_queue: Arc<Queue<AppResetMessage>>,
+) -> impl FnOnce(Task) + Send + 'static {
+    let mut recognizer = GestureRecognizer::new();
+    move |_| loop {
+        let timeout = recognizer
+            .deadline()
+            .map_or(Duration::infinite(), |deadline| {
+                Duration::ms(deadline.saturating_duration_since(clock::now()).as_millis() as u32)
+            });
+        let gesture = match button_queue.receive(timeout) {
+            Ok(pressed) => recognizer.edge(pressed, clock::now()),
+            Err(_) => recognizer.poll(clock::now()),
+        };
+        if let Some(gesture) = gesture {
+            let _ = state_queue.send(
+                AppResetMessage::FromButton { gesture },
+                Duration::infinite(),
+            );
+        }
+    }
+}
+
+/// Lights the LED pointing to magnetic north. A long press starts the
+/// calibration: turn the board every which way while the LEDs spin, then
+/// long press again to store the result.
+///
+/// A double press starts the accelerometer calibration instead. The lit LEDs
+/// count the step; put the board down in that orientation and short press
+/// once it is still. A double press cancels it.
+///
+/// A short press switches to the bubble level and back. It lights the LEDs
+/// towards the lowest edge, more of them the further the board is tilted.
+pub fn compass_task(
+    gesture_queue: Arc<Queue<AppResetMessage>>,
+    profile: AlarmProfile,
     mut accelerometer: Accelerometer,
+    mut leds: Leds,
+    mut calibration_store: CalibrationStore<InternalFlash>,
+) -> impl FnOnce(Task) + Send + 'static {
+    let mut calibration = calibration_store.load_or_default();
+    let mut calibrator = None;
+    let mut accel_calibrator: Option<AccelCalibrator> = None;
+    // Showing the bubble level instead of the compass.
+    let mut level = false;
+    move |_| {
+        // Inside the task, so the transfer waits on the I2C interrupt: while
+        // the task is being built the interrupts are still masked.
+        let _ = accelerometer.enable_magnetometer();
+        loop {
+            let gesture = match gesture_queue.receive(Duration::zero()) {
+                Ok(AppResetMessage::FromButton { gesture }) => Some(gesture),
+                _ => None,
+            };
+            match (gesture, accel_calibrator.as_mut()) {
+                (Some(Gesture::Long), None) => {
+                    calibrator = match calibrator.take() {
+                        None => Some(MagCalibrator::new()),
+                        Some(calibrator) => {
+                            // A calibration that did not cover every axis is dropped.
+                            if let Some(mag) = calibrator.finish() {
+                                calibration.mag = mag;
+                                let _ = calibration_store.save(&calibration);
+                            }
+                            None
+                        }
+                    };
+                }
+                (Some(Gesture::Double), None) if calibrator.is_none() => {
+                    accel_calibrator = Some(AccelCalibrator::new());
+                }
+                (Some(Gesture::Short), None) if calibrator.is_none() => level = !level,
+                // Cancels, keeping the old calibration.
+                (Some(Gesture::Double), Some(_)) => accel_calibrator = None,
+                (Some(Gesture::Short), Some(calibrating)) => {
+                    // A confirm in the wrong orientation is ignored and the
+                    // same step lights up again.
+                    if calibrating.confirm() {
+                        if let Some(accel) = calibrating.finish() {
+                            calibration.accel = accel;
+                            accelerometer.set_calibration(accel);
+                            let _ = calibration_store.save(&calibration);
+                            accel_calibrator = None;
+                        }
+                    }
+                }
+                _ => {}
+            }
+            leds.set_low_all_direction();
+            if let Some(calibrating) = accel_calibrator.as_mut() {
+                if let Ok(raw) = accelerometer.raw_accel() {
+                    calibrating.update(motion::to_milli_g(raw, profile.accel_sensitivity));
+                }
+                show_orientation(&mut leds, calibrating);
+                CurrentTask::delay(Duration::ms(COMPASS_PERIOD_MS));
+                continue;
+            }
+            if level {
+                let sample = accelerometer.accel().ok();
+                let tilt = sample.and_then(|raw| {
+                    Tilt::from_accel(motion::to_milli_g(raw, profile.accel_sensitivity))
+                });
+                if let Some(tilt) = tilt {
+                    show_level(&mut leds, &tilt);
+                }
+                CurrentTask::delay(Duration::ms(COMPASS_PERIOD_MS));
+                continue;
+            }
+            match (
+                accelerometer.accel(),
+                accelerometer.mag(),
+                calibrator.as_mut(),
+            ) {
+                (_, Ok(mag), Some(calibrator)) => {
+                    calibrator.update(mag);
+                    leds.to_next_direction();
+                    leds.set_high_current_direction();
+                }
+                (Ok(accel), Ok(mag), None) => {
+                    if let Some(bearing) = compass::north_bearing(
+                        motion::to_milli_g(accel, profile.accel_sensitivity),
+                        calibration.mag.apply(mag),
+                    ) {
+                        leds.current_direction = CompassPoint::from_bearing(bearing).into();
+                        leds.set_high_current_direction();
+                    }
+                }
+                _ => {}
+            }
+            CurrentTask::delay(Duration::ms(COMPASS_PERIOD_MS));
+        }
+    }
+}
+
+/// Lights one LED per step of the accelerometer calibration, clockwise from
+/// north, up to and including the orientation asked for next.
+fn show_orientation(leds: &mut Leds, calibrator: &AccelCalibrator) {
+    let step = Orientation::ALL
+        .iter()
+        .position(|&orientation| calibrator.next() == Some(orientation))
+        .unwrap_or(Orientation::ALL.len() - 1);
+    leds.current_direction = LedDirection::N;
+    for _ in 0..=step {
+        leds.set_high_current_direction();
+        leds.to_next_direction();
+    }
+}
+
+/// Lights the LEDs towards the lowest edge, more of them the further the
+/// board is tilted, or all of them while it is level.
+fn show_level(leds: &mut Leds, tilt: &Tilt) {
+    for (point, lit) in CompassPoint::ALL.into_iter().zip(tilt.level_leds()) {
+        if lit {
+            leds.current_direction = point.into();
+            leds.set_high_current_direction();
+        }
+    }
+}
+
+/// Arms and disarms the alarm as the schedule in the profile says, sleeping
+/// until the RTC alarm or the console asks for the schedule to be
+/// re-evaluated.
+pub fn schedule_task(
+    schedule_queue: Arc<Queue<()>>,
+    state_queue: Arc<Queue<AppResetMessage>>,
+    profile_arc: Arc<Mutex<AlarmProfile>>,
+    rtc_arc: Arc<Mutex<Rtc>>,
 ) -> impl FnOnce(Task) + Send + 'static {
-    let mut prev_x = 0;
-    let mut prev_y = 0;
-    let mut prev_z = 0;
-    if let Ok(axis) = accelerometer.accel() {
-        prev_x = axis.x;
-        prev_y = axis.y;
-        prev_z = axis.z;
+    let mut scheduler = Scheduler::new();
+    move |_| loop {
+        let schedule = current_profile(&profile_arc).schedule;
+        let message = match rtc_arc.lock(Duration::infinite()) {
+            Ok(mut rtc) => {
+                let now = rtc.now();
+                rtc.set_alarm(schedule.next_change(&now));
+                scheduler.poll(&schedule, &now)
+            }
+            Err(_) => None,
+        };
+        if let Some(message) = message {
+            let _ = state_queue.send(message, Duration::infinite());
+        }
+        let _ = schedule_queue.receive(Duration::infinite());
     }
+}
+
+/// What console commands run against. The shared parts are locked only while
+/// a command runs.
+pub struct ConsoleResources {
+    pub profile: Arc<Mutex<AlarmProfile>>,
+    pub config_store: ConfigStore<InternalFlash>,
+    pub journal: Arc<Mutex<Journal<InternalFlash>>>,
+    pub activity: Arc<Mutex<ActivityReport>>,
+    pub rtc: Arc<Mutex<Rtc>>,
+}
+
+pub fn console_task(
+    console_queue: Arc<Queue<u8>>,
+    state_queue: Arc<Queue<AppResetMessage>>,
+    schedule_queue: Arc<Queue<()>>,
+    mut resources: ConsoleResources,
+    mut console: Console,
+) -> impl FnOnce(Task) + Send + 'static {
+    let mut line = LineBuffer::<CONSOLE_LINE_LENGTH>::new();
     move |_| loop {
-        if let Ok(axis) = accelerometer.accel() {
-            let difference =
-                (axis.x - prev_x).abs() + (axis.y - prev_y).abs() + (axis.z - prev_z).abs();
-            if difference > DIFFERENCE_TOLERANCE {
-                let _ = state_queue.send(AppResetMessage::FromAccelerometer, Duration::infinite());
-            }
-            prev_x = axis.x;
-            prev_y = axis.y;
-            prev_z = axis.z;
+        if let Ok(byte) = console_queue.receive(Duration::infinite()) {
+            let _ = match line.push(byte).map(|text| text.and_then(Command::parse)) {
+                Some(Ok(command)) => {
+                    if let Some(message) = command.reset_message() {
+                        let _ = state_queue.send(message, Duration::infinite());
+                    }
+                    let result = match (
+                        resources.profile.lock(Duration::infinite()),
+                        resources.journal.lock(Duration::infinite()),
+                        resources.activity.lock(Duration::infinite()),
+                        resources.rtc.lock(Duration::infinite()),
+                    ) {
+                        (Ok(mut profile), Ok(mut journal), Ok(activity), Ok(mut rtc)) => {
+                            let context = CommandContext {
+                                profile: &mut profile,
+                                store: &mut resources.config_store,
+                                journal: &mut journal,
+                                activity: &activity,
+                                calendar: &mut *rtc,
+                            };
+                            command.execute(context, &mut console)
+                        }
+                        _ => continue,
+                    };
+                    if command.affects_schedule() {
+                        let _ = schedule_queue.send((), Duration::zero());
+                    }
+                    result
+                }
+                Some(Err(e)) => writeln!(console, "error: {}", e),
+                None => continue,
+            };
         }
-        CurrentTask::delay(Duration::ms(1000));
     }
 }
 
-pub fn output_task(
+/// Applies every reset message to the state as it arrives, so none waits for
+/// an LED animation to finish.
+pub fn transition_task(
     state_queue: Arc<Queue<AppResetMessage>>,
     s_arc: Arc<Mutex<AppState>>,
+    profile_arc: Arc<Mutex<AlarmProfile>>,
+    journal_arc: Arc<Mutex<Journal<InternalFlash>>>,
+) -> impl FnOnce(Task) + Send + 'static {
+    move |_| loop {
+        let Ok(transition) = state_queue.receive(Duration::infinite()) else {
+            continue;
+        };
+        let profile = current_profile(&profile_arc);
+        if let (Ok(mut s), Ok(mut journal)) = (
+            s_arc.lock(Duration::infinite()),
+            journal_arc.lock(Duration::infinite()),
+        ) {
+            let now = clock::now();
+            let from = s.kind();
+            let _ = s.handle_reset(transition, now, &profile);
+            let _ = journal.record_reset(now, transition, from, s.kind());
+        }
+    }
+}
+
+pub fn output_task(
+    s_arc: Arc<Mutex<AppState>>,
+    profile_arc: Arc<Mutex<AlarmProfile>>,
+    journal_arc: Arc<Mutex<Journal<InternalFlash>>>,
     mut leds: Leds,
 ) -> impl FnOnce(Task) + Send + 'static {
     move |_| loop {
-        if let Ok(mut s) = s_arc.lock(Duration::infinite()) {
-            if let Ok(transition) = state_queue.receive(Duration::zero()) {
-                match transition {
-                    AppResetMessage::FromButton => match *s {
-                        AppState::Alarm => *s = AppState::Active(ACTIVE_COUNTER_INITIAL_VALUE),
-                        _ => {}
-                    },
-                    AppResetMessage::FromAccelerometer => match *s {
-                        AppState::PreAlarm(_) => {
-                            *s = AppState::Active(ACTIVE_COUNTER_INITIAL_VALUE)
-                        }
-                        _ => {}
-                    },
-                }
-            }
-            match *s {
-                AppState::Active(counter) => {
-                    if counter != 0 {
-                        let _ = leds.north.set_high();
-                        CurrentTask::delay(Duration::ms(250));
-                        let _ = leds.north.set_low();
-                        CurrentTask::delay(Duration::ms(750));
-                        s.decrement_counter();
-                    } else {
-                        s.transition();
+        let profile = current_profile(&profile_arc);
+        // Work on a copy so the lock is not held while the LEDs animate.
+        let s = match s_arc.lock(Duration::infinite()) {
+            Ok(mut s) => {
+                let now = clock::now();
+                if let Ok(mut journal) = journal_arc.lock(Duration::infinite()) {
+                    let mut from = s.kind();
+                    while s.escalate(now, &profile).is_ok() {
+                        let _ = journal.record_timeout(now, from, s.kind());
+                        from = s.kind();
                     }
                 }
-                AppState::PreAlarm(counter) => {
-                    let first_direction = leds.current_direction;
+                *s
+            }
+            Err(_) => continue,
+        };
+        match s {
+            AppState::Active { .. } => {
+                let _ = leds.north.set_high();
+                CurrentTask::delay(Duration::ms(250));
+                let _ = leds.north.set_low();
+                delay_within_deadline(&s, 750);
+            }
+            AppState::PreAlarm { .. } => {
+                let first_direction = leds.current_direction;
+                leds.turn_on_current_for(Duration::ms(100));
+                leds.to_next_direction();
+                while leds.current_direction != first_direction {
                     leds.turn_on_current_for(Duration::ms(100));
                     leds.to_next_direction();
-                    while leds.current_direction != first_direction {
-                        leds.turn_on_current_for(Duration::ms(100));
-                        leds.to_next_direction();
-                    }
-                    CurrentTask::delay(Duration::ms(2000));
-                    s.decrement_counter();
-                    s.decrement_counter();
-                    if counter == 0 {
-                        s.transition();
-                    }
                 }
-                AppState::Alarm => {
-                    leds.set_high_all_direction();
-                    CurrentTask::delay(Duration::ms(1000));
-                    leds.set_low_all_direction();
-                    CurrentTask::delay(Duration::ms(1000));
+                delay_within_deadline(&s, 2000);
+            }
+            AppState::Alarm { .. } => {
+                leds.set_high_all_direction();
+                CurrentTask::delay(Duration::ms(1000));
+                leds.set_low_all_direction();
+                delay_within_deadline(&s, 1000);
+            }
+            AppState::Emergency => {
+                leds.set_high_all_direction();
+                CurrentTask::delay(Duration::ms(100));
+                leds.set_low_all_direction();
+                CurrentTask::delay(Duration::ms(100));
+            }
+            AppState::Disarmed => {
+                let _ = leds.south.set_high();
+                CurrentTask::delay(Duration::ms(50));
+                let _ = leds.south.set_low();
+                CurrentTask::delay(Duration::ms(950));
+            }
+            AppState::SensorFault => {
+                // East and west flash twice, which no other state does.
+                for _ in 0..2 {
+                    let _ = leds.east.set_high();
+                    let _ = leds.west.set_high();
+                    CurrentTask::delay(Duration::ms(100));
+                    let _ = leds.east.set_low();
+                    let _ = leds.west.set_low();
+                    CurrentTask::delay(Duration::ms(100));
                 }
-            };
-        }
+                CurrentTask::delay(Duration::ms(600));
+            }
+        };
     }
 }
+
+/// Sleeps for `ms`, waking early if the current state's deadline comes first so
+/// the animation never holds back an escalation.
+fn delay_within_deadline(state: &AppState, ms: u32) {
+    let ms = state
+        .time_until_escalation(clock::now())
+        .map_or(ms, |left| left.as_millis().min(u128::from(ms)) as u32);
+    CurrentTask::delay(Duration::ms(ms));
+}